@@ -7,11 +7,15 @@
 //! Cargo re-run the build script whenever `memory.x` is changed,
 //! updating `memory.x` ensures a rebuild of the application with the
 //! new memory settings.
+//!
+//! It also passes the git commit being built to the firmware as the
+//! `GIT_HASH` environment variable, for the firmware version string.
 
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
 fn main() {
     // Put `memory.x` in our output directory and ensure it's
@@ -28,4 +32,43 @@ fn main() {
     // here, we ensure the build script is only re-run when
     // `memory.x` is changed.
     println!("cargo:rerun-if-changed=memory.x");
+
+    println!("cargo:rustc-env=GIT_HASH={}", git_hash());
+    // Rebuild the version string when the checked out commit changes
+    let head = Path::new(".git/HEAD");
+    if let Ok(contents) = fs::read_to_string(head) {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        if let Some(branch) = contents.strip_prefix("ref: ") {
+            let branch = Path::new(".git").join(branch.trim());
+            if branch.exists() {
+                println!("cargo:rerun-if-changed={}", branch.display());
+            }
+        }
+    }
+}
+
+/// Short hash of the commit being built, marked `-dirty` with uncommitted
+/// changes to tracked files, or `unknown` outside a git checkout
+fn git_hash() -> String {
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    };
+
+    match git(&["rev-parse", "--short=8", "HEAD"]) {
+        Some(hash) => {
+            let dirty = git(&["status", "--porcelain", "--untracked-files=no"])
+                .is_some_and(|status| !status.is_empty());
+            if dirty {
+                format!("{hash}-dirty")
+            } else {
+                hash
+            }
+        }
+        None => "unknown".to_owned(),
+    }
 }
@@ -0,0 +1,89 @@
+//! Boot/report protocol switching of the keyboard interface, in keyboard mode
+//!
+//! BIOSes and bootloaders don't parse report descriptors; they send
+//! SET_PROTOCOL to put a keyboard in boot protocol and then expect the fixed
+//! 8-byte boot report. `usbd_hid`'s `HIDClass` can only track the protocol in
+//! a way that blocks reports in one of the two modes, so this helper class
+//! sits in front of it in the `poll()` list and answers the protocol requests
+//! itself, and the main loop picks the report format from `is_boot()`.
+
+use usb_device::{
+    class_prelude::*,
+    control::{Recipient, Request, RequestType},
+};
+
+/// HID class request reading the current protocol
+const HID_REQ_GET_PROTOCOL: u8 = 0x03;
+/// HID class request switching between boot and report protocol
+const HID_REQ_SET_PROTOCOL: u8 = 0x0B;
+/// Protocol values of GET_PROTOCOL and SET_PROTOCOL
+const PROTOCOL_BOOT: u8 = 0;
+const PROTOCOL_REPORT: u8 = 1;
+
+/// Tracks the protocol the host has selected for the keyboard interface
+pub struct BootProtocol {
+    // Interface number of the keyboard's HID class
+    interface: u16,
+    boot: bool,
+}
+
+impl BootProtocol {
+    /// Track the protocol of HID interface `interface`, starting in report
+    /// protocol as the HID spec requires
+    pub fn new(interface: u16) -> Self {
+        Self {
+            interface,
+            boot: false,
+        }
+    }
+
+    /// Whether the host has switched the interface to boot protocol
+    pub fn is_boot(&self) -> bool {
+        self.boot
+    }
+
+    /// Whether `req` is a class request with `request` for this interface
+    fn is_protocol_request(&self, req: &Request, request: u8) -> bool {
+        req.request_type == RequestType::Class
+            && req.recipient == Recipient::Interface
+            && req.index == self.interface
+            && req.request == request
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for BootProtocol {
+    // A bus reset puts the device back in report protocol
+    fn reset(&mut self) {
+        self.boot = false;
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+        if self.is_protocol_request(&req, HID_REQ_GET_PROTOCOL) {
+            let protocol = if self.boot {
+                PROTOCOL_BOOT
+            } else {
+                PROTOCOL_REPORT
+            };
+            xfer.accept_with(&[protocol]).ok();
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+        if !self.is_protocol_request(&req, HID_REQ_SET_PROTOCOL) {
+            return;
+        }
+
+        match (req.value & 0xFF) as u8 {
+            PROTOCOL_BOOT => self.boot = true,
+            PROTOCOL_REPORT => self.boot = false,
+            // Stall unknown protocols so the host knows the switch failed
+            _ => {
+                xfer.reject().ok();
+                return;
+            }
+        }
+        xfer.accept().ok();
+    }
+}
@@ -0,0 +1,954 @@
+//! The button box's physical inputs, mapped onto logical buttons
+//!
+//! `ButtonBox` reads every physical input, debounces it, runs the combos and
+//! the per-input press modes (toggle, repeat, turbo, tap-hold, long press,
+//! double tap and hold to activate), and reports the logical buttons that
+//! result. It reaches the hardware only through `ButtonInput` and
+//! `InputSource`, so it runs the same on the host with scripted inputs.
+
+use embedded_hal::digital::InputPin;
+
+use crate::{
+    button_config::{ButtonConfig, ButtonMode, ButtonPull, Polarity, SwitchType},
+    combo::ComboTable,
+    debounce::{DebounceMode, Debouncer},
+    macro_recorder::MacroRecorder,
+    REPORT_BUTTONS,
+};
+
+/// Logical press events for buttons with long-press or double-tap detection
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(all(target_arch = "arm", target_os = "none"), derive(defmt::Format))]
+enum ButtonEvent {
+    /// Released before the long-press threshold, and not tapped again within
+    /// the double-tap window, reported as a normal press
+    ShortPress,
+    /// Held past the long-press threshold
+    LongPress,
+    /// Pressed again within the double-tap window of the previous release
+    DoubleTap,
+    /// Released after a long press or double tap
+    Release,
+}
+
+/// A direct button input, read through `InputPin`, with the pull resistor and
+/// edge interrupts `ButtonBox` configures at runtime
+///
+/// `ButtonBox` only reaches its pins through this trait, so its state machine
+/// can be driven by inputs other than GPIO pins, e.g. scripted ones.
+pub trait ButtonInput: InputPin {
+    /// Enable the pull resistor `pull`
+    fn set_pull(&mut self, pull: ButtonPull);
+    /// Raise `IO_IRQ_BANK0` on rising and falling edges
+    fn listen_edges(&self);
+}
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+mod gpio {
+    use rp_pico::hal::gpio::{DynPinId, DynPullType, FunctionSio, Interrupt, Pin, SioInput};
+
+    use super::{ButtonInput, ButtonPull};
+
+    impl ButtonInput for Pin<DynPinId, FunctionSio<SioInput>, DynPullType> {
+        fn set_pull(&mut self, pull: ButtonPull) {
+            self.set_pull_type(match pull {
+                ButtonPull::Up => DynPullType::Up,
+                ButtonPull::Down => DynPullType::Down,
+                ButtonPull::None => DynPullType::None,
+            });
+        }
+
+        fn listen_edges(&self) {
+            self.set_interrupt_enabled(Interrupt::EdgeLow, true);
+            self.set_interrupt_enabled(Interrupt::EdgeHigh, true);
+        }
+    }
+}
+
+/// Inputs scanned besides the direct button pins, e.g. a matrix or an I/O
+/// expander, numbered after them
+pub trait InputSource {
+    /// Pressed inputs at `now_ms`, bit per input
+    fn read(&mut self, now_ms: u32) -> u16;
+    /// Whether there are no inputs to scan, so they needn't be read until
+    /// the direct buttons change
+    fn is_empty(&self) -> bool;
+}
+
+/// No inputs besides the direct button pins
+impl InputSource for () {
+    fn read(&mut self, _now_ms: u32) -> u16 {
+        0
+    }
+
+    fn is_empty(&self) -> bool {
+        true
+    }
+}
+
+/// Timing thresholds for the button state machines
+#[derive(Clone, Copy)]
+pub struct ButtonTiming {
+    pub debounce: DebounceMode,
+    pub long_press_ms: u32,
+    pub repeat_delay_ms: u32,
+    pub repeat_interval_ms: u32,
+    pub double_tap_ms: u32,
+    pub turbo_period_ms: u32,
+}
+
+impl ButtonTiming {
+    /// Whether the debounce mode is valid, and the repeat interval and turbo
+    /// period are long enough to split into a pressed and a released half
+    pub const fn is_valid(&self) -> bool {
+        self.debounce.is_valid() && self.repeat_interval_ms >= 2 && self.turbo_period_ms >= 2
+    }
+}
+
+/// Physical inputs of the box mapped onto logical buttons
+///
+/// `N` inputs are read: `PINS` direct button pins, then the scanned ones.
+/// `PROFILES` mappings can be switched between, and `COMBOS` chords are
+/// evaluated on the inputs before they are mapped.
+pub struct ButtonBox<
+    const N: usize,
+    const PINS: usize,
+    const PROFILES: usize,
+    const COMBOS: usize,
+    P: ButtonInput,
+    S: InputSource,
+> {
+    buttons: [P; PINS],
+    scanned: S,
+    // Mapping of every profile, the index of the active one, and a copy of
+    // its mapping that the state machines read
+    profiles: [[ButtonConfig; N]; PROFILES],
+    profile: usize,
+    config: [ButtonConfig; N],
+    // Logical button that switches profiles, and whether it was reported on
+    // the previous read
+    profile_button: usize,
+    profile_held: bool,
+    // Inputs enabled in the first profile; the rest always read released
+    enabled: u16,
+    // Debounced state of the raw samples
+    debouncer: Debouncer<N>,
+    combos: ComboTable<COMBOS>,
+    // Debounced state left after combos hide their inputs
+    input_state: u16,
+    timing: ButtonTiming,
+    // Tick (ms) at which each button was last pressed
+    press_time: [u32; N],
+    // Long-press buttons currently held / held past the threshold
+    held: u16,
+    long_pressed: u16,
+    // Short presses reported for a single report on release
+    short_pulse: u16,
+    // Tick (ms) at which each button was last released
+    release_time: [u32; N],
+    // Double-tap buttons tapped once, waiting out the window for a second tap
+    tap_pending: u16,
+    // Double-tap buttons held down on their second tap
+    double_tapped: u16,
+    // Latched state of toggle buttons, flipped on each press
+    toggled: u16,
+    // Repeating and turbo buttons currently held, re-read every loop to keep
+    // their timing
+    repeating: u16,
+    // Hold-to-activate buttons held past their hold time, and ones still
+    // being held towards it
+    activated: u16,
+    arming: u16,
+    // Solo inputs pressed most recently in their group, the only ones of
+    // their group allowed to report
+    solo_active: u16,
+    // Logical buttons reported by the last read
+    last_buttons: u32,
+    // Failed pin reads since startup, consecutive reads with a failure, and
+    // the pins that failed on the last read
+    read_errors: u32,
+    failed_reads: u32,
+    failed_pins: u16,
+    // Debounced presses of each physical input since startup or the last
+    // `reset-counts` command
+    press_count: [u32; N],
+    // Logical buttons held by the console's `press` command, the ones whose
+    // press starts at the next read, and the start tick (ms) and duration
+    // (ms) of each press
+    simulated: u32,
+    simulated_starting: u32,
+    simulated_press: [(u32, u32); REPORT_BUTTONS],
+    // Button changes recorded by the console's `record` command, and their
+    // replay
+    recorder: MacroRecorder,
+}
+
+impl<
+        const N: usize,
+        const PINS: usize,
+        const PROFILES: usize,
+        const COMBOS: usize,
+        P: ButtonInput,
+        S: InputSource,
+    > ButtonBox<N, PINS, PROFILES, COMBOS, P, S>
+{
+    /// Button box reading `buttons` and then `scanned`, starting on the first
+    /// of `profiles`
+    ///
+    /// Each direct button pin is switched to the pull of its config.
+    pub fn new(
+        buttons: [P; PINS],
+        scanned: S,
+        combos: ComboTable<COMBOS>,
+        profiles: [[ButtonConfig; N]; PROFILES],
+        profile_button: usize,
+        timing: ButtonTiming,
+        recorder: MacroRecorder,
+    ) -> Self {
+        let mut button_box = Self {
+            buttons,
+            scanned,
+            profiles,
+            profile: 0,
+            config: profiles[0],
+            profile_button,
+            profile_held: false,
+            enabled: enabled_inputs(&profiles[0]),
+            debouncer: Debouncer::new(timing.debounce),
+            combos,
+            input_state: 0,
+            timing,
+            press_time: [0; N],
+            held: 0,
+            long_pressed: 0,
+            short_pulse: 0,
+            release_time: [0; N],
+            tap_pending: 0,
+            double_tapped: 0,
+            toggled: 0,
+            repeating: 0,
+            solo_active: 0,
+            activated: 0,
+            arming: 0,
+            last_buttons: 0,
+            read_errors: 0,
+            failed_reads: 0,
+            failed_pins: 0,
+            press_count: [0; N],
+            simulated: 0,
+            simulated_starting: 0,
+            simulated_press: [(0, 0); REPORT_BUTTONS],
+            recorder,
+        };
+        button_box.apply_pulls();
+        button_box
+    }
+
+    /// Set the pull resistor of every direct button pin from its config
+    fn apply_pulls(&mut self) {
+        for (button, config) in self.buttons.iter_mut().zip(self.config.iter()) {
+            button.set_pull(config.pull);
+        }
+    }
+
+    /// Enable rising and falling edge interrupts on every direct button pin
+    pub fn enable_interrupts(&self) {
+        for button in self.buttons.iter() {
+            button.listen_edges();
+        }
+    }
+
+    /// Whether a raw change is still waiting out the debounce period, or a
+    /// timed event is pending, so the buttons must be read again
+    ///
+    /// The scanned inputs raise no edge interrupts, so they are always read
+    /// if there are any.
+    pub fn needs_update(&self) -> bool {
+        let long_press_pending = self.held & !self.long_pressed != 0;
+        self.debouncer.settling()
+            || long_press_pending
+            || self.short_pulse != 0
+            || self.tap_pending != 0
+            || self.failed_reads != 0
+            || self.combos.pending()
+            || self.repeating != 0
+            || self.arming != 0
+            || self.simulated != 0
+            || self.recorder.pending()
+            || !self.scanned.is_empty()
+    }
+
+    /// Pressed inputs at `now_ms`, before debouncing, bit per input
+    pub fn read_raw(&mut self, now_ms: u32) -> u16 {
+        let mut buttons = 0u16;
+
+        // Read button states. The polarity gives the level of closed
+        // contacts, and a normally-closed switch is pressed while they're
+        // open. A pin that can't be read counts as released, but is recorded
+        // and counted so a fault doesn't go unnoticed.
+        self.failed_pins = 0;
+        for (index, button) in self.buttons.iter_mut().enumerate() {
+            let config = &self.config[index];
+            let active_high = config.polarity == Polarity::ActiveHigh;
+            let normally_closed = config.switch == SwitchType::NormallyClosed;
+            match button.is_high() {
+                Ok(high) if (high == active_high) != normally_closed => buttons |= 1 << index,
+                Ok(_) => {}
+                Err(_) => {
+                    self.read_errors = self.read_errors.wrapping_add(1);
+                    self.failed_pins |= 1 << index;
+                }
+            }
+        }
+        self.failed_reads = if self.failed_pins != 0 {
+            self.failed_reads + 1
+        } else {
+            0
+        };
+
+        buttons | (self.scanned.read(now_ms) << PINS)
+    }
+
+    /// Number of failed button pin reads since startup
+    pub fn read_errors(&self) -> u32 {
+        self.read_errors
+    }
+
+    /// Number of reads in a row, up to the last one, that had a pin fail
+    pub fn failed_reads(&self) -> u32 {
+        self.failed_reads
+    }
+
+    /// Direct button pins (bit per pin) that failed on the last read
+    pub fn failed_pins(&self) -> u16 {
+        self.failed_pins
+    }
+
+    fn read_buttons(&mut self, now_ms: u32) -> u32 {
+        // A disabled input reads released, and is debounced as such, so
+        // disabling a held one releases it
+        let raw = self.read_raw(now_ms) & self.enabled;
+
+        let was_stable = self.debouncer.state();
+        let stable = self.debouncer.update(raw, now_ms);
+        let pressed = stable & !was_stable;
+        for (index, count) in self.press_count.iter_mut().enumerate() {
+            if pressed & (1 << index) != 0 {
+                *count = count.wrapping_add(1);
+            }
+        }
+
+        // Raw mode passes the debounced inputs straight through, input N on
+        // button bit N
+        if cfg!(feature = "raw") {
+            return stable.into();
+        }
+
+        // Chords report their own button, and may hide their inputs
+        let previous_state = self.input_state;
+        let (inputs, combo_buttons) = self.combos.apply(stable, now_ms);
+        self.input_state = inputs;
+
+        self.update_solo(inputs & !previous_state);
+
+        // Map each physical input onto its logical button
+        let mut buttons = combo_buttons;
+        self.repeating = 0;
+        self.arming = 0;
+        for index in 0..N {
+            let bit = 1 << index;
+            let pressed = inputs & bit != 0;
+            let config = self.config[index];
+            // Every logical button the input reports as, OR-ed into the
+            // report so inputs sharing a button don't clear each other's
+            let outputs = (1 << config.button) | config.extra_buttons;
+
+            // What counts as a press of a toggle button: the press edge, or
+            // a short press when it also has a long-press or double-tap button
+            let timed = config.long_press.is_some()
+                || config.double_tap.is_some()
+                || config.mode == ButtonMode::TapHold;
+            let press = if !timed {
+                if pressed && previous_state & bit == 0 {
+                    self.press_time[index] = now_ms;
+                }
+
+                // A hold-to-activate button counts as pressed once held for
+                // its hold time, and as released the moment it's let go
+                let held_ms = now_ms.wrapping_sub(self.press_time[index]);
+                let active = pressed && held_ms >= u32::from(config.hold_to_activate_ms);
+                if pressed && !active {
+                    self.arming |= bit;
+                }
+                let press_edge = active && self.activated & bit == 0;
+                if active {
+                    self.activated |= bit;
+                } else {
+                    self.activated &= !bit;
+                }
+
+                match config.mode {
+                    ButtonMode::Momentary if active => buttons |= outputs,
+                    ButtonMode::Repeat if active => {
+                        self.repeating |= bit;
+                        if self.repeat_state(index, now_ms) {
+                            buttons |= outputs;
+                        }
+                    }
+                    ButtonMode::Turbo if active => {
+                        self.repeating |= bit;
+                        if self.turbo_state(index, now_ms) {
+                            buttons |= outputs;
+                        }
+                    }
+                    _ => {}
+                }
+                press_edge
+            } else {
+                let event = self.press_event(index, pressed, now_ms, config);
+                #[cfg(all(target_arch = "arm", target_os = "none"))]
+                if let Some(event) = event {
+                    defmt::debug!("Button {}: {}", index + 1, event);
+                }
+                if let Some(long_press_button) = config.long_press {
+                    if self.long_pressed & bit != 0 {
+                        buttons |= 1 << long_press_button;
+                    }
+                }
+                if let Some(double_tap_button) = config.double_tap {
+                    if self.double_tapped & bit != 0 {
+                        buttons |= 1 << double_tap_button;
+                    }
+                }
+                // A repeating or turbo button can't repeat a press only sent
+                // on release, so it acts as a momentary one here
+                if config.mode != ButtonMode::Toggle && self.short_pulse & bit != 0 {
+                    buttons |= outputs;
+                }
+                event == Some(ButtonEvent::ShortPress)
+            };
+
+            // Toggle buttons report their latched state instead
+            if config.mode == ButtonMode::Toggle {
+                if press {
+                    self.toggled ^= bit;
+                }
+                if self.toggled & bit != 0 {
+                    buttons |= outputs;
+                }
+            }
+        }
+
+        // The profile button switches profiles on its press rather than
+        // reporting
+        let profile_bit = 1 << self.profile_button;
+        let profile_held = buttons & profile_bit != 0;
+        if profile_held && !self.profile_held {
+            self.next_profile();
+        }
+        self.profile_held = profile_held;
+        buttons &= !profile_bit;
+
+        buttons & !self.solo_hidden()
+    }
+
+    /// Make each newly pressed solo input the active one of its group, and
+    /// un-latch the toggle buttons of the rest of the group
+    fn update_solo(&mut self, pressed: u16) {
+        for index in 0..N {
+            let config = self.config[index];
+            if !config.solo || pressed & (1 << index) == 0 {
+                continue;
+            }
+            let group = self.solo_group(config.group);
+            self.solo_active = (self.solo_active & !group) | (1 << index);
+            self.toggled &= !(group & !(1 << index));
+        }
+    }
+
+    /// Solo inputs in group `group`
+    fn solo_group(&self, group: u8) -> u16 {
+        let mut members = 0;
+        for (index, config) in self.config.iter().enumerate() {
+            if config.solo && config.group == group {
+                members |= 1 << index;
+            }
+        }
+        members
+    }
+
+    /// Logical buttons of solo inputs other than the active one of their
+    /// group, which must not report
+    fn solo_hidden(&self) -> u32 {
+        let mut hidden = 0;
+        for (index, config) in self.config.iter().enumerate() {
+            if !config.solo || self.solo_active & (1 << index) != 0 {
+                continue;
+            }
+            hidden |= (1 << config.button) | config.extra_buttons;
+            if let Some(long_press) = config.long_press {
+                hidden |= 1 << long_press;
+            }
+            if let Some(double_tap) = config.double_tap {
+                hidden |= 1 << double_tap;
+            }
+        }
+        hidden
+    }
+
+    /// Reported state of held repeating button `index`
+    ///
+    /// The button reads as pressed until the repeat delay has passed, then
+    /// repeats by reading released for the first half of every interval.
+    fn repeat_state(&self, index: usize, now_ms: u32) -> bool {
+        let held_ms = now_ms.wrapping_sub(self.press_time[index]);
+        match held_ms.checked_sub(self.timing.repeat_delay_ms) {
+            None => true,
+            Some(repeat_ms) => {
+                let interval = self.timing.repeat_interval_ms;
+                repeat_ms % interval >= interval / 2
+            }
+        }
+    }
+
+    /// Reported state of held turbo button `index`
+    ///
+    /// Counting from the press edge, each turbo period reads pressed for its
+    /// first half and released for the second. Once released the button is
+    /// no longer reported at all, so it always ends in the off state.
+    fn turbo_state(&self, index: usize, now_ms: u32) -> bool {
+        let held_ms = now_ms.wrapping_sub(self.press_time[index]);
+        let period = self.timing.turbo_period_ms;
+        held_ms % period < period / 2
+    }
+
+    /// Advance the long-press and double-tap state machine for button `index`
+    ///
+    /// A tap of a button with a double-tap button is only reported once the
+    /// double-tap window has passed without a second press, as a one-report
+    /// pulse. A second press within the window reports the double-tap button
+    /// instead, for as long as it is held.
+    fn press_event(
+        &mut self,
+        index: usize,
+        pressed: bool,
+        now_ms: u32,
+        config: ButtonConfig,
+    ) -> Option<ButtonEvent> {
+        let bit = 1 << index;
+
+        // A short press only lasts for the report after its release
+        self.short_pulse &= !bit;
+
+        let was_pressed = self.held & bit != 0;
+        let long_pressed = self.long_pressed & bit != 0;
+        let double_tapped = self.double_tapped & bit != 0;
+        let since_release = now_ms.wrapping_sub(self.release_time[index]);
+        match (was_pressed, pressed) {
+            (false, true) => {
+                self.held |= bit;
+                self.press_time[index] = now_ms;
+                if self.tap_pending & bit == 0 {
+                    None
+                } else if since_release <= self.timing.double_tap_ms {
+                    self.tap_pending &= !bit;
+                    self.double_tapped |= bit;
+                    Some(ButtonEvent::DoubleTap)
+                } else {
+                    // The window ran out unnoticed, so the first tap still
+                    // needs reporting
+                    self.tap_pending &= !bit;
+                    self.short_pulse |= bit;
+                    Some(ButtonEvent::ShortPress)
+                }
+            }
+            (true, true) if !long_pressed && !double_tapped && config.long_press.is_some() => {
+                let held_ms = now_ms.wrapping_sub(self.press_time[index]);
+                // A tap-hold input may have a threshold of its own
+                let threshold_ms = if config.mode == ButtonMode::TapHold && config.tap_hold_ms != 0
+                {
+                    u32::from(config.tap_hold_ms)
+                } else {
+                    self.timing.long_press_ms
+                };
+                if held_ms >= threshold_ms {
+                    self.long_pressed |= bit;
+                    Some(ButtonEvent::LongPress)
+                } else {
+                    None
+                }
+            }
+            (true, false) => {
+                self.held &= !bit;
+                self.release_time[index] = now_ms;
+                if long_pressed || double_tapped {
+                    self.long_pressed &= !bit;
+                    self.double_tapped &= !bit;
+                    Some(ButtonEvent::Release)
+                } else if config.double_tap.is_some() {
+                    // Wait to see whether a second tap follows
+                    self.tap_pending |= bit;
+                    None
+                } else {
+                    self.short_pulse |= bit;
+                    Some(ButtonEvent::ShortPress)
+                }
+            }
+            (false, false)
+                if self.tap_pending & bit != 0 && since_release > self.timing.double_tap_ms =>
+            {
+                self.tap_pending &= !bit;
+                self.short_pulse |= bit;
+                Some(ButtonEvent::ShortPress)
+            }
+            _ => None,
+        }
+    }
+
+    /// Read the inputs at `now_ms`, and return whether the logical buttons
+    /// changed since the last read
+    pub fn has_changed(&mut self, now_ms: u32) -> bool {
+        // Simulated presses and a recording's replay add to the real ones, so
+        // an input pressed during one still reports
+        let buttons = self.read_buttons(now_ms) | self.simulated_buttons(now_ms);
+        let buttons = buttons | self.recorder.update(buttons, now_ms);
+        let changed = buttons != self.last_buttons;
+        self.last_buttons = buttons;
+        changed
+    }
+
+    /// Debounced physical inputs, bit per input in `buttons` pin order,
+    /// before any combo or mapping
+    pub fn inputs(&self) -> u16 {
+        self.debouncer.state()
+    }
+
+    /// Physical inputs of the combos firing on the last read, bit per input
+    pub fn combo_inputs(&self) -> u16 {
+        self.combos.active_inputs()
+    }
+
+    /// Debounced presses of each physical input, for wear diagnostics
+    pub fn press_counts(&self) -> &[u32; N] {
+        &self.press_count
+    }
+
+    /// Start every input's press count over from 0
+    pub fn reset_press_counts(&mut self) {
+        self.press_count = [0; N];
+    }
+
+    /// Report logical `button` pressed for `duration_ms`, starting at the
+    /// next read, as if an input mapped to it were held
+    ///
+    /// Simulating a button already held starts its press over.
+    pub fn simulate_press(&mut self, button: usize, duration_ms: u32) {
+        self.simulated |= 1 << button;
+        self.simulated_starting |= 1 << button;
+        self.simulated_press[button] = (0, duration_ms);
+    }
+
+    /// Logical buttons held by simulated presses at `now_ms`, dropping the
+    /// presses that have run their time
+    fn simulated_buttons(&mut self, now_ms: u32) -> u32 {
+        for (index, (start_ms, duration_ms)) in self.simulated_press.iter_mut().enumerate() {
+            let bit = 1 << index;
+            if self.simulated_starting & bit != 0 {
+                *start_ms = now_ms;
+            }
+            if self.simulated & bit != 0 && now_ms.wrapping_sub(*start_ms) >= *duration_ms {
+                self.simulated &= !bit;
+            }
+        }
+        self.simulated_starting = 0;
+        self.simulated
+    }
+
+    /// Start recording button changes for the recorder's play button to
+    /// replay, replacing the last recording
+    pub fn start_recording(&mut self) {
+        self.recorder.start();
+    }
+
+    /// Stop recording, returning the changes recorded, or `None` if no
+    /// recording was running
+    pub fn stop_recording(&mut self) -> Option<usize> {
+        self.recorder.stop()
+    }
+
+    /// Changes held by a recording that filled up and stopped early since
+    /// the last call
+    pub fn take_recording_overflow(&mut self) -> Option<usize> {
+        self.recorder.take_overflow()
+    }
+
+    /// Logical buttons reported by the last read, bit per report button
+    pub fn buttons(&self) -> u32 {
+        self.last_buttons
+    }
+
+    /// Replace the mapping of the first profile, the one stored in flash
+    pub fn set_config(&mut self, config: [ButtonConfig; N]) {
+        self.profiles[0] = config;
+        self.enabled = enabled_inputs(&config);
+        if self.profile == 0 {
+            self.config = config;
+            self.apply_pulls();
+        }
+    }
+
+    /// Index of the active profile
+    pub fn profile(&self) -> usize {
+        self.profile
+    }
+
+    /// Switch to the next profile, wrapping around after the last
+    ///
+    /// Latched and timed button states belong to the old mapping, so they
+    /// are dropped, and held inputs report through the new one from the next
+    /// read.
+    fn next_profile(&mut self) {
+        self.profile = (self.profile + 1) % PROFILES;
+        self.config = self.profiles[self.profile];
+        self.long_pressed = 0;
+        self.short_pulse = 0;
+        self.tap_pending = 0;
+        self.double_tapped = 0;
+        self.toggled = 0;
+        self.activated = 0;
+        self.solo_active = 0;
+        self.apply_pulls();
+    }
+}
+
+/// Mask of the inputs `config` leaves enabled
+fn enabled_inputs<const N: usize>(config: &[ButtonConfig; N]) -> u16 {
+    config
+        .iter()
+        .enumerate()
+        .filter(|(_, button)| button.enabled)
+        .fold(0, |mask, (index, _)| mask | (1 << index))
+}
+
+#[cfg(test)]
+mod tests {
+    use core::convert::Infallible;
+
+    use embedded_hal::digital::ErrorType;
+
+    use super::*;
+    use crate::combo::Combo;
+
+    /// Input pin reading `script`, one level per read, and the last level
+    /// once the script has run out
+    struct MockPin<'a> {
+        script: &'a [bool],
+        reads: usize,
+    }
+
+    impl<'a> MockPin<'a> {
+        fn new(script: &'a [bool]) -> Self {
+            Self { script, reads: 0 }
+        }
+    }
+
+    impl ErrorType for MockPin<'_> {
+        type Error = Infallible;
+    }
+
+    impl InputPin for MockPin<'_> {
+        fn is_high(&mut self) -> Result<bool, Infallible> {
+            let level = self.script[self.reads.min(self.script.len() - 1)];
+            self.reads += 1;
+            Ok(level)
+        }
+
+        fn is_low(&mut self) -> Result<bool, Infallible> {
+            self.is_high().map(|high| !high)
+        }
+    }
+
+    impl ButtonInput for MockPin<'_> {
+        fn set_pull(&mut self, _pull: ButtonPull) {}
+
+        fn listen_edges(&self) {}
+    }
+
+    /// A single input with a pin of its own, and no combos or other profiles
+    type TestBox<'a> = ButtonBox<1, 1, 1, 0, MockPin<'a>, ()>;
+
+    /// Active-high, so a script reads `true` while the input is pressed
+    const BUTTON: ButtonConfig = ButtonConfig {
+        button: 0,
+        long_press: None,
+        double_tap: None,
+        mode: ButtonMode::Momentary,
+        polarity: Polarity::ActiveHigh,
+        pull: ButtonPull::None,
+        switch: SwitchType::NormallyOpen,
+        group: 0,
+        solo: false,
+        hold_to_activate_ms: 0,
+        extra_buttons: 0,
+        tap_hold_ms: 0,
+        enabled: true,
+    };
+
+    /// The firmware's defaults: a press is accepted 5ms after its last
+    /// bounce, and a turbo button runs at 10Hz
+    const TIMING: ButtonTiming = ButtonTiming {
+        debounce: DebounceMode::Settle {
+            debounce_ms: 5,
+            min_press_ms: 2,
+        },
+        long_press_ms: 500,
+        repeat_delay_ms: 400,
+        repeat_interval_ms: 100,
+        double_tap_ms: 250,
+        turbo_period_ms: 100,
+    };
+
+    fn single_input(config: ButtonConfig, script: &[bool]) -> TestBox<'_> {
+        ButtonBox::new(
+            [MockPin::new(script)],
+            (),
+            ComboTable::new([], 0),
+            [[config]],
+            31,
+            TIMING,
+            MacroRecorder::new(30),
+        )
+    }
+
+    /// Levels of a pin read once a millisecond, pressed from `press_ms` up
+    /// to `release_ms` and released until `end_ms`
+    fn press(press_ms: usize, release_ms: usize, end_ms: usize) -> Vec<bool> {
+        (0..end_ms)
+            .map(|ms| (press_ms..release_ms).contains(&ms))
+            .collect()
+    }
+
+    /// Read `button_box` once a millisecond until `end_ms`, and return each
+    /// change of the logical buttons with the tick (ms) it was read at
+    fn changes<const N: usize, const PINS: usize, const COMBOS: usize>(
+        button_box: &mut ButtonBox<N, PINS, 1, COMBOS, MockPin, ()>,
+        end_ms: u32,
+    ) -> Vec<(u32, u32)> {
+        (0..end_ms)
+            .filter_map(|now_ms| {
+                let changed = button_box.has_changed(now_ms);
+                changed.then(|| (now_ms, button_box.buttons()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn debounce_ignores_a_spike_and_reports_a_bouncy_press_once() {
+        let mut script = press(12, 40, 60);
+        // A lone spike on the idle input, then bounces on both edges
+        script[3] = true;
+        script[10] = true;
+        script[41] = true;
+        let mut button_box = single_input(BUTTON, &script);
+
+        // Pressed and released 5ms after the last bounce of each edge
+        assert_eq!(changes(&mut button_box, 60), [(17, 0b1), (47, 0)]);
+    }
+
+    #[test]
+    fn long_press_reports_its_own_button_instead() {
+        let config = ButtonConfig {
+            long_press: Some(1),
+            ..BUTTON
+        };
+
+        // Held past the threshold: only the long-press button, from 500ms
+        // after the debounced press until the debounced release
+        let script = press(10, 710, 800);
+        let mut button_box = single_input(config, &script);
+        assert_eq!(changes(&mut button_box, 800), [(515, 0b10), (715, 0)]);
+
+        // Released before it: the button itself, for the one read after the
+        // release
+        let script = press(10, 110, 200);
+        let mut button_box = single_input(config, &script);
+        assert_eq!(changes(&mut button_box, 200), [(115, 0b1), (116, 0)]);
+    }
+
+    #[test]
+    fn toggle_flips_on_each_press_edge() {
+        let config = ButtonConfig {
+            mode: ButtonMode::Toggle,
+            ..BUTTON
+        };
+        let mut script = press(10, 50, 200);
+        script[100..150].fill(true);
+        let mut button_box = single_input(config, &script);
+
+        // Latched by the first press, through its release, and cleared by
+        // the second
+        assert_eq!(changes(&mut button_box, 200), [(15, 0b1), (105, 0)]);
+    }
+
+    #[test]
+    fn turbo_alternates_from_the_press_and_ends_released() {
+        let config = ButtonConfig {
+            mode: ButtonMode::Turbo,
+            ..BUTTON
+        };
+        let script = press(10, 250, 400);
+        let mut button_box = single_input(config, &script);
+
+        // Pressed for the first half of each 100ms period from the debounced
+        // press, and released for good with the input, mid-period
+        assert_eq!(
+            changes(&mut button_box, 400),
+            [
+                (15, 0b1),
+                (65, 0),
+                (115, 0b1),
+                (165, 0),
+                (215, 0b1),
+                (255, 0)
+            ]
+        );
+    }
+
+    /// Two inputs reporting buttons 0 and 1, and, like the firmware's
+    /// default combo, button 2 while both are held, hiding the inputs for
+    /// the 50ms window
+    fn chord_inputs<'a>(scripts: [&'a [bool]; 2]) -> ButtonBox<2, 2, 1, 1, MockPin<'a>, ()> {
+        let combo = Combo {
+            button_mask: 0b11,
+            output_bit: 2,
+            suppress: true,
+        };
+        let second = ButtonConfig {
+            button: 1,
+            ..BUTTON
+        };
+        ButtonBox::new(
+            scripts.map(MockPin::new),
+            (),
+            ComboTable::new([combo], 50),
+            [[BUTTON, second]],
+            31,
+            TIMING,
+            MacroRecorder::new(30),
+        )
+    }
+
+    #[test]
+    fn tap_of_a_suppressed_combo_input_is_still_reported() {
+        // Input 0 tapped for 20ms, released well within the window: a press
+        // of its button for the report after the debounced release
+        let tap = press(10, 30, 100);
+        let mut button_box = chord_inputs([&tap, &[false]]);
+        assert_eq!(changes(&mut button_box, 100), [(35, 0b1), (36, 0)]);
+
+        // Both inputs together still only report the combo
+        let chord = press(20, 30, 100);
+        let mut button_box = chord_inputs([&tap, &chord]);
+        assert_eq!(changes(&mut button_box, 100), [(25, 0b100), (35, 0)]);
+    }
+}
@@ -0,0 +1,112 @@
+//! How each physical input is reported
+//!
+//! A `ButtonConfig` per input maps it onto its logical buttons and picks the
+//! press timing and wiring it's read with. The firmware keeps one table of
+//! them per profile, and stores the first in flash.
+
+/// How the reported state of a button follows its physical input
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(all(target_arch = "arm", target_os = "none"), derive(defmt::Format))]
+pub enum ButtonMode {
+    /// Reported pressed for as long as the input is held
+    Momentary,
+    /// Each press flips the reported state, which latches until the next one
+    Toggle,
+    /// Reported while held, and re-pressed at an interval once held past the
+    /// repeat delay
+    Repeat,
+    /// Reported pressed and released in turn for as long as the input is
+    /// held (autofire), starting pressed
+    Turbo,
+    /// Dual function, like a mod-tap key: a tap reports the button for one
+    /// report on release, while holding past the tap-hold threshold reports
+    /// the `long_press` button instead and drops the tap; needs a
+    /// `long_press` button
+    TapHold,
+}
+
+/// Level a pressed button drives its pin to
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(all(target_arch = "arm", target_os = "none"), derive(defmt::Format))]
+pub enum Polarity {
+    /// Pressed connects the pin to ground
+    ActiveLow,
+    /// Pressed connects the pin to 3V3
+    ActiveHigh,
+}
+
+/// Pull resistor enabled on a button's pin, holding it at the released
+/// level of its polarity
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(all(target_arch = "arm", target_os = "none"), derive(defmt::Format))]
+pub enum ButtonPull {
+    /// Internal pull-up, for active-low buttons
+    Up,
+    /// Internal pull-down, for active-high buttons
+    Down,
+    /// No internal pull, for buttons with an external pull resistor
+    None,
+}
+
+/// Whether a button's contacts close or open when it is pressed
+///
+/// This is independent of `Polarity`: the polarity says which level the pin
+/// is at while the contacts are closed, and a normally-closed switch is
+/// pressed while they are open.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(all(target_arch = "arm", target_os = "none"), derive(defmt::Format))]
+pub enum SwitchType {
+    /// Pressing closes the contacts
+    NormallyOpen,
+    /// Pressing opens the contacts
+    NormallyClosed,
+}
+
+/// Describes how one physical input is reported
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ButtonConfig {
+    /// Logical button (0-based report bit) the input reports as
+    pub button: usize,
+    /// Logical button reported instead when the input is held past the
+    /// long-press threshold
+    pub long_press: Option<usize>,
+    /// Logical button reported instead while the input is held down on a
+    /// second press within the double-tap window
+    pub double_tap: Option<usize>,
+    /// Whether the logical button follows the input or latches on each press
+    pub mode: ButtonMode,
+    /// Level the pin reads while pressed; only used by direct buttons
+    pub polarity: Polarity,
+    /// Pull resistor on the pin; only used by direct buttons
+    pub pull: ButtonPull,
+    /// Whether pressing closes or opens the contacts; only used by direct
+    /// buttons
+    pub switch: SwitchType,
+    /// Group the input belongs to when `solo` is set, 0 to 127
+    pub group: u8,
+    /// Radio-button behavior: pressing the input clears the buttons of every
+    /// other solo input in its group
+    pub solo: bool,
+    /// Time (ms) the input must be held without a break before its button
+    /// reports, 0 to report straight away; ignored with `long_press` or
+    /// `double_tap`
+    pub hold_to_activate_ms: u16,
+    /// Further logical buttons (a mask of report bits) reported together
+    /// with `button`, 0 for none
+    pub extra_buttons: u32,
+    /// Time (ms) a `TapHold` input must be held for its hold action, 0 to use
+    /// the long-press threshold
+    pub tap_hold_ms: u16,
+    /// Whether the input is scanned at all; a disabled input always reads
+    /// released, for taking a flaky switch out without rewiring. Only the
+    /// first profile's flag counts, since it's a property of the wiring
+    pub enabled: bool,
+}
+
+impl ButtonConfig {
+    /// Whether every mode has what it needs: a `TapHold` input's hold action
+    /// is its `long_press` button, so it must have one
+    pub const fn is_valid(&self) -> bool {
+        !matches!(self.mode, ButtonMode::TapHold) || self.long_press.is_some()
+    }
+}
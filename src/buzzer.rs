@@ -0,0 +1,101 @@
+//! Piezo buzzer clicking on every button press
+//!
+//! The buzzer sits on PWM slice 2 channel B (GPIO5). The slice is tuned to
+//! the tone's frequency and runs at a 50% duty cycle while a click plays, and
+//! at 0% the rest of the time. To move the buzzer to another pin, change the
+//! slice in `BuzzerPwm` and the `channel_b` uses to the ones of that pin
+//! (GPIO N is on slice N / 2 % 8, channel A when N is even).
+
+use embedded_hal::pwm::SetDutyCycle;
+
+use crate::bsp::hal::pwm::{FreeRunning, Pwm2, Slice};
+
+/// PWM slice type driving the buzzer, from its channel B
+pub type BuzzerPwm = Slice<Pwm2, FreeRunning>;
+
+/// What a press does while the click of an earlier one is still playing
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum BuzzerPolicy {
+    /// Start the click over, so it always ends `duration_ms` after the last
+    /// press
+    Retrigger,
+    /// Let the playing click finish, and stay silent for the new press
+    Ignore,
+}
+
+/// Tone played on each press
+#[derive(Clone, Copy)]
+pub struct BuzzerConfig {
+    /// Whether presses click at all
+    pub enabled: bool,
+    /// Pitch of the click, 20Hz to 20kHz
+    pub frequency_hz: u32,
+    /// Time (ms) each click plays for
+    pub duration_ms: u32,
+    pub policy: BuzzerPolicy,
+}
+
+impl BuzzerConfig {
+    /// Whether the tone is audible and the slice can produce it
+    pub const fn is_valid(&self) -> bool {
+        self.frequency_hz >= 20 && self.frequency_hz <= 20_000 && self.duration_ms > 0
+    }
+}
+
+/// Buzzer on a PWM slice
+pub struct Buzzer {
+    pwm: BuzzerPwm,
+    config: BuzzerConfig,
+    // Tick (ms) at which the playing click started, while one plays
+    playing_since: Option<u32>,
+}
+
+impl Buzzer {
+    /// Take over `pwm`, with its channel B already routed to the buzzer pin,
+    /// and tune it to the configured tone from the `sys_clock_hz` counter
+    /// clock, starting silent
+    pub fn new(mut pwm: BuzzerPwm, config: BuzzerConfig, sys_clock_hz: u32) -> Self {
+        // The smallest divider that fits a period of the tone in the 16-bit
+        // counter gives the finest pitch
+        let divider = sys_clock_hz.div_ceil(config.frequency_hz << 16).max(1);
+        let top = sys_clock_hz / (divider * config.frequency_hz) - 1;
+        pwm.set_div_int(divider as u8);
+        pwm.set_top(top as u16);
+        pwm.enable();
+
+        let mut buzzer = Self {
+            pwm,
+            config,
+            playing_since: None,
+        };
+        buzzer.silence();
+        buzzer
+    }
+
+    /// Play a click for a press at `now_ms`, as the policy allows
+    pub fn click(&mut self, now_ms: u32) {
+        if !self.config.enabled {
+            return;
+        }
+        if self.playing_since.is_some() && self.config.policy == BuzzerPolicy::Ignore {
+            return;
+        }
+        self.playing_since = Some(now_ms);
+        self.pwm.channel_b.set_duty_cycle_percent(50).ok();
+    }
+
+    /// End the click once it has played for its duration
+    pub fn update(&mut self, now_ms: u32) {
+        let done = self
+            .playing_since
+            .is_some_and(|since| now_ms.wrapping_sub(since) >= self.config.duration_ms);
+        if done {
+            self.silence();
+        }
+    }
+
+    fn silence(&mut self) {
+        self.playing_since = None;
+        self.pwm.channel_b.set_duty_cycle_fully_off().ok();
+    }
+}
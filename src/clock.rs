@@ -0,0 +1,144 @@
+//! System clock setup, with the system PLL at `SYS_CLOCK_HZ`
+//!
+//! `init_clocks()` stands in for the HAL's `init_clocks_and_plls()`, which
+//! always runs the system clock at 125MHz. The USB PLL keeps the HAL's 48MHz
+//! setup whatever the system clock, since USB needs exactly that, and the ADC
+//! and RTC run from it too. The cores, PIO, PWM and I2C follow the system
+//! clock; the timer ticks from the crystal, so every millisecond timing in
+//! the firmware is the same at any speed.
+//!
+//! Past `STOCK_MAX_HZ`, the RP2040's rated speed, the core voltage is raised
+//! to 1.15V first, as the Pico SDK does for its 200MHz setting.
+
+use crate::bsp::{
+    hal::{
+        clocks::{ClocksManager, InitError},
+        fugit::HertzU32,
+        pac::{
+            vreg_and_chip_reset::vreg::VSEL_A, CLOCKS, PLL_SYS, PLL_USB, RESETS,
+            VREG_AND_CHIP_RESET, XOSC,
+        },
+        pll::{common_configs::PLL_USB_48MHZ, setup_pll_blocking, PLLConfig},
+        vreg,
+        watchdog::Watchdog,
+        xosc::setup_xosc_blocking,
+    },
+    XOSC_CRYSTAL_FREQ,
+};
+
+/// Slowest system clock (Hz) accepted; the USB controller needs the system
+/// clock at least as fast as its own 48MHz
+pub const SYS_CLOCK_MIN_HZ: u32 = 48_000_000;
+/// Fastest system clock (Hz) the RP2040 is rated for at its default core
+/// voltage
+pub const STOCK_MAX_HZ: u32 = 133_000_000;
+/// Fastest system clock (Hz) accepted, with the core voltage raised
+pub const SYS_CLOCK_MAX_HZ: u32 = 200_000_000;
+
+/// Range of the PLL's VCO (Hz), and of its post dividers
+const VCO_MIN_HZ: u32 = 750_000_000;
+const VCO_MAX_HZ: u32 = 1_600_000_000;
+const POST_DIV_MAX: u32 = 7;
+
+/// System PLL setup producing exactly `sys_hz` from the Pico's 12MHz crystal,
+/// or `None` if it's out of bounds or no setup reaches it exactly
+///
+/// Of the setups that do, the one with the fastest VCO is picked, which has
+/// the least jitter; for 125MHz that is the HAL's own 1500MHz / 6 / 2.
+pub const fn sys_pll_config(sys_hz: u32) -> Option<PLLConfig> {
+    if sys_hz < SYS_CLOCK_MIN_HZ || sys_hz > SYS_CLOCK_MAX_HZ {
+        return None;
+    }
+
+    let mut best: Option<PLLConfig> = None;
+    let mut best_vco = 0;
+    let mut post_div1 = POST_DIV_MAX;
+    while post_div1 >= 1 {
+        // The second divider is never the larger, as the datasheet advises
+        let mut post_div2 = 1;
+        while post_div2 <= post_div1 {
+            let vco_hz = sys_hz as u64 * (post_div1 * post_div2) as u64;
+            let in_range = vco_hz >= VCO_MIN_HZ as u64 && vco_hz <= VCO_MAX_HZ as u64;
+            // With a reference divider of 1, the feedback divider has to
+            // bring the crystal up to the VCO exactly
+            let exact = vco_hz.is_multiple_of(XOSC_CRYSTAL_FREQ as u64);
+            if in_range && exact && vco_hz as u32 > best_vco {
+                best_vco = vco_hz as u32;
+                best = Some(PLLConfig {
+                    vco_freq: HertzU32::from_raw(best_vco),
+                    refdiv: 1,
+                    post_div1: post_div1 as u8,
+                    post_div2: post_div2 as u8,
+                });
+            }
+            post_div2 += 1;
+        }
+        post_div1 -= 1;
+    }
+    best
+}
+
+/// Start the crystal and both PLLs, and run the system clock at
+/// `SYS_CLOCK_HZ`
+pub fn init_clocks(
+    xosc_dev: XOSC,
+    clocks_dev: CLOCKS,
+    pll_sys_dev: PLL_SYS,
+    pll_usb_dev: PLL_USB,
+    vreg_dev: &mut VREG_AND_CHIP_RESET,
+    resets: &mut RESETS,
+    watchdog: &mut Watchdog,
+) -> Result<ClocksManager, InitError> {
+    let Some(sys_pll) = sys_pll_config(crate::SYS_CLOCK_HZ) else {
+        unreachable!("checked when SYS_CLOCK_HZ is compiled");
+    };
+
+    // Raise the core voltage before the clock, and give the regulator time
+    // to settle; the cores still run from the ring oscillator here, around
+    // 6MHz, so this is a couple of milliseconds
+    if crate::SYS_CLOCK_HZ > STOCK_MAX_HZ {
+        vreg::set_voltage(vreg_dev, VSEL_A::VOLTAGE1_15);
+        cortex_m::asm::delay(12_000);
+    }
+
+    let xosc = setup_xosc_blocking(xosc_dev, HertzU32::from_raw(XOSC_CRYSTAL_FREQ))
+        .map_err(InitError::XoscErr)?;
+
+    // The watchdog tick drives the timer, a microsecond each
+    watchdog.enable_tick_generation((XOSC_CRYSTAL_FREQ / 1_000_000) as u8);
+
+    let mut clocks = ClocksManager::new(clocks_dev);
+    let pll_sys = setup_pll_blocking(
+        pll_sys_dev,
+        xosc.operating_frequency(),
+        sys_pll,
+        &mut clocks,
+        resets,
+    )
+    .map_err(InitError::PllError)?;
+    let pll_usb = setup_pll_blocking(
+        pll_usb_dev,
+        xosc.operating_frequency(),
+        PLL_USB_48MHZ,
+        &mut clocks,
+        resets,
+    )
+    .map_err(InitError::PllError)?;
+
+    clocks
+        .init_default(&xosc, &pll_sys, &pll_usb)
+        .map_err(InitError::ClockError)?;
+    Ok(clocks)
+}
+
+// The stock clock keeps the HAL's setup, and the bounds are ones a PLL setup
+// exists for
+const _: () = {
+    let Some(stock) = sys_pll_config(125_000_000) else {
+        panic!("no PLL setup for 125MHz");
+    };
+    assert!(stock.post_div1 == 6 && stock.post_div2 == 2);
+    assert!(sys_pll_config(SYS_CLOCK_MIN_HZ).is_some());
+    assert!(sys_pll_config(SYS_CLOCK_MAX_HZ).is_some());
+    assert!(sys_pll_config(SYS_CLOCK_MAX_HZ + 1).is_none());
+};
@@ -0,0 +1,152 @@
+//! Button combos (chords)
+//!
+//! A combo fires when all of its inputs are pressed within a short window of
+//! each other, and reports its own logical button for as long as they stay
+//! held. Combos work on the debounced physical inputs, before they are mapped
+//! to logical buttons.
+
+/// A chord of physical inputs reported as one logical button
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Combo {
+    /// Physical inputs (bit per input, in `buttons` pin order) making up the chord
+    pub button_mask: u16,
+    /// Logical button (0-based report bit) reported while the combo is held
+    pub output_bit: usize,
+    /// Hide the member inputs while the combo may still fire or is held, so
+    /// they aren't reported individually as well
+    pub suppress: bool,
+}
+
+/// Progress of a single combo
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ComboState {
+    /// None of the inputs are pressed
+    Idle,
+    /// Some inputs are pressed, the first one at the given tick (ms)
+    Window(u32),
+    /// The inputs weren't all pressed in time, or the combo has ended, so
+    /// wait until they are all released
+    Missed,
+    /// All inputs were pressed in time and are still held
+    Active,
+}
+
+/// Evaluates a set of combos against the debounced inputs
+pub struct ComboTable<const N: usize> {
+    combos: [Combo; N],
+    window_ms: u32,
+    state: [ComboState; N],
+    // Inputs kept hidden after their combo ended, until they are released
+    consumed: u16,
+    // Inputs hidden by an open window on the last call, and the ones
+    // released from it, reported pressed for that call only
+    waiting: u16,
+    tapped: u16,
+}
+
+impl<const N: usize> ComboTable<N> {
+    pub fn new(combos: [Combo; N], window_ms: u32) -> Self {
+        Self {
+            combos,
+            window_ms,
+            state: [ComboState::Idle; N],
+            consumed: 0,
+            waiting: 0,
+            tapped: 0,
+        }
+    }
+
+    /// Whether a combo is part pressed and still within its window, so the
+    /// inputs must be read again once the window closes, or a tap is being
+    /// reported and must end on the next read
+    pub fn pending(&self) -> bool {
+        self.tapped != 0
+            || self
+                .state
+                .iter()
+                .any(|state| matches!(state, ComboState::Window(_)))
+    }
+
+    /// Inputs of the combos that are firing, bit per input
+    pub fn active_inputs(&self) -> u16 {
+        self.combos
+            .iter()
+            .zip(self.state.iter())
+            .filter(|(_, state)| **state == ComboState::Active)
+            .fold(0, |inputs, (combo, _)| inputs | combo.button_mask)
+    }
+
+    /// Evaluate every combo against the pressed `inputs`
+    ///
+    /// Returns the inputs left to report individually, and the logical button
+    /// bits of the combos that are firing. An input hidden while its window
+    /// was open and released before the combo fired was a tap, so it is
+    /// reported pressed for this call, as a one-report pulse.
+    pub fn apply(&mut self, inputs: u16, now_ms: u32) -> (u16, u32) {
+        // Released inputs no longer need hiding
+        self.consumed &= inputs;
+
+        let mut hidden = 0u16;
+        let mut waiting = 0u16;
+        let mut firing = 0u16;
+        let mut outputs = 0u32;
+
+        for (combo, state) in self.combos.iter().zip(self.state.iter_mut()) {
+            let held = inputs & combo.button_mask;
+            let all_held = held == combo.button_mask;
+
+            *state = match *state {
+                _ if held == 0 => ComboState::Idle,
+                ComboState::Idle => {
+                    if all_held {
+                        ComboState::Active
+                    } else {
+                        ComboState::Window(now_ms)
+                    }
+                }
+                ComboState::Window(first_press) => {
+                    if now_ms.wrapping_sub(first_press) > self.window_ms {
+                        // Too slow to be a chord, the inputs count as normal presses
+                        ComboState::Missed
+                    } else if all_held {
+                        ComboState::Active
+                    } else {
+                        ComboState::Window(first_press)
+                    }
+                }
+                ComboState::Missed => ComboState::Missed,
+                ComboState::Active => {
+                    if all_held {
+                        ComboState::Active
+                    } else {
+                        // Releasing any input ends the combo; the rest stay
+                        // hidden until released so they don't register as a
+                        // press of their own
+                        if combo.suppress {
+                            self.consumed |= held;
+                        }
+                        ComboState::Missed
+                    }
+                }
+            };
+
+            match *state {
+                ComboState::Active => {
+                    outputs |= 1 << combo.output_bit;
+                    if combo.suppress {
+                        hidden |= combo.button_mask;
+                        firing |= combo.button_mask;
+                    }
+                }
+                // Might still become the combo, so hold off on reporting it
+                ComboState::Window(_) if combo.suppress => waiting |= held,
+                _ => {}
+            }
+        }
+
+        self.tapped = self.waiting & !inputs;
+        self.waiting = waiting & !firing;
+        hidden |= waiting;
+        ((inputs & !(hidden | self.consumed)) | self.tapped, outputs)
+    }
+}
@@ -0,0 +1,86 @@
+//! Button configuration persisted in onboard flash
+//!
+//! The configuration lives in the last 4KB sector of the Pico's 2MB flash,
+//! which `memory.x` keeps out of the firmware image, in the layout
+//! `stored_config` reads and writes. If the sector doesn't hold a valid
+//! config, the built-in `BUTTON_MAP` is used instead.
+
+use button_box_firmware::stored_config::{self, CONFIG_VERSION};
+use rp2040_flash::flash;
+
+pub use button_box_firmware::button_config::{
+    ButtonConfig, ButtonMode, ButtonPull, Polarity, SwitchType,
+};
+
+use crate::{BUTTON_COUNT, BUTTON_MAP};
+
+/// Offset of the reserved config sector from the start of flash
+const CONFIG_OFFSET: u32 = 0x1F_F000;
+/// Address the config sector is mapped to through XIP
+const CONFIG_ADDR: u32 = 0x1000_0000 + CONFIG_OFFSET;
+/// Erase granularity of the flash
+const SECTOR_SIZE: u32 = 4096;
+/// Program granularity of the flash
+const PAGE_SIZE: usize = 256;
+
+/// Runtime configuration of the button box
+pub type Config = stored_config::Config<BUTTON_COUNT>;
+
+// The whole config is programmed as a single flash page
+const _: () = assert!(Config::LEN <= PAGE_SIZE);
+
+/// Logical buttons routed to the keyboard interface unless the host changed
+/// it, the `KEY_MAP` ones
+#[cfg(not(feature = "keyboard"))]
+const DEFAULT_KEYBOARD_BUTTONS: u32 = crate::keys::KEY_BUTTONS;
+#[cfg(feature = "keyboard")]
+const DEFAULT_KEYBOARD_BUTTONS: u32 = 0;
+
+/// Configuration used until one is saved: `BUTTON_MAP`, with the default
+/// routing
+pub fn default_config() -> Config {
+    Config {
+        buttons: BUTTON_MAP,
+        keyboard_buttons: DEFAULT_KEYBOARD_BUTTONS,
+    }
+}
+
+/// Read the stored configuration, falling back to the built-in default if
+/// the sector is blank, corrupt, or from an incompatible layout
+pub fn load_config() -> Config {
+    let mut bytes = [0u8; Config::LEN];
+    for (offset, byte) in bytes.iter_mut().enumerate() {
+        // The sector is memory mapped, so it can be read like RAM
+        *byte = unsafe { core::ptr::read_volatile((CONFIG_ADDR as *const u8).add(offset)) };
+    }
+
+    match Config::from_bytes(&bytes, default_config()) {
+        Some((mut config, version)) => {
+            if version != CONFIG_VERSION {
+                crate::serial_log::info!("Migrating stored config from version {}", version);
+            }
+            // The media key and mouse buttons can't be routed, and may have
+            // moved since the config was saved
+            config.keyboard_buttons &= !crate::ROUTED_BUTTONS;
+            config
+        }
+        None => {
+            crate::serial_log::warn!("No valid stored config, using defaults");
+            default_config()
+        }
+    }
+}
+
+/// Write `config` to the reserved flash sector
+///
+/// Interrupts are disabled for the duration, since nothing may execute from
+/// flash while it is being erased and programmed.
+pub fn save_config(config: &Config) {
+    let mut page = [0xFFu8; PAGE_SIZE];
+    config.to_bytes(&mut page);
+
+    cortex_m::interrupt::free(|_| unsafe {
+        flash::flash_range_erase(CONFIG_OFFSET, SECTOR_SIZE, true);
+        flash::flash_range_program(CONFIG_OFFSET, &page, true);
+    });
+}
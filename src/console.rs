@@ -0,0 +1,381 @@
+//! Line-based configuration console over USB CDC serial
+//!
+//! Commands are typed one per line, with 1-based input and button numbers:
+//!
+//! - `set <input> <button> [<button>...]` reports physical input `<input>` as
+//!   `<button>`, and as every further button listed
+//! - `disable <input>` makes `<input>` always read released, and
+//!   `enable <input>` scans it again
+//! - `dump` prints the current mapping
+//! - `save` writes the current mapping to flash
+//! - `diag` prints what each byte of the HID reports means
+//! - `counts` prints how many times each input has been pressed
+//! - `reset-counts` sets the press counts back to zero
+//! - `invert` turns inverting the reported buttons on or off, for testing
+//! - `press <button> <ms>` reports `<button>` pressed for `<ms>`
+//!   milliseconds on top of the real inputs, for testing the host side
+//! - `record start` records the button changes that follow, with their
+//!   timing, and `record stop` ends the recording, for the replay button to
+//!   play back
+//! - `version` prints the firmware version and the commit it was built from
+//! - `bootloader` detaches from USB and reboots into the UF2 bootloader
+
+use core::fmt::Write;
+
+use heapless::{String, Vec};
+use usb_device::bus::UsbBus;
+use usbd_serial::SerialPort;
+
+use button_box_firmware::hid_descriptor::descriptor_fields::{
+    FIELD_DESCRIPTIONS, REPORT_STRUCTURE, USB_DEVICE_INFO,
+};
+
+use crate::{
+    config::{ButtonMode, ButtonPull, Config, Polarity, SwitchType},
+    BUTTON_COUNT, REPORT_BUTTONS,
+};
+
+/// Longest command line accepted, longer input is dropped
+const LINE_LEN: usize = 32;
+/// Longest simulated press (ms) accepted by `press`
+const MAX_PRESS_MS: u32 = 60_000;
+
+/// A parsed console command, with 0-based indices
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Report physical input `input` as logical button `button`, and as
+    /// every button set in `extra_buttons`
+    Set {
+        input: usize,
+        button: usize,
+        extra_buttons: u32,
+    },
+    /// Scan physical input `input`, or treat it as always released
+    Enable { input: usize, enabled: bool },
+    /// Print the current mapping
+    Dump,
+    /// Persist the current mapping to flash
+    Save,
+    /// Print the report descriptor and report layout explanations
+    Diag,
+    /// Print the press count of every input
+    Counts,
+    /// Zero the press counts
+    ResetCounts,
+    /// Report logical button `button` pressed for `duration_ms`
+    Press { button: usize, duration_ms: u32 },
+    /// Start or stop recording button changes for replay
+    Record { start: bool },
+    /// Toggle reporting every button inverted
+    InvertReport,
+    /// Print the firmware version
+    Version,
+    /// Reboot into the USB bootloader
+    Bootloader,
+}
+
+/// Text sections printed by `diag` after the descriptor fields
+const DIAG_SECTIONS: [&str; 2] = [REPORT_STRUCTURE, USB_DEVICE_INFO];
+
+/// Diagnostics output in progress, sent a line at a time
+///
+/// The dump is several kilobytes, far more than the serial port buffers, so
+/// it is only fed to the port as fast as the host reads it.
+struct Diagnostics {
+    // Line being sent and how many of its bytes went out already
+    line: String<96>,
+    sent: usize,
+    // Next `FIELD_DESCRIPTIONS` entry, then the next `DIAG_SECTIONS` entry
+    // and what is left of the one being sent
+    field: usize,
+    section: usize,
+    rest: &'static str,
+    active: bool,
+}
+
+impl Diagnostics {
+    fn new() -> Self {
+        Self {
+            line: String::new(),
+            sent: 0,
+            field: 0,
+            section: 0,
+            rest: "",
+            active: false,
+        }
+    }
+
+    /// Start over from the first descriptor field
+    fn start(&mut self) {
+        *self = Self::new();
+        self.active = true;
+        self.line.push_str("HID Report Descriptor:\r\n").ok();
+    }
+
+    /// Load the next line of the dump into `line`, or return `false` once
+    /// everything has been sent
+    fn next_line(&mut self) -> bool {
+        self.line.clear();
+        self.sent = 0;
+
+        if let Some((bytes, description)) = FIELD_DESCRIPTIONS.get(self.field) {
+            self.field += 1;
+            write!(self.line, "  {bytes:<18}{description}\r\n").ok();
+            return true;
+        }
+
+        while self.rest.is_empty() {
+            let Some(&section) = DIAG_SECTIONS.get(self.section) else {
+                return false;
+            };
+            self.section += 1;
+            self.rest = section;
+        }
+
+        let (line, rest) = self.rest.split_once('\n').unwrap_or((self.rest, ""));
+        self.rest = rest;
+        self.line.push_str(line).ok();
+        self.line.push_str("\r\n").ok();
+        true
+    }
+
+    /// Send as much of the dump as the serial port takes right now
+    fn flush<B: UsbBus>(&mut self, serial: &mut SerialPort<B>) {
+        while self.active {
+            if self.sent == self.line.len() && !self.next_line() {
+                self.active = false;
+                return;
+            }
+            match serial.write(&self.line.as_bytes()[self.sent..]) {
+                Ok(written) => self.sent += written,
+                // The buffer is full, carry on next loop
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+/// Assembles serial input into lines and parses them into commands
+pub struct Console {
+    line: Vec<u8, LINE_LEN>,
+    // Bytes read from the serial port that haven't been consumed yet
+    rx: [u8; 64],
+    rx_len: usize,
+    rx_pos: usize,
+    diagnostics: Diagnostics,
+}
+
+impl Console {
+    pub fn new() -> Self {
+        Self {
+            line: Vec::new(),
+            rx: [0; 64],
+            rx_len: 0,
+            rx_pos: 0,
+            diagnostics: Diagnostics::new(),
+        }
+    }
+
+    /// Start printing the diagnostics dump, which `poll()` then feeds to the
+    /// serial port as the host reads it
+    pub fn start_diagnostics(&mut self) {
+        self.diagnostics.start();
+    }
+
+    /// Read pending serial input, echoing it back, and return the result of
+    /// parsing a line once a full one has arrived
+    ///
+    /// At most one line is returned per call; any input after it is kept for
+    /// the next call. A diagnostics dump in progress is continued first.
+    pub fn poll<B: UsbBus>(
+        &mut self,
+        serial: &mut SerialPort<B>,
+    ) -> Option<Result<Command, &'static str>> {
+        self.diagnostics.flush(serial);
+
+        if self.rx_pos == self.rx_len {
+            self.rx_pos = 0;
+            self.rx_len = serial.read(&mut self.rx).unwrap_or(0);
+        }
+
+        while self.rx_pos < self.rx_len {
+            let byte = self.rx[self.rx_pos];
+            self.rx_pos += 1;
+
+            match byte {
+                b'\r' | b'\n' => {
+                    // Skip the empty line between the halves of a CR LF
+                    if self.line.is_empty() {
+                        continue;
+                    }
+                    write_str(serial, "\r\n");
+                    let result = parse(&self.line);
+                    self.line.clear();
+                    return Some(result);
+                }
+                // Backspace / delete
+                0x08 | 0x7F => {
+                    if self.line.pop().is_some() {
+                        write_str(serial, "\x08 \x08");
+                    }
+                }
+                _ => {
+                    if self.line.push(byte).is_ok() {
+                        serial.write(&[byte]).ok();
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// Write `text` to the serial port, dropping it if the host isn't reading
+pub fn write_str<B: UsbBus>(serial: &mut SerialPort<B>, text: &str) {
+    serial.write(text.as_bytes()).ok();
+}
+
+/// Print the firmware name and version
+pub fn write_version<B: UsbBus>(serial: &mut SerialPort<B>) {
+    let mut line = String::<64>::new();
+    write!(
+        line,
+        "{} {}\r\n",
+        env!("CARGO_PKG_NAME"),
+        crate::device_info::FIRMWARE_VERSION
+    )
+    .ok();
+    write_str(serial, &line);
+}
+
+/// Print the mapping of every input, one per line
+pub fn write_config<B: UsbBus>(serial: &mut SerialPort<B>, config: &Config) {
+    for (input, button) in config.buttons.iter().enumerate() {
+        let mut line = String::<128>::new();
+        write!(line, "input {} -> button {}", input + 1, button.button + 1).ok();
+        for extra in (0..REPORT_BUTTONS).filter(|extra| button.extra_buttons & (1 << extra) != 0) {
+            write!(line, " + {}", extra + 1).ok();
+        }
+        if let Some(long_press) = button.long_press {
+            write!(line, " (long press: button {})", long_press + 1).ok();
+        }
+        if let Some(double_tap) = button.double_tap {
+            write!(line, " (double tap: button {})", double_tap + 1).ok();
+        }
+        let mode = match button.mode {
+            ButtonMode::Momentary => "",
+            ButtonMode::Toggle => " (toggle)",
+            ButtonMode::Repeat => " (repeat)",
+            ButtonMode::Turbo => " (turbo)",
+            ButtonMode::TapHold => " (tap-hold)",
+        };
+        line.push_str(mode).ok();
+        if button.mode == ButtonMode::TapHold && button.tap_hold_ms != 0 {
+            write!(line, " (hold after {}ms)", button.tap_hold_ms).ok();
+        }
+        if button.polarity == Polarity::ActiveHigh {
+            line.push_str(" (active high)").ok();
+        }
+        let pull = match button.pull {
+            ButtonPull::Up => "",
+            ButtonPull::Down => " (pull-down)",
+            ButtonPull::None => " (no pull)",
+        };
+        line.push_str(pull).ok();
+        if button.switch == SwitchType::NormallyClosed {
+            line.push_str(" (normally closed)").ok();
+        }
+        if button.hold_to_activate_ms != 0 {
+            write!(line, " (hold {}ms to activate)", button.hold_to_activate_ms).ok();
+        }
+        if button.solo {
+            write!(line, " (solo group {})", button.group).ok();
+        }
+        if !button.enabled {
+            line.push_str(" (disabled)").ok();
+        }
+        line.push_str("\r\n").ok();
+        write_str(serial, &line);
+    }
+}
+
+/// Print the press count of every input, one per line
+pub fn write_counts<B: UsbBus>(serial: &mut SerialPort<B>, counts: &[u32]) {
+    for (input, count) in counts.iter().enumerate() {
+        let mut line = String::<32>::new();
+        write!(line, "input {}: {} presses\r\n", input + 1, count).ok();
+        write_str(serial, &line);
+    }
+}
+
+/// Print how many button changes a recording captured
+pub fn write_recorded<B: UsbBus>(serial: &mut SerialPort<B>, changes: usize) {
+    let mut line = String::<32>::new();
+    write!(line, "recorded {changes} changes\r\n").ok();
+    write_str(serial, &line);
+}
+
+fn parse(line: &[u8]) -> Result<Command, &'static str> {
+    let line = core::str::from_utf8(line).map_err(|_| "invalid characters")?;
+    let mut words = line.split_whitespace();
+
+    match words.next() {
+        Some("set") => {
+            let input = parse_number(words.next(), BUTTON_COUNT).ok_or("input out of range")?;
+            let button = parse_number(words.next(), REPORT_BUTTONS).ok_or("button out of range")?;
+            let mut extra_buttons = 0u32;
+            for word in words {
+                let extra =
+                    parse_number(Some(word), REPORT_BUTTONS).ok_or("button out of range")?;
+                extra_buttons |= 1 << extra;
+            }
+            // Listing the main button again adds nothing
+            extra_buttons &= !(1 << button);
+            Ok(Command::Set {
+                input,
+                button,
+                extra_buttons,
+            })
+        }
+        Some(word @ ("enable" | "disable")) => {
+            let input = parse_number(words.next(), BUTTON_COUNT).ok_or("input out of range")?;
+            Ok(Command::Enable {
+                input,
+                enabled: word == "enable",
+            })
+        }
+        Some("dump") => Ok(Command::Dump),
+        Some("save") => Ok(Command::Save),
+        Some("diag") => Ok(Command::Diag),
+        Some("counts") => Ok(Command::Counts),
+        Some("reset-counts") => Ok(Command::ResetCounts),
+        Some("invert") => Ok(Command::InvertReport),
+        Some("press") => {
+            let button = parse_number(words.next(), REPORT_BUTTONS).ok_or("button out of range")?;
+            let duration_ms = words
+                .next()
+                .and_then(|word| word.parse().ok())
+                .filter(|ms| (1..=MAX_PRESS_MS).contains(ms))
+                .ok_or("duration out of range")?;
+            Ok(Command::Press {
+                button,
+                duration_ms,
+            })
+        }
+        Some("record") => match words.next() {
+            Some("start") => Ok(Command::Record { start: true }),
+            Some("stop") => Ok(Command::Record { start: false }),
+            _ => Err("expected start or stop"),
+        },
+        Some("version") => Ok(Command::Version),
+        Some("bootloader") => Ok(Command::Bootloader),
+        _ => Err("unknown command"),
+    }
+}
+
+/// Parse a 1-based number up to `max` into a 0-based index
+fn parse_number(word: Option<&str>, max: usize) -> Option<usize> {
+    let number: usize = word?.parse().ok()?;
+    (1..=max).contains(&number).then(|| number - 1)
+}
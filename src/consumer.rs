@@ -0,0 +1,51 @@
+//! Consumer control (media key) reports
+//!
+//! Logical buttons listed in `CONSUMER_MAP` are reported through a second HID
+//! interface on the Consumer usage page instead of the gamepad, so the host
+//! treats them as media keys.
+
+use usbd_hid::descriptor::{generator_prelude::*, MediaKey};
+
+// HID Report descriptor for up to two simultaneous consumer usages
+#[gen_hid_descriptor(
+    (collection = APPLICATION, usage_page = CONSUMER, usage = CONSUMER_CONTROL) = {
+        (usage_page = CONSUMER, usage_min = 0x00, usage_max = 0x514) = {
+            #[item_settings data,array,absolute,not_null] usage_id=input;
+            #[item_settings data,array,absolute,not_null] second_usage_id=input;
+        };
+    }
+)]
+pub struct ConsumerControlReport {
+    // Usage code of each held media key, 0 for an empty slot. These are
+    // separate fields since the descriptor macro can't serialize u16 arrays.
+    pub usage_id: u16,
+    pub second_usage_id: u16,
+}
+
+/// Logical buttons sent as media keys, with the consumer usage each one sends
+///
+/// Buttons 6 and 7 aren't wired by default; map an input onto them (e.g. with
+/// the console's `set` command) to use them.
+pub const CONSUMER_MAP: [(usize, MediaKey); 2] = [
+    (crate::BUTTON_COUNT + 3, MediaKey::PlayPause),
+    (crate::BUTTON_COUNT + 4, MediaKey::Mute),
+];
+
+/// Report bits of every button in `CONSUMER_MAP`
+pub const CONSUMER_BUTTONS: u32 = button_box_firmware::mapped_buttons(&CONSUMER_MAP);
+
+/// Build a consumer report holding the media key of every mapped button set
+/// in `buttons`
+///
+/// Keys beyond the second are dropped, in `CONSUMER_MAP` order.
+pub fn consumer_report(buttons: u32) -> ConsumerControlReport {
+    let mut pressed = CONSUMER_MAP
+        .iter()
+        .filter(|(button, _)| buttons & (1 << button) != 0)
+        .map(|&(_, key)| u16::from(key));
+
+    ConsumerControlReport {
+        usage_id: pressed.next().unwrap_or(0),
+        second_usage_id: pressed.next().unwrap_or(0),
+    }
+}
@@ -0,0 +1,244 @@
+//! Debouncing of raw button samples
+//!
+//! `Debouncer` turns the raw sample of every physical input, a bit each,
+//! into its debounced state with the algorithm `DebounceMode` picks for the
+//! box. The settle timer suits most switches; the integrator rides out a
+//! switch that keeps chattering while held, and the shift register demands an
+//! unbroken run of matching samples, for inputs with rare long glitches.
+//!
+//! The integrator and shift register take at most one sample a millisecond,
+//! so like every other timing they don't depend on how fast the loop runs.
+
+/// Debounce algorithm, shared by every input of the box
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(all(target_arch = "arm", target_os = "none"), derive(defmt::Format))]
+pub enum DebounceMode {
+    /// Accept a new state once the input has stayed unchanged for
+    /// `debounce_ms`, and a press once it has also held for `min_press_ms`
+    Settle { debounce_ms: u32, min_press_ms: u32 },
+    /// Count up each millisecond the input reads pressed and down each one it
+    /// reads released, between 0 and `samples`, and flip the state at either
+    /// end, so a bounce only delays the change by the samples it cost
+    Integrator { samples: u8 },
+    /// Accept a new state once the last `samples` samples, one a millisecond,
+    /// all agree on it, so any bounce starts the count over
+    ShiftRegister { samples: u8 },
+}
+
+impl DebounceMode {
+    /// Whether the sample counts are ones the debouncer can hold
+    pub const fn is_valid(&self) -> bool {
+        match *self {
+            Self::Settle { .. } => true,
+            Self::Integrator { samples } => samples >= 1,
+            Self::ShiftRegister { samples } => samples >= 1 && samples as u32 <= u32::BITS,
+        }
+    }
+}
+
+/// Debounced state of `N` inputs
+pub struct Debouncer<const N: usize> {
+    mode: DebounceMode,
+    // Raw sample from the previous update, and the debounced state
+    raw: u16,
+    stable: u16,
+    // Tick (ms) at which each input's raw sample last changed, for `Settle`
+    last_change_time: [u32; N],
+    // Integrator count of each input, for `Integrator`
+    counts: [u8; N],
+    // Recent samples of each input, newest in bit 0, for `ShiftRegister`
+    history: [u32; N],
+    // Tick (ms) of the last sample counted by the integrator or shift register
+    sampled_ms: Option<u32>,
+}
+
+impl<const N: usize> Debouncer<N> {
+    pub const fn new(mode: DebounceMode) -> Self {
+        Self {
+            mode,
+            raw: 0,
+            stable: 0,
+            last_change_time: [0; N],
+            counts: [0; N],
+            history: [0; N],
+            sampled_ms: None,
+        }
+    }
+
+    /// Debounced state, bit per input
+    pub fn state(&self) -> u16 {
+        self.stable
+    }
+
+    /// Whether an input's debounced state may still change without a new
+    /// raw edge, so the inputs must be sampled again
+    pub fn settling(&self) -> bool {
+        if self.raw != self.stable {
+            return true;
+        }
+        match self.mode {
+            DebounceMode::Integrator { samples } => (0..N).any(|index| {
+                let end = if self.stable & (1 << index) != 0 {
+                    samples
+                } else {
+                    0
+                };
+                self.counts[index] != end
+            }),
+            _ => false,
+        }
+    }
+
+    /// Feed the `raw` sample taken at `now_ms`, and return the debounced state
+    pub const fn update(&mut self, raw: u16, now_ms: u32) -> u16 {
+        match self.mode {
+            DebounceMode::Settle {
+                debounce_ms,
+                min_press_ms,
+            } => self.settle(raw, now_ms, debounce_ms, min_press_ms),
+            DebounceMode::Integrator { samples } => {
+                if self.next_sample(now_ms) {
+                    self.integrate(raw, samples);
+                }
+            }
+            DebounceMode::ShiftRegister { samples } => {
+                if self.next_sample(now_ms) {
+                    self.shift(raw, samples);
+                }
+            }
+        }
+        self.raw = raw;
+        self.stable
+    }
+
+    /// Whether `now_ms` is a new millisecond, to be counted as a sample
+    const fn next_sample(&mut self, now_ms: u32) -> bool {
+        let new = !matches!(self.sampled_ms, Some(sampled) if sampled == now_ms);
+        self.sampled_ms = Some(now_ms);
+        new
+    }
+
+    // The passes below are `const` with plain loops so the traces at the end
+    // of the file can run them at compile time
+
+    const fn settle(&mut self, raw: u16, now_ms: u32, debounce_ms: u32, min_press_ms: u32) {
+        let mut index = 0;
+        while index < N {
+            let bit = 1 << index;
+
+            // Any raw edge restarts the settle period for that input
+            if (raw ^ self.raw) & bit != 0 {
+                self.last_change_time[index] = now_ms;
+            }
+
+            // Accept the new state once it has held for the debounce period,
+            // and a press once it has also held for the minimum press time
+            let settle_ms = if raw & bit != 0 && min_press_ms > debounce_ms {
+                min_press_ms
+            } else {
+                debounce_ms
+            };
+            let settled = now_ms.wrapping_sub(self.last_change_time[index]) >= settle_ms;
+            if settled {
+                self.stable = (self.stable & !bit) | (raw & bit);
+            }
+            index += 1;
+        }
+    }
+
+    const fn integrate(&mut self, raw: u16, samples: u8) {
+        let mut index = 0;
+        while index < N {
+            let bit = 1 << index;
+            let count = &mut self.counts[index];
+            if raw & bit == 0 {
+                *count = count.saturating_sub(1);
+            } else if *count < samples {
+                *count += 1;
+            }
+
+            if *count == samples {
+                self.stable |= bit;
+            } else if *count == 0 {
+                self.stable &= !bit;
+            }
+            index += 1;
+        }
+    }
+
+    const fn shift(&mut self, raw: u16, samples: u8) {
+        let window = u32::MAX >> (u32::BITS - samples as u32);
+        let mut index = 0;
+        while index < N {
+            let bit = 1 << index;
+            let history = (self.history[index] << 1) | (raw & bit != 0) as u32;
+            self.history[index] = history;
+
+            if history & window == 0 {
+                self.stable &= !bit;
+            } else if history & window == window {
+                self.stable |= bit;
+            }
+            index += 1;
+        }
+    }
+}
+
+/// Feed `trace`, a sample of one input per millisecond, through a debouncer
+/// in `mode`, and return its final state and how often it changed
+const fn run_trace(mode: DebounceMode, trace: &[u8]) -> (bool, u32) {
+    let mut debouncer = Debouncer::<1>::new(mode);
+    let mut changes = 0;
+    let mut ms = 0;
+    while ms < trace.len() {
+        let was = debouncer.stable;
+        if debouncer.update(trace[ms] as u16, ms as u32) != was {
+            changes += 1;
+        }
+        ms += 1;
+    }
+    (debouncer.stable != 0, changes)
+}
+
+// Every mode must turn a bouncy press into a single clean one, and ignore a
+// lone noise spike, checked when the crate is compiled
+const _: () = {
+    // A press that bounces for 3ms, is held with a one-sample dropout, then
+    // released with bounces
+    const PRESS: [u8; 30] = [
+        1, 0, 1, 1, 0, 1, 1, 1, 1, 1, 1, 1, 0, 1, 1, 1, 1, 1, 1, 1, //
+        0, 1, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    // A single-sample spike on an idle input
+    const SPIKE: [u8; 12] = [0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0];
+
+    const MODES: [DebounceMode; 3] = [
+        DebounceMode::Settle {
+            debounce_ms: 5,
+            min_press_ms: 2,
+        },
+        DebounceMode::Integrator { samples: 5 },
+        DebounceMode::ShiftRegister { samples: 5 },
+    ];
+
+    let mut index = 0;
+    while index < MODES.len() {
+        let mode = MODES[index];
+        assert!(mode.is_valid());
+
+        // Pressed once, released once, and released at the end
+        let (pressed, changes) = run_trace(mode, &PRESS);
+        assert!(!pressed && changes == 2);
+        // And pressed while the press is held
+        let (pressed, _) = run_trace(mode, PRESS.split_at(20).0);
+        assert!(pressed);
+
+        let (pressed, changes) = run_trace(mode, &SPIKE);
+        assert!(!pressed && changes == 0);
+        index += 1;
+    }
+
+    // Sample counts the debouncers can't hold
+    assert!(!DebounceMode::Integrator { samples: 0 }.is_valid());
+    assert!(!DebounceMode::ShiftRegister { samples: 33 }.is_valid());
+};
@@ -0,0 +1,54 @@
+//! USB identity of the button box
+//!
+//! Everything the host sees before it reads a single report lives here, so
+//! rebranding the box only means editing these constants.
+
+use core::fmt::Write;
+
+use heapless::String;
+use rp2040_flash::flash;
+
+/// USB vendor ID (Van Ooijen Technische Informatica, shared VID for hobby projects)
+pub const VID: u16 = 0x16c0;
+/// USB product ID (generic HID device)
+pub const PID: u16 = 0x27dd;
+
+/// Manufacturer string descriptor
+pub const MANUFACTURER: &str = "Button Box Co";
+/// Product string descriptor, the name most hosts show for the device
+pub const PRODUCT: &str = "2-Button Box";
+
+/// Firmware version and the git commit it was built from, e.g.
+/// `0.1.0+1a2b3c4d`, printed by the console's `version` command
+pub const FIRMWARE_VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), "+", env!("GIT_HASH"));
+/// Longest version string allowed, so it always fits on one console line
+pub const FIRMWARE_VERSION_MAX_LEN: usize = 32;
+
+const _: () = assert!(
+    FIRMWARE_VERSION.len() <= FIRMWARE_VERSION_MAX_LEN,
+    "firmware version string too long"
+);
+
+/// Length of the flash unique ID in bytes
+const UNIQUE_ID_LEN: usize = 8;
+/// Length of the serial number string, two hex digits per unique ID byte
+pub const SERIAL_LEN: usize = UNIQUE_ID_LEN * 2;
+
+/// Serial number string descriptor, the unique ID of the board's flash chip
+/// in upper-case hex
+///
+/// Every Pico's flash has a different ID, so several boxes on one host can be
+/// told apart. Interrupts are disabled while the ID is read, since nothing
+/// may execute from flash while it is busy answering the command.
+pub fn serial_number() -> String<SERIAL_LEN> {
+    let mut id = [0u8; UNIQUE_ID_LEN];
+    cortex_m::interrupt::free(|_| unsafe {
+        flash::flash_unique_id(&mut id, true);
+    });
+
+    let mut serial = String::new();
+    for byte in id {
+        write!(serial, "{:02X}", byte).ok();
+    }
+    serial
+}
@@ -0,0 +1,161 @@
+//! SSD1306 128x32 OLED on I2C, showing which buttons are held
+//!
+//! The top two rows of the display hold a box per report button, the first
+//! 16 buttons above the rest, filled while the button is pressed and outlined
+//! while it isn't. Only boxes whose state changed are redrawn, one per
+//! `update()`, so a burst of changes is spread over several loops instead of
+//! blocking one with a long I2C transfer.
+//!
+//! The boxes are `embedded-graphics` rectangles drawn into the `ssd1306`
+//! driver's frame buffer, whose `flush()` only sends the area that changed.
+
+use embedded_graphics::{
+    pixelcolor::BinaryColor,
+    prelude::*,
+    primitives::{PrimitiveStyle, PrimitiveStyleBuilder, Rectangle},
+};
+use ssd1306::{mode::BufferedGraphicsMode, prelude::*, I2CDisplayInterface, Ssd1306};
+
+use crate::bsp::hal::{
+    gpio::{
+        bank0::{Gpio6, Gpio7},
+        FunctionI2C, Pin, PullUp,
+    },
+    i2c::I2C,
+    pac::I2C1,
+};
+
+/// I2C bus the display sits on, SDA on GPIO6 and SCL on GPIO7
+pub type DisplayI2c = I2C<
+    I2C1,
+    (
+        Pin<Gpio6, FunctionI2C, PullUp>,
+        Pin<Gpio7, FunctionI2C, PullUp>,
+    ),
+>;
+
+/// Driver of the panel at 0x3C, the address of most SSD1306 modules (build
+/// the interface with `I2CDisplayInterface::new_alternate_address()` for one
+/// with the address pad moved to 0x3D)
+type Panel =
+    Ssd1306<I2CInterface<DisplayI2c>, DisplaySize128x32, BufferedGraphicsMode<DisplaySize128x32>>;
+
+/// Pixels across and down the panel
+const WIDTH: u32 = 128;
+const HEIGHT: u32 = 32;
+/// Boxes shown, one per report button, and boxes in each row
+const BOXES: usize = 32;
+const ROW_BOXES: usize = 16;
+/// Pixels per box across and down, including the gap to the next one
+const BOX_WIDTH: u32 = WIDTH / ROW_BOXES as u32;
+const BOX_HEIGHT: u32 = 8;
+
+/// Minimum time (ms) between samples of the buttons to show, capping the
+/// refresh rate at about 30Hz
+const REFRESH_MS: u32 = 33;
+
+// Every box must fit across the panel, and both rows down it
+const _: () = assert!(BOX_WIDTH >= 3);
+const _: () = assert!(BOXES.div_ceil(ROW_BOXES) as u32 * BOX_HEIGHT <= HEIGHT);
+const _: () = assert!(BOXES == crate::REPORT_BUTTONS);
+
+/// Display of the held buttons
+pub struct Display {
+    panel: Panel,
+    // Whether the controller is known to be set up, cleared by any bus error
+    // so a reconnected display is set up again
+    initialized: bool,
+    // Buttons to show, and the boxes drawn filled on the panel right now
+    target: u32,
+    shown: u32,
+    // Tick (ms) at which `target` was last sampled
+    sampled_ms: u32,
+}
+
+impl Display {
+    pub fn new(i2c: DisplayI2c) -> Self {
+        let panel = Ssd1306::new(
+            I2CDisplayInterface::new(i2c),
+            DisplaySize128x32,
+            DisplayRotation::Rotate0,
+        )
+        .into_buffered_graphics_mode();
+        let mut display = Self {
+            panel,
+            initialized: false,
+            target: 0,
+            shown: 0,
+            sampled_ms: 0,
+        };
+        display.init();
+        // Retries after this stay quiet, since nothing may be connected
+        if !display.initialized {
+            crate::serial_log::warn!("SSD1306 display not responding");
+        }
+        display
+    }
+
+    /// Switch the panel on and draw every box empty
+    fn init(&mut self) {
+        // Clear the whole panel; the following updates outline the boxes
+        self.panel.clear_buffer();
+        let result = self.panel.init().and_then(|_| self.panel.flush());
+        self.initialized = result.is_ok();
+        // Nothing is drawn yet, so mark every box as needing a redraw
+        self.shown = !self.target;
+    }
+
+    /// Show the held `buttons`, drawing at most one changed box
+    ///
+    /// The buttons are only sampled every `REFRESH_MS`, but the latest state
+    /// is always the one drawn in the end, however short a press was.
+    pub fn update(&mut self, buttons: u32, now_ms: u32) {
+        if now_ms.wrapping_sub(self.sampled_ms) >= REFRESH_MS {
+            self.sampled_ms = now_ms;
+            self.target = buttons;
+            if !self.initialized {
+                self.init();
+            }
+        }
+        if !self.initialized {
+            return;
+        }
+
+        let changed = self.target ^ self.shown;
+        if changed == 0 {
+            return;
+        }
+        let index = changed.trailing_zeros() as usize;
+        let pressed = self.target & (1 << index) != 0;
+        match self.draw_box(index, pressed) {
+            Ok(()) => self.shown ^= 1 << index,
+            Err(_) => self.initialized = false,
+        }
+    }
+
+    /// Draw box `index` filled or outlined, and send it to the panel
+    fn draw_box(&mut self, index: usize, filled: bool) -> Result<(), <Panel as DrawTarget>::Error> {
+        let style = if filled {
+            PrimitiveStyle::with_fill(BinaryColor::On)
+        } else {
+            // Filled with off pixels, to clear a box drawn filled before
+            PrimitiveStyleBuilder::new()
+                .stroke_color(BinaryColor::On)
+                .stroke_width(1)
+                .fill_color(BinaryColor::Off)
+                .build()
+        };
+
+        // A blank column to the right of the box and a blank row above and
+        // below it
+        let column = (index % ROW_BOXES) as u32 * BOX_WIDTH;
+        let row = (index / ROW_BOXES) as u32 * BOX_HEIGHT + 1;
+        Rectangle::new(
+            Point::new(column as i32, row as i32),
+            Size::new(BOX_WIDTH - 1, BOX_HEIGHT - 2),
+        )
+        .into_styled(style)
+        .draw(&mut self.panel)?;
+        self.panel.flush()
+    }
+}
@@ -0,0 +1,99 @@
+//! MCP23017 16-bit I/O expander on I2C, for buttons beyond the Pico's pins
+//!
+//! The expander's inputs are read with a single register transfer per scan.
+//! Buttons connect each used expander pin to ground; the MCP23017's own
+//! pull-ups hold released inputs high, so a low pin reads as pressed.
+
+use embedded_hal::i2c::I2c;
+
+use crate::bsp::hal::{
+    gpio::{
+        bank0::{Gpio0, Gpio1},
+        FunctionI2C, Pin, PullUp,
+    },
+    i2c::I2C,
+    pac::I2C0,
+};
+
+/// I2C bus the expander sits on, SDA on GPIO0 and SCL on GPIO1
+pub type ExpanderI2c = I2C<
+    I2C0,
+    (
+        Pin<Gpio0, FunctionI2C, PullUp>,
+        Pin<Gpio1, FunctionI2C, PullUp>,
+    ),
+>;
+
+/// 7-bit address with A0-A2 tied to ground
+const ADDRESS: u8 = 0x20;
+/// Register addresses in the default `IOCON.BANK = 0` layout, where each A
+/// register is followed by its B twin so both ports transfer together
+const IODIRA: u8 = 0x00;
+const GPPUA: u8 = 0x0C;
+const GPIOA: u8 = 0x12;
+
+/// The first `N` pins of an MCP23017, GPA0-GPA7 then GPB0-GPB7
+pub struct Expander<const N: usize> {
+    i2c: ExpanderI2c,
+    // Whether the pull-ups are known to be set up, cleared by any bus error
+    // so a re-powered expander is configured again
+    initialized: bool,
+}
+
+impl<const N: usize> Expander<N> {
+    const INPUT_MASK: u16 = ((1u32 << N) - 1) as u16;
+
+    pub fn new(i2c: ExpanderI2c) -> Self {
+        const { assert!(N <= 16) };
+        let mut expander = Self {
+            i2c,
+            initialized: false,
+        };
+        if !expander.is_empty() {
+            expander.init();
+        }
+        expander
+    }
+
+    /// Whether the expander has no buttons at all
+    pub fn is_empty(&self) -> bool {
+        N == 0
+    }
+
+    /// Make every pin an input with its pull-up enabled
+    fn init(&mut self) {
+        let result = self
+            .i2c
+            .write(ADDRESS, &[IODIRA, 0xFF, 0xFF])
+            .and_then(|_| self.i2c.write(ADDRESS, &[GPPUA, 0xFF, 0xFF]));
+        self.initialized = result.is_ok();
+        if !self.initialized {
+            crate::serial_log::warn!("MCP23017 not responding");
+        }
+    }
+
+    /// Read the pressed buttons, bit `n` for expander pin `n`
+    ///
+    /// A NACK or bus error reads as every input released, so a missing or
+    /// disconnected expander never leaves a button stuck on.
+    pub fn read(&mut self) -> u16 {
+        if self.is_empty() {
+            return 0;
+        }
+        if !self.initialized {
+            self.init();
+            if !self.initialized {
+                return 0;
+            }
+        }
+
+        let mut ports = [0u8; 2];
+        match self.i2c.write_read(ADDRESS, &[GPIOA], &mut ports) {
+            Ok(()) => !u16::from_le_bytes(ports) & Self::INPUT_MASK,
+            Err(_) => {
+                self.initialized = false;
+                0
+            }
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! Feature report answering the host's GET_REPORT and SET_REPORT requests
+//!
+//! `usbd_hid`'s `HIDClass` rejects both, so this helper class sits in front
+//! of it in the `poll()` list and answers feature report requests for the
+//! gamepad interface itself. Everything else falls through to the HID class.
+//!
+//! Reading the report returns the debounced buttons. Writing it remaps a
+//! physical input to a logical button, like the console's `set` command, and
+//! can ask for the configuration to be saved as well. A write can instead
+//! route a logical button to the gamepad or keyboard interface, or ask the box
+//! to identify itself by blinking its status LED.
+
+use button_box_firmware::hid_descriptor;
+use usb_device::{
+    class_prelude::*,
+    control::{Recipient, Request, RequestType},
+};
+
+/// HID class request for reading a report over the control pipe
+const HID_REQ_GET_REPORT: u8 = 0x01;
+/// HID class request for writing a report over the control pipe
+const HID_REQ_SET_REPORT: u8 = 0x09;
+/// Report type of a feature report, in the high byte of the request value
+const REPORT_TYPE_FEATURE: u8 = 3;
+
+/// Bytes in the feature report: four of buttons, then input, button and flags
+const REPORT_LEN: usize = 7;
+
+const _: () = assert!(
+    hid_descriptor::report_len(
+        hid_descriptor::HID_REPORT_DESCRIPTOR,
+        hid_descriptor::FEATURE_ITEM
+    ) == REPORT_LEN,
+    "HID_REPORT_DESCRIPTOR's feature report doesn't match REPORT_LEN"
+);
+/// Flag asking for the configuration to be saved after the mapping is applied
+const FLAG_SAVE: u8 = 0x01;
+/// Flag asking the box to identify itself, with no mapping applied
+const FLAG_IDENTIFY: u8 = 0x02;
+/// Flag making the write route a button instead of mapping an input, with
+/// the interface in place of the input
+const FLAG_ROUTE: u8 = 0x04;
+/// Interfaces a button can be routed to
+const ROUTE_GAMEPAD: u8 = 0;
+const ROUTE_KEYBOARD: u8 = 1;
+
+/// Mapping written by the host through the feature report
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct MappingWrite {
+    /// Physical input to remap (0-based)
+    pub input: usize,
+    /// Logical button (0-based report bit) to report it as
+    pub button: usize,
+    /// Persist the configuration to flash afterwards
+    pub save: bool,
+}
+
+/// Routing written by the host through the feature report
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct RouteWrite {
+    /// Logical button (0-based report bit) to route
+    pub button: usize,
+    /// Report it on the keyboard interface rather than the gamepad
+    pub keyboard: bool,
+    /// Persist the configuration to flash afterwards
+    pub save: bool,
+}
+
+/// Request decoded from a feature report write
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Write {
+    Mapping(MappingWrite),
+    Route(RouteWrite),
+    Identify,
+}
+
+/// Answers feature report reads with the latest debounced buttons, and takes
+/// mapping writes for the main loop to apply
+pub struct FeatureReport {
+    // Interface number of the HID class the report belongs to
+    interface: u16,
+    // Report bytes: little-endian button bits, then the last accepted mapping
+    report: [u8; REPORT_LEN],
+    // Accepted writes the main loop hasn't applied yet
+    pending: Option<MappingWrite>,
+    pending_route: Option<RouteWrite>,
+    // Whether an identify request is waiting for the main loop
+    identify: bool,
+}
+
+impl FeatureReport {
+    /// Serve the feature report of HID interface `interface`
+    ///
+    /// Interfaces are numbered in the order classes are created, so the
+    /// gamepad's `HIDClass`, created first, is interface 0.
+    pub fn new(interface: u16) -> Self {
+        Self {
+            interface,
+            report: [0; REPORT_LEN],
+            pending: None,
+            pending_route: None,
+            identify: false,
+        }
+    }
+
+    /// Set the buttons returned by the next read
+    pub fn set_buttons(&mut self, buttons: u32) {
+        self.report[..4].copy_from_slice(&buttons.to_le_bytes());
+    }
+
+    /// Take the mapping the host wrote since the last call, if any
+    ///
+    /// Only the latest write is kept, so this should be called every loop.
+    pub fn take_mapping(&mut self) -> Option<MappingWrite> {
+        self.pending.take()
+    }
+
+    /// Take the routing the host wrote since the last call, if any
+    ///
+    /// Only the latest write is kept, so this should be called every loop.
+    pub fn take_route(&mut self) -> Option<RouteWrite> {
+        self.pending_route.take()
+    }
+
+    /// Whether the host asked the box to identify itself since the last call
+    pub fn take_identify(&mut self) -> bool {
+        core::mem::take(&mut self.identify)
+    }
+
+    /// Whether `req` targets this interface's feature report with `request`
+    fn is_feature_request(&self, req: &Request, request: u8) -> bool {
+        req.request_type == RequestType::Class
+            && req.recipient == Recipient::Interface
+            && req.index == self.interface
+            && req.request == request
+            && (req.value >> 8) as u8 == REPORT_TYPE_FEATURE
+    }
+}
+
+/// Decode a written feature report, or `None` if it is malformed
+///
+/// The button bytes are ignored. An identify request ignores the mapping
+/// bytes too, and can't be combined with saving; otherwise the input (or the
+/// interface of a route) must exist, the button must fit in the report and
+/// no unknown flag may be set.
+fn parse_write(data: &[u8]) -> Option<Write> {
+    let &[_, _, _, _, input, button, flags] = data else {
+        return None;
+    };
+    if flags == FLAG_IDENTIFY {
+        return Some(Write::Identify);
+    }
+    if flags & FLAG_ROUTE != 0 {
+        return parse_route(input, button, flags & !FLAG_ROUTE);
+    }
+    let (input, button) = (usize::from(input), usize::from(button));
+
+    if input >= crate::BUTTON_COUNT || button >= crate::REPORT_BUTTONS || flags & !FLAG_SAVE != 0 {
+        return None;
+    }
+
+    Some(Write::Mapping(MappingWrite {
+        input,
+        button,
+        save: flags & FLAG_SAVE != 0,
+    }))
+}
+
+/// Decode a route write, with `FLAG_ROUTE` taken out of `flags`
+///
+/// Only builds with the keyboard interface next to the gamepad can route,
+/// and buttons of the media key and mouse interfaces stay where they are.
+fn parse_route(interface: u8, button: u8, flags: u8) -> Option<Write> {
+    let button = usize::from(button);
+    let keyboard = match interface {
+        ROUTE_GAMEPAD => false,
+        ROUTE_KEYBOARD => true,
+        _ => return None,
+    };
+    if cfg!(feature = "keyboard") || button >= crate::REPORT_BUTTONS || flags & !FLAG_SAVE != 0 {
+        return None;
+    }
+    if crate::ROUTED_BUTTONS & (1 << button) != 0 {
+        return None;
+    }
+
+    Some(Write::Route(RouteWrite {
+        button,
+        keyboard,
+        save: flags & FLAG_SAVE != 0,
+    }))
+}
+
+impl<B: UsbBus> UsbClass<B> for FeatureReport {
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+        if self.is_feature_request(&req, HID_REQ_GET_REPORT) {
+            xfer.accept_with(&self.report).ok();
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+        if !self.is_feature_request(&req, HID_REQ_SET_REPORT) {
+            return;
+        }
+
+        match parse_write(xfer.data()) {
+            Some(Write::Identify) => {
+                self.identify = true;
+                xfer.accept().ok();
+            }
+            Some(Write::Mapping(mapping)) => {
+                // Read back as the mapping, without the one-shot flags
+                self.report[4] = mapping.input as u8;
+                self.report[5] = mapping.button as u8;
+                self.report[6] = 0;
+                self.pending = Some(mapping);
+                xfer.accept().ok();
+            }
+            Some(Write::Route(route)) => {
+                // Read back as the route, keeping only the route flag
+                self.report[4] = if route.keyboard {
+                    ROUTE_KEYBOARD
+                } else {
+                    ROUTE_GAMEPAD
+                };
+                self.report[5] = route.button as u8;
+                self.report[6] = FLAG_ROUTE;
+                self.pending_route = Some(route);
+                xfer.accept().ok();
+            }
+            // Stall the request so the host sees the write failed
+            None => {
+                xfer.reject().ok();
+            }
+        }
+    }
+}
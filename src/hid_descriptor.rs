@@ -2,42 +2,329 @@
 //!
 //! This module provides detailed analysis of the HID report descriptor
 //! used by the button box, including the raw byte representation.
+//!
+//! Not every helper here is used by the firmware itself; they document the
+//! report format for host-side tooling and debugging.
+#![allow(dead_code)]
+
+use button_bits::BUTTON_MASK;
+
+/// How the gamepad presents itself to the host: the usage page and usage of
+/// its application collection, and the logical range of the X/Y stick
+///
+/// Some sims only bind to a device that looks like one they know, such as a
+/// joystick rather than a gamepad, or one with signed axes. The buttons, hat
+/// and slider keep their ranges whatever the profile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DescriptorProfile {
+    /// Usage page of the application collection (Generic Desktop is 0x01)
+    pub usage_page: u8,
+    /// Usage of the application collection on that page
+    pub usage: u8,
+    /// Logical minimum of the X and Y axes, reported with the stick at its
+    /// lowest
+    pub axis_min: i16,
+    /// Logical maximum of the X and Y axes, reported with the stick at its
+    /// highest
+    pub axis_max: i16,
+}
+
+impl DescriptorProfile {
+    /// Generic Desktop gamepad with 0-255 axes, the default
+    pub const GAMEPAD: Self = Self {
+        usage_page: 0x01,
+        usage: 0x05,
+        axis_min: 0,
+        axis_max: 255,
+    };
+    /// Generic Desktop joystick with 0-255 axes
+    pub const JOYSTICK: Self = Self {
+        usage_page: 0x01,
+        usage: 0x04,
+        axis_min: 0,
+        axis_max: 255,
+    };
+    /// Generic Desktop joystick with signed axes, centered on 0
+    pub const SIGNED_JOYSTICK: Self = Self {
+        usage_page: 0x01,
+        usage: 0x04,
+        axis_min: -128,
+        axis_max: 127,
+    };
+
+    /// Whether the usage is set and the axis range fits the 8-bit axis
+    /// fields, read as unsigned (0-255) or, with a negative minimum, signed
+    /// (-128 to 127)
+    pub const fn is_valid(&self) -> bool {
+        let fits = if self.axis_min < 0 {
+            self.axis_min >= i8::MIN as i16 && self.axis_max <= i8::MAX as i16
+        } else {
+            self.axis_max <= u8::MAX as i16
+        };
+        self.usage_page != 0 && self.usage != 0 && self.axis_min < self.axis_max && fits
+    }
+
+    /// Report byte of an axis at `position` (0-255), scaled onto the axis
+    /// range
+    const fn axis_byte(&self, position: u8) -> u8 {
+        let span = (self.axis_max - self.axis_min) as i32;
+        let offset = (position as i32 * span + u8::MAX as i32 / 2) / u8::MAX as i32;
+        // Truncating to the low byte gives the two's complement of a
+        // negative value, as a signed field reads it
+        (self.axis_min as i32 + offset) as u8
+    }
+}
+
+/// Profile `HID_REPORT_DESCRIPTOR` is built with
+pub const PROFILE: DescriptorProfile = DescriptorProfile::GAMEPAD;
 
-/// Raw HID report descriptor bytes for a 2-button gamepad
+const _: () = assert!(
+    PROFILE.is_valid(),
+    "PROFILE is not a valid descriptor profile"
+);
+
+const AXIS_MIN: [u8; 2] = PROFILE.axis_min.to_le_bytes();
+const AXIS_MAX: [u8; 2] = PROFILE.axis_max.to_le_bytes();
+
+/// Report byte of an axis at `position` (0-255, 0x80 the middle), scaled
+/// onto `PROFILE`'s axis range
 ///
-/// This is the compiled form of our HID descriptor that gets sent to the host.
-/// Understanding this helps with debugging and compatibility issues.
+/// With the default 0-255 range this is `position` itself.
+pub const fn axis_byte(position: u8) -> u8 {
+    PROFILE.axis_byte(position)
+}
+
+// The stick's ends reach the ends of the range
+const _: () = {
+    assert!(axis_byte(0) == AXIS_MIN[0]);
+    assert!(axis_byte(u8::MAX) == AXIS_MAX[0]);
+};
+
+// The presets are all valid. The unsigned ones report the position as it
+// is, and the signed one moves every position down by 128, so the middle is
+// at 0
+const _: () = {
+    let mut position = 0;
+    while position <= u8::MAX as u32 {
+        let byte = position as u8;
+        assert!(DescriptorProfile::GAMEPAD.axis_byte(byte) == byte);
+        assert!(DescriptorProfile::JOYSTICK.axis_byte(byte) == byte);
+        let signed = DescriptorProfile::SIGNED_JOYSTICK.axis_byte(byte) as i8;
+        assert!(signed as i32 == position as i32 - 128);
+        position += 1;
+    }
+
+    assert!(DescriptorProfile::GAMEPAD.is_valid());
+    assert!(DescriptorProfile::JOYSTICK.is_valid());
+    assert!(DescriptorProfile::SIGNED_JOYSTICK.is_valid());
+    // Too wide for a signed byte, or backwards
+    assert!(!DescriptorProfile {
+        axis_min: -128,
+        axis_max: 255,
+        ..DescriptorProfile::GAMEPAD
+    }
+    .is_valid());
+    assert!(!DescriptorProfile {
+        axis_min: 10,
+        axis_max: 10,
+        ..DescriptorProfile::GAMEPAD
+    }
+    .is_valid());
+};
+
+/// Raw HID report descriptor bytes for a 32-button gamepad (or as `PROFILE`
+/// sets) with X/Y axes, a hat switch, a signed slider, an output report with
+/// 8 LEDs and a rumble intensity, and a feature report of the 32 buttons and
+/// a mapping write
+///
+/// This is the HID descriptor that gets sent to the host, returned by
+/// `ButtonBoxReport::desc()`. It is written by hand rather than generated,
+/// since `gen_hid_descriptor` can't narrow a field's logical maximum and the
+/// hat needs a 0-7 range, and `PROFILE` fills in the application usage and
+/// the axis range. Understanding this helps with debugging and
+/// compatibility issues. Like the generator, it sets Report Size before
+/// Report Count for each main item.
+#[rustfmt::skip]
 pub const HID_REPORT_DESCRIPTOR: &[u8] = &[
-    // Usage Page (Generic Desktop)
-    0x05, 0x01, // Usage (Gamepad)
-    0x09, 0x05, // Collection (Application)
-    0xA1, 0x01, // Usage (Pointer)
-    0x09, 0x01, // Collection (Physical)
-    0xA1, 0x00, // Usage Page (Button)
-    0x05, 0x09, // Usage Minimum (Button 1)
-    0x19, 0x01, // Usage Maximum (Button 2)
-    0x29, 0x02, // Logical Minimum (0)
-    0x15, 0x00, // Logical Maximum (1)
-    0x25, 0x01, // Report Count (2)
-    0x95, 0x02, // Report Size (1 bit)
-    0x75, 0x01, // Input (Data, Variable, Absolute)
-    0x81, 0x02, // Report Count (6) - Padding bits
-    0x95, 0x06, // Report Size (1 bit)
-    0x75, 0x01, // Input (Constant, Variable, Absolute)
-    0x81, 0x01, // End Collection (Physical)
+    0x05, PROFILE.usage_page, // Usage Page (Generic Desktop by default)
+    0x09, PROFILE.usage, // Usage (Gamepad by default)
+    0xA1, 0x01, // Collection (Application)
+    0x09, 0x01, //   Usage (Pointer)
+    0xA1, 0x00, //   Collection (Physical)
+    0x05, 0x09, //     Usage Page (Button)
+    0x19, 0x01, //     Usage Minimum (Button 1)
+    0x29, 0x20, //     Usage Maximum (Button 32)
+    0x15, 0x00, //     Logical Minimum (0)
+    0x25, 0x01, //     Logical Maximum (1)
+    0x75, 0x01, //     Report Size (1 bit)
+    0x95, 0x20, //     Report Count (32)
+    0x81, 0x02, //     Input (Data, Variable, Absolute)
+    0x05, 0x01, //     Usage Page (Generic Desktop)
+    0x09, 0x30, //     Usage (X)
+    0x16, AXIS_MIN[0], AXIS_MIN[1], //     Logical Minimum (0 by default)
+    0x26, AXIS_MAX[0], AXIS_MAX[1], //     Logical Maximum (255 by default)
+    0x75, 0x08, //     Report Size (8 bits)
+    0x95, 0x01, //     Report Count (1)
+    0x81, 0x02, //     Input (Data, Variable, Absolute)
+    0x09, 0x31, //     Usage (Y)
+    0x81, 0x02, //     Input (Data, Variable, Absolute), same size as X
+    0x09, 0x39, //     Usage (Hat switch)
+    0x15, 0x00, //     Logical Minimum (0)
+    0x25, 0x07, //     Logical Maximum (7)
+    0x35, 0x00, //     Physical Minimum (0)
+    0x46, 0x3B, 0x01, // Physical Maximum (315 degrees)
+    0x65, 0x14, //     Unit (Degrees, English Rotation)
+    0x81, 0x42, //     Input (Data, Variable, Absolute, Null State)
+    0x65, 0x00, //     Unit (None)
+    0x09, 0x36, //     Usage (Slider)
+    0x15, 0x81, //     Logical Minimum (-127)
+    0x25, 0x7F, //     Logical Maximum (127)
+    0x45, 0x00, //     Physical Maximum (0), so the physical range follows the logical one
+    0x81, 0x02, //     Input (Data, Variable, Absolute)
+    0x15, 0x00, //     Logical Minimum (0)
+    0x05, 0x08, //     Usage Page (LEDs)
+    0x19, 0x01, //     Usage Minimum (LED 1)
+    0x29, 0x08, //     Usage Maximum (LED 8)
+    0x25, 0x01, //     Logical Maximum (1)
+    0x75, 0x01, //     Report Size (1 bit)
+    0x95, 0x08, //     Report Count (8)
+    0x91, 0x02, //     Output (Data, Variable, Absolute)
+    0x06, 0x00, 0xFF, //     Usage Page (Vendor Defined 0xFF00)
+    0x09, 0x01, //     Usage (Rumble intensity)
+    0x26, 0xFF, 0x00, //     Logical Maximum (255)
+    0x75, 0x08, //     Report Size (8 bits)
+    0x95, 0x01, //     Report Count (1)
+    0x91, 0x02, //     Output (Data, Variable, Absolute)
+    0x05, 0x09, //     Usage Page (Button)
+    0x19, 0x01, //     Usage Minimum (Button 1)
+    0x29, 0x20, //     Usage Maximum (Button 32)
+    0x25, 0x01, //     Logical Maximum (1)
+    0x75, 0x01, //     Report Size (1 bit)
+    0x95, 0x20, //     Report Count (32)
+    0xB1, 0x02, //     Feature (Data, Variable, Absolute)
+    0x06, 0x00, 0xFF, //     Usage Page (Vendor Defined 0xFF00)
+    0x09, 0x02, //     Usage (Mapping input)
+    0x09, 0x03, //     Usage (Mapping button)
+    0x09, 0x04, //     Usage (Mapping flags)
+    0x26, 0xFF, 0x00, //     Logical Maximum (255)
+    0x75, 0x08, //     Report Size (8 bits)
+    0x95, 0x03, //     Report Count (3)
+    0xB1, 0x02, //     Feature (Data, Variable, Absolute)
+    0xC0, //   End Collection (Physical)
     0xC0, // End Collection (Application)
-    0xC0,
 ];
 
+/// Main item prefixes, with the size bits masked off
+pub const INPUT_ITEM: u8 = 0x80;
+pub const OUTPUT_ITEM: u8 = 0x90;
+pub const FEATURE_ITEM: u8 = 0xB0;
+/// Global items setting the size and count of the following main items
+const REPORT_SIZE_ITEM: u8 = 0x74;
+const REPORT_COUNT_ITEM: u8 = 0x94;
+
+/// Report Size and Report Count of a decoded main item
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportField {
+    pub size: u32,
+    pub count: u32,
+}
+
+/// Decode `descriptor` and return the size and count of its `n`th `item`
+/// (`INPUT_ITEM`, `OUTPUT_ITEM` or `FEATURE_ITEM`), or `None` if it has fewer
+///
+/// Only short items are understood, which is all this descriptor uses.
+pub const fn nth_main_item(descriptor: &[u8], item: u8, n: usize) -> Option<ReportField> {
+    let mut size = 0;
+    let mut count = 0;
+    let mut seen = 0;
+    let mut offset = 0;
+
+    while offset < descriptor.len() {
+        let prefix = descriptor[offset];
+        let data_len = match prefix & 0x03 {
+            3 => 4,
+            len => len as usize,
+        };
+
+        let mut data = 0u32;
+        let mut byte = 0;
+        while byte < data_len {
+            data |= (descriptor[offset + 1 + byte] as u32) << (8 * byte);
+            byte += 1;
+        }
+
+        let tag = prefix & 0xFC;
+        if tag == REPORT_SIZE_ITEM {
+            size = data;
+        } else if tag == REPORT_COUNT_ITEM {
+            count = data;
+        } else if tag == item {
+            if seen == n {
+                return Some(ReportField { size, count });
+            }
+            seen += 1;
+        }
+
+        offset += 1 + data_len;
+    }
+
+    None
+}
+
+/// Bytes of the report made of every `item` (`INPUT_ITEM`, `OUTPUT_ITEM` or
+/// `FEATURE_ITEM`) of `descriptor`, rounded up to a whole byte
+pub const fn report_len(descriptor: &[u8], item: u8) -> usize {
+    let mut bits = 0;
+    let mut n = 0;
+    while let Some(field) = nth_main_item(descriptor, item, n) {
+        bits += field.size * field.count;
+        n += 1;
+    }
+    bits.div_ceil(8) as usize
+}
+
+const fn is_field(field: Option<ReportField>, size: u32, count: u32) -> bool {
+    match field {
+        Some(field) => field.size == size && field.count == count,
+        None => false,
+    }
+}
+
+// Decoding the descriptor must give the report layout the rest of this module
+// and `ButtonBoxReport`'s serialization assume: 32 one-bit buttons, then one
+// byte each for X, Y, the hat and the slider, in an 8-byte input report, and 8 LED bits
+// then a rumble byte in a 2-byte output report, and the 32 buttons again
+// then three mapping bytes in a 7-byte feature report
+const _: () = {
+    let desc = HID_REPORT_DESCRIPTOR;
+    assert!(is_field(nth_main_item(desc, INPUT_ITEM, 0), 1, 32));
+    assert!(is_field(nth_main_item(desc, INPUT_ITEM, 1), 8, 1));
+    assert!(is_field(nth_main_item(desc, INPUT_ITEM, 2), 8, 1));
+    assert!(is_field(nth_main_item(desc, INPUT_ITEM, 3), 8, 1));
+    assert!(is_field(nth_main_item(desc, INPUT_ITEM, 4), 8, 1));
+    assert!(nth_main_item(desc, INPUT_ITEM, 5).is_none());
+    assert!(is_field(nth_main_item(desc, OUTPUT_ITEM, 0), 1, 8));
+    assert!(is_field(nth_main_item(desc, OUTPUT_ITEM, 1), 8, 1));
+    assert!(nth_main_item(desc, OUTPUT_ITEM, 2).is_none());
+    assert!(is_field(nth_main_item(desc, FEATURE_ITEM, 0), 1, 32));
+    assert!(is_field(nth_main_item(desc, FEATURE_ITEM, 1), 8, 3));
+    assert!(nth_main_item(desc, FEATURE_ITEM, 2).is_none());
+    assert!(report_len(desc, INPUT_ITEM) == 8);
+    assert!(report_len(desc, OUTPUT_ITEM) == 2);
+    assert!(report_len(desc, FEATURE_ITEM) == 7);
+    // And it opens with the profile's usage
+    assert!(desc[1] == PROFILE.usage_page && desc[3] == PROFILE.usage);
+};
+
 /// Structure representing a single HID report from the button box
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(C, packed)]
 pub struct ButtonBoxHidReport {
-    /// Button states packed into a single byte
+    /// Button states packed into 32 bits, sent little-endian
     /// - Bit 0: Button 1 (1 = pressed, 0 = released)
     /// - Bit 1: Button 2 (1 = pressed, 0 = released)
-    /// - Bits 2-7: Padding (always 0)
-    pub buttons: u8,
+    /// - Bits 2-31: Buttons 3-32 (0 unless wired)
+    pub buttons: u32,
 }
 
 impl ButtonBoxHidReport {
@@ -46,44 +333,71 @@ impl ButtonBoxHidReport {
         Self { buttons: 0 }
     }
 
-    /// Set the state of button 1
-    pub fn set_button1(&mut self, pressed: bool) {
+    /// Set the state of the button at `index` (0-based)
+    pub const fn set_button(&mut self, index: usize, pressed: bool) {
         if pressed {
-            self.buttons |= 0x01;
+            self.buttons |= 1 << index;
         } else {
-            self.buttons &= !0x01;
+            self.buttons &= !(1 << index);
         }
     }
 
+    /// Get the state of the button at `index` (0-based)
+    pub const fn button_pressed(&self, index: usize) -> bool {
+        (self.buttons & (1 << index)) != 0
+    }
+
+    /// Set the state of button 1
+    pub const fn set_button1(&mut self, pressed: bool) {
+        self.set_button(0, pressed);
+    }
+
     /// Set the state of button 2
-    pub fn set_button2(&mut self, pressed: bool) {
-        if pressed {
-            self.buttons |= 0x02;
-        } else {
-            self.buttons &= !0x02;
-        }
+    pub const fn set_button2(&mut self, pressed: bool) {
+        self.set_button(1, pressed);
     }
 
     /// Get the state of button 1
-    pub fn button1_pressed(&self) -> bool {
-        (self.buttons & 0x01) != 0
+    pub const fn button1_pressed(&self) -> bool {
+        self.button_pressed(0)
     }
 
     /// Get the state of button 2
-    pub fn button2_pressed(&self) -> bool {
-        (self.buttons & 0x02) != 0
+    pub const fn button2_pressed(&self) -> bool {
+        self.button_pressed(1)
     }
 
     /// Convert to byte array for transmission
-    pub fn as_bytes(&self) -> [u8; 1] {
-        [self.buttons]
+    pub const fn as_bytes(&self) -> [u8; 4] {
+        self.buttons.to_le_bytes()
     }
 
-    /// Create from raw bytes
-    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
-        if bytes.len() >= 1 {
+    /// Create from raw bytes, or `None` if there are fewer than 4
+    ///
+    /// Bits of buttons that aren't wired (`PADDING_MASK`) are silently
+    /// cleared, so a report from a box with more buttons decodes without
+    /// error but loses them. Check the raw bits with
+    /// `button_helpers::is_valid_button_mask()` first to catch that.
+    pub const fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() >= 4 {
             Some(Self {
-                buttons: bytes[0] & 0x03, // Mask to only use first 2 bits
+                // Mask to only use wired buttons
+                buttons: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) & BUTTON_MASK,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Create from raw bytes keeping every button bit, or `None` if there
+    /// are fewer than 4
+    ///
+    /// For boxes built with the `raw` feature, or with more buttons wired,
+    /// whose reports use bits past `BUTTON_MASK`.
+    pub const fn from_raw_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() >= 4 {
+            Some(Self {
+                buttons: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
             })
         } else {
             None
@@ -99,44 +413,141 @@ impl Default for ButtonBoxHidReport {
 
 /// HID Descriptor field descriptions for documentation purposes
 pub mod descriptor_fields {
-    //! Human-readable descriptions of HID descriptor fields
-
     /// HID descriptor field explanations
     pub const FIELD_DESCRIPTIONS: &[(&str, &str)] = &[
-        ("0x05, 0x01", "Usage Page (Generic Desktop)"),
-        ("0x09, 0x05", "Usage (Gamepad)"),
+        (
+            "0x05, 0x01",
+            "Usage Page (PROFILE, Generic Desktop by default)",
+        ),
+        ("0x09, 0x05", "Usage (PROFILE, Gamepad by default)"),
         ("0xA1, 0x01", "Collection (Application)"),
         ("0x09, 0x01", "Usage (Pointer)"),
         ("0xA1, 0x00", "Collection (Physical)"),
         ("0x05, 0x09", "Usage Page (Button)"),
         ("0x19, 0x01", "Usage Minimum (Button 1)"),
-        ("0x29, 0x02", "Usage Maximum (Button 2)"),
+        ("0x29, 0x20", "Usage Maximum (Button 32)"),
         ("0x15, 0x00", "Logical Minimum (0)"),
         ("0x25, 0x01", "Logical Maximum (1)"),
-        ("0x95, 0x02", "Report Count (2 buttons)"),
         ("0x75, 0x01", "Report Size (1 bit each)"),
+        ("0x95, 0x20", "Report Count (32 buttons)"),
+        ("0x81, 0x02", "Input (Data, Variable, Absolute)"),
+        ("0x05, 0x01", "Usage Page (Generic Desktop)"),
+        ("0x09, 0x30", "Usage (X)"),
+        (
+            "0x16, 0x00, 0x00",
+            "Logical Minimum (PROFILE, 0 by default)",
+        ),
+        (
+            "0x26, 0xFF, 0x00",
+            "Logical Maximum (PROFILE, 255 by default)",
+        ),
+        ("0x75, 0x08", "Report Size (8 bits)"),
+        ("0x95, 0x01", "Report Count (1)"),
+        ("0x81, 0x02", "Input (Data, Variable, Absolute)"),
+        ("0x09, 0x31", "Usage (Y)"),
+        ("0x81, 0x02", "Input (Data, Variable, Absolute)"),
+        ("0x09, 0x39", "Usage (Hat switch)"),
+        ("0x15, 0x00", "Logical Minimum (0)"),
+        ("0x25, 0x07", "Logical Maximum (7)"),
+        ("0x35, 0x00", "Physical Minimum (0)"),
+        ("0x46, 0x3B, 0x01", "Physical Maximum (315 degrees)"),
+        ("0x65, 0x14", "Unit (Degrees, English Rotation)"),
+        ("0x81, 0x42", "Input (Data, Variable, Absolute, Null State)"),
+        ("0x65, 0x00", "Unit (None)"),
+        ("0x09, 0x36", "Usage (Slider)"),
+        ("0x15, 0x81", "Logical Minimum (-127)"),
+        ("0x25, 0x7F", "Logical Maximum (127)"),
+        (
+            "0x45, 0x00",
+            "Physical Maximum (0, follow the logical range)",
+        ),
         ("0x81, 0x02", "Input (Data, Variable, Absolute)"),
-        ("0x95, 0x06", "Report Count (6 padding bits)"),
+        ("0x15, 0x00", "Logical Minimum (0)"),
+        ("0x05, 0x08", "Usage Page (LEDs)"),
+        ("0x19, 0x01", "Usage Minimum (LED 1)"),
+        ("0x29, 0x08", "Usage Maximum (LED 8)"),
+        ("0x25, 0x01", "Logical Maximum (1)"),
         ("0x75, 0x01", "Report Size (1 bit each)"),
-        ("0x81, 0x01", "Input (Constant, Variable, Absolute)"),
+        ("0x95, 0x08", "Report Count (8 LEDs)"),
+        ("0x91, 0x02", "Output (Data, Variable, Absolute)"),
+        ("0x06, 0x00, 0xFF", "Usage Page (Vendor Defined 0xFF00)"),
+        ("0x09, 0x01", "Usage (Rumble intensity)"),
+        ("0x26, 0xFF, 0x00", "Logical Maximum (255)"),
+        ("0x75, 0x08", "Report Size (8 bits)"),
+        ("0x95, 0x01", "Report Count (1)"),
+        ("0x91, 0x02", "Output (Data, Variable, Absolute)"),
+        ("0x05, 0x09", "Usage Page (Button)"),
+        ("0x19, 0x01", "Usage Minimum (Button 1)"),
+        ("0x29, 0x20", "Usage Maximum (Button 32)"),
+        ("0x25, 0x01", "Logical Maximum (1)"),
+        ("0x75, 0x01", "Report Size (1 bit each)"),
+        ("0x95, 0x20", "Report Count (32 buttons)"),
+        ("0xB1, 0x02", "Feature (Data, Variable, Absolute)"),
+        ("0x06, 0x00, 0xFF", "Usage Page (Vendor Defined 0xFF00)"),
+        ("0x09, 0x02", "Usage (Mapping input)"),
+        ("0x09, 0x03", "Usage (Mapping button)"),
+        ("0x09, 0x04", "Usage (Mapping flags)"),
+        ("0x26, 0xFF, 0x00", "Logical Maximum (255)"),
+        ("0x75, 0x08", "Report Size (8 bits)"),
+        ("0x95, 0x03", "Report Count (3)"),
+        ("0xB1, 0x02", "Feature (Data, Variable, Absolute)"),
         ("0xC0", "End Collection (Physical)"),
         ("0xC0", "End Collection (Application)"),
     ];
 
     /// Report structure explanation
     pub const REPORT_STRUCTURE: &str = r#"
-Report Structure (1 byte total):
-================================
+Report Structure (8 bytes total):
+=================================
+Bytes 0-3: Buttons 1-32, little-endian (bit 0 of byte 0 is button 1,
+           bit 7 of byte 3 is button 32; 1 = pressed, 0 = released)
+  Byte 0, bit 0: Button 1 state
+  Byte 0, bit 1: Button 2 state
+  Everything else: Buttons 3-32 (0 unless wired)
+Byte 4: X axis (0-255, 0x80 = centered; another range if PROFILE sets one)
+Byte 5: Y axis (0-255, 0x80 = centered; another range if PROFILE sets one)
+Byte 6: Hat switch (0 = N, 1 = NE, ... 7 = NW, 8 = centered)
+Byte 7: Slider (signed, -127 to 127, 0 = middle of travel or no slider)
+
+Examples (default profile):
+- No buttons pressed, stick and hat centered: 0x00 0x00 0x00 0x00 0x80 0x80 0x08 0x00
+- Button 1 pressed: 0x01 0x00 0x00 0x00 0x80 0x80 0x08 0x00
+- Button 2 pressed: 0x02 0x00 0x00 0x00 0x80 0x80 0x08 0x00
+- Button 32 pressed: 0x00 0x00 0x00 0x80 0x80 0x80 0x08 0x00
+- Both buttons pressed, stick full left: 0x03 0x00 0x00 0x00 0x00 0x80 0x08 0x00
+- Hat pushed right: 0x00 0x00 0x00 0x00 0x80 0x80 0x02 0x00
+- Slider at its top end: 0x00 0x00 0x00 0x00 0x80 0x80 0x08 0x7F
+
+Output Report (2 bytes, host to device):
+========================================
 Byte 0:
-  Bit 0: Button 1 state (1 = pressed, 0 = released)
-  Bit 1: Button 2 state (1 = pressed, 0 = released)
-  Bits 2-7: Padding (constant 0)
+  Bits 0-3: LED 1-4 state (1 = on, 0 = off)
+  Bits 4-7: LEDs 5-8 (no LED wired)
+Byte 1: Rumble motor intensity (0 = off, 255 = full)
+
+Feature Report (7 bytes, GET_REPORT and SET_REPORT):
+====================================================
+Bytes 0-3: Current debounced buttons, laid out like input report bytes 0-3
+           (ignored when written)
+Byte 4: Physical input to remap (0-based), or with bit 2 of byte 6 the
+        interface to route to (0 = gamepad, 1 = keyboard)
+Byte 5: Logical button to report it as, or to route (0-31)
+Byte 6: Flags
+  Bit 0: Save the configuration to flash after applying the write
+  Bit 1: Identify: blink the status LED for 5s instead, bytes 4-5 ignored
+         (no other flag may be set with it)
+  Bit 2: Route: report the button on the interface in byte 4 instead of
+         remapping an input (not in keyboard builds, and not for media key
+         or mouse buttons)
+  Bits 3-7: Must be 0
+
+Writing the report applies the mapping or route in bytes 4-6, reading it back
+returns the last one accepted (with only the route flag kept).
 
 Examples:
-- No buttons pressed: 0x00
-- Button 1 pressed: 0x01
-- Button 2 pressed: 0x02
-- Both buttons pressed: 0x03
+- Report input 1 as button 5 and save: 0x00 0x00 0x00 0x00 0x01 0x04 0x01
+- Route button 2 to the keyboard and save: 0x00 0x00 0x00 0x00 0x01 0x01 0x05
+- Identify the box: 0x00 0x00 0x00 0x00 0x00 0x00 0x02
 "#;
 
     /// USB device information
@@ -147,44 +558,339 @@ Vendor ID (VID): 0x16C0 (Van Ooijen Technische Informatica)
 Product ID (PID): 0x27DD (Generic HID device)
 Manufacturer: "Button Box Co"
 Product: "2-Button Box"
-Serial Number: "001"
-Device Class: Interface-specific (0x00)
+Serial Number: flash unique ID, 16 hex digits
+Device Class: Miscellaneous, with Interface Association Descriptors (0xEF)
 "#;
 }
 
+/// Value of an uppercase hex digit
+const fn hex_digit(digit: u8) -> u8 {
+    match digit {
+        b'0'..=b'9' => digit - b'0',
+        b'A'..=b'F' => digit - b'A' + 10,
+        _ => panic!("FIELD_DESCRIPTIONS bytes must be uppercase hex"),
+    }
+}
+
+// `FIELD_DESCRIPTIONS` spells out every byte of `HID_REPORT_DESCRIPTOR`, in
+// order, so its usage and axis entries can't go stale when `PROFILE` changes
+const _: () = {
+    let fields = descriptor_fields::FIELD_DESCRIPTIONS;
+    let mut offset = 0;
+    let mut field = 0;
+    while field < fields.len() {
+        let text = fields[field].0.as_bytes();
+        // "0xAB", then ", " before each further byte
+        let mut at = 0;
+        while at < text.len() {
+            assert!(text[at] == b'0' && text[at + 1] == b'x');
+            let byte = hex_digit(text[at + 2]) << 4 | hex_digit(text[at + 3]);
+            assert!(
+                offset < HID_REPORT_DESCRIPTOR.len() && HID_REPORT_DESCRIPTOR[offset] == byte,
+                "FIELD_DESCRIPTIONS doesn't match HID_REPORT_DESCRIPTOR, update it with PROFILE"
+            );
+            offset += 1;
+            at += 6;
+        }
+        field += 1;
+    }
+    assert!(offset == HID_REPORT_DESCRIPTOR.len());
+};
+
 /// Constants for button bit positions
 pub mod button_bits {
-    pub const BUTTON1_BIT: u8 = 0x01;
-    pub const BUTTON2_BIT: u8 = 0x02;
-    pub const BUTTON_MASK: u8 = 0x03;
-    pub const PADDING_MASK: u8 = 0xFC;
+    pub const BUTTON1_BIT: u32 = 0x0000_0001;
+    pub const BUTTON2_BIT: u32 = 0x0000_0002;
+    /// Bits of the buttons wired on the default 2-button box
+    pub const BUTTON_MASK: u32 = 0x0000_0003;
+    /// Report bits with no button wired to them
+    pub const PADDING_MASK: u32 = !BUTTON_MASK;
 }
 
 /// Helper functions for button state manipulation
 pub mod button_helpers {
     use super::button_bits::*;
 
-    /// Extract button states from raw byte
-    pub fn extract_buttons(raw_byte: u8) -> (bool, bool) {
-        let button1 = (raw_byte & BUTTON1_BIT) != 0;
-        let button2 = (raw_byte & BUTTON2_BIT) != 0;
+    /// Extract button states from raw button bits
+    pub const fn extract_buttons(raw: u32) -> (bool, bool) {
+        let button1 = (raw & BUTTON1_BIT) != 0;
+        let button2 = (raw & BUTTON2_BIT) != 0;
         (button1, button2)
     }
 
-    /// Create button byte from individual button states
-    pub fn create_button_byte(button1: bool, button2: bool) -> u8 {
-        let mut byte = 0u8;
+    /// Create button bits from individual button states
+    pub const fn create_button_mask(button1: bool, button2: bool) -> u32 {
+        let mut mask = 0u32;
         if button1 {
-            byte |= BUTTON1_BIT;
+            mask |= BUTTON1_BIT;
         }
         if button2 {
-            byte |= BUTTON2_BIT;
+            mask |= BUTTON2_BIT;
         }
-        byte
+        mask
     }
 
-    /// Validate that a byte only has valid button bits set
-    pub fn is_valid_button_byte(byte: u8) -> bool {
-        (byte & PADDING_MASK) == 0
+    /// Validate that only wired button bits are set
+    pub const fn is_valid_button_mask(mask: u32) -> bool {
+        (mask & PADDING_MASK) == 0
+    }
+}
+
+/// Check `ButtonBoxHidReport`'s byte conversions for the button bits `raw`
+const fn check_round_trip(raw: u32) {
+    use button_bits::*;
+    use button_helpers::*;
+
+    let bytes = raw.to_le_bytes();
+
+    // Decoding drops the padding bits and keeps the wired ones
+    let report = match ButtonBoxHidReport::from_bytes(&bytes) {
+        Some(report) => report,
+        None => panic!(),
+    };
+    assert!(report.buttons == raw & BUTTON_MASK);
+    assert!(is_valid_button_mask(report.buttons));
+
+    // Anything with padding bits set is rejected by the check
+    assert!(is_valid_button_mask(raw) == (raw & PADDING_MASK == 0));
+
+    // A decoded report survives another round trip unchanged
+    match ButtonBoxHidReport::from_bytes(&report.as_bytes()) {
+        Some(again) => assert!(again.buttons == report.buttons),
+        None => panic!(),
+    }
+
+    // Raw decoding keeps every bit, and encodes back to the same bytes
+    match ButtonBoxHidReport::from_raw_bytes(&bytes) {
+        Some(report) => {
+            assert!(report.buttons == raw);
+            let again = report.as_bytes();
+            assert!(
+                again[0] == bytes[0]
+                    && again[1] == bytes[1]
+                    && again[2] == bytes[2]
+                    && again[3] == bytes[3]
+            );
+        }
+        None => panic!(),
+    }
+}
+
+// Invariants of `ButtonBoxHidReport`'s byte conversions, checked when the
+// crate is compiled for every value of each report byte, with the other
+// bytes all clear or all set; all 2^32 button patterns would take too long
+const _: () = {
+    let mut byte = 0;
+    while byte < 4 {
+        let mut value = 0u32;
+        while value <= u8::MAX as u32 {
+            let shift = 8 * byte;
+            check_round_trip(value << shift);
+            check_round_trip((value << shift) | !(0xFF << shift));
+            value += 1;
+        }
+        byte += 1;
+    }
+    // And a short buffer fails
+    assert!(ButtonBoxHidReport::from_bytes(&[0xFF, 0xFF, 0xFF]).is_none());
+    assert!(ButtonBoxHidReport::from_raw_bytes(&[0xFF, 0xFF, 0xFF]).is_none());
+};
+
+// Each button is its own bit, little-endian: button N + 1 is bit N % 8 of
+// byte N / 8
+const _: () = {
+    let mut index = 0;
+    while index < 32 {
+        let mut report = ButtonBoxHidReport::new();
+        report.set_button(index, true);
+        let bytes = report.as_bytes();
+        let mut byte = 0;
+        while byte < 4 {
+            let expected = if byte == index / 8 {
+                1 << (index % 8)
+            } else {
+                0
+            };
+            assert!(bytes[byte] == expected);
+            byte += 1;
+        }
+        match ButtonBoxHidReport::from_raw_bytes(&bytes) {
+            Some(decoded) => assert!(decoded.button_pressed(index)),
+            None => panic!(),
+        }
+        index += 1;
+    }
+};
+
+/// Whether `report` encodes to exactly `bytes`
+const fn encodes_to(report: ButtonBoxHidReport, bytes: [u8; 4]) -> bool {
+    let encoded = report.as_bytes();
+    encoded[0] == bytes[0]
+        && encoded[1] == bytes[1]
+        && encoded[2] == bytes[2]
+        && encoded[3] == bytes[3]
+}
+
+// The descriptor declares the buttons as 32 one-bit fields, which HID packs
+// from the least significant bit of the first byte up, so the field reads as
+// a little-endian `u32`. Checked against spelled-out bytes rather than
+// `to_le_bytes()`, with bits set in every byte up to the top one, so a swap
+// of the byte order on either side can't cancel itself out
+const _: () = {
+    use button_bits::*;
+
+    // Buttons 1, 2, 17, 22, 27 and 32: one or two bits in each byte
+    const BUTTONS: u32 = 0x8421_0003;
+    const BYTES: [u8; 4] = [0x03, 0x00, 0x21, 0x84];
+    assert!(encodes_to(ButtonBoxHidReport { buttons: BUTTONS }, BYTES));
+
+    match ButtonBoxHidReport::from_raw_bytes(&BYTES) {
+        Some(report) => {
+            assert!(report.buttons == BUTTONS);
+            assert!(report.button_pressed(0) && report.button_pressed(16));
+            // Button 32 is the top bit of the last byte
+            assert!(report.button_pressed(31) && !report.button_pressed(30));
+            assert!(encodes_to(report, BYTES));
+        }
+        None => panic!(),
+    }
+    // Decoding with the wired mask keeps only the low byte's buttons
+    match ButtonBoxHidReport::from_bytes(&BYTES) {
+        Some(report) => assert!(report.buttons == BUTTON1_BIT | BUTTON2_BIT),
+        None => panic!(),
+    }
+
+    // The last byte holds the high buttons, not button 1 as it would in
+    // big-endian order
+    match ButtonBoxHidReport::from_raw_bytes(&[0x00, 0x00, 0x00, 0x01]) {
+        Some(report) => assert!(report.buttons == 1 << 24 && !report.button1_pressed()),
+        None => panic!(),
+    }
+    match ButtonBoxHidReport::from_bytes(&[0x00, 0x00, 0x00, 0x01]) {
+        Some(report) => assert!(report.buttons == 0),
+        None => panic!(),
+    }
+    // Button 16 is the top bit of the second byte
+    assert!(encodes_to(
+        ButtonBoxHidReport {
+            buttons: 0x0000_8000
+        },
+        [0x00, 0x80, 0x00, 0x00]
+    ));
+
+    // Only the first four bytes are the buttons; the axes after them in the
+    // input report don't leak in
+    match ButtonBoxHidReport::from_raw_bytes(&[0x01, 0x00, 0x00, 0x80, 0x7F, 0x7F, 0x08, 0x00]) {
+        Some(report) => assert!(report.buttons == 0x8000_0001),
+        None => panic!(),
+    }
+};
+
+// Setting and clearing the two buttons touches only their own bits
+const _: () = {
+    use button_bits::*;
+
+    let mut report = ButtonBoxHidReport::new();
+    report.set_button1(true);
+    assert!(report.buttons == BUTTON1_BIT && report.button1_pressed() && !report.button2_pressed());
+    report.set_button2(true);
+    assert!(report.buttons == BUTTON1_BIT | BUTTON2_BIT);
+    report.set_button1(false);
+    assert!(report.buttons == BUTTON2_BIT && !report.button1_pressed() && report.button2_pressed());
+    report.set_button2(false);
+    assert!(report.buttons == 0);
+
+    let bytes = ButtonBoxHidReport {
+        buttons: BUTTON2_BIT,
+    }
+    .as_bytes();
+    assert!(bytes[0] == 0x02 && bytes[1] == 0x00 && bytes[2] == 0x00 && bytes[3] == 0x00);
+
+    assert!(button_helpers::create_button_mask(true, false) == BUTTON1_BIT);
+    let (button1, button2) = button_helpers::extract_buttons(BUTTON2_BIT | 0x8000_0000);
+    assert!(!button1 && button2);
+};
+
+#[cfg(test)]
+mod tests {
+    use super::button_bits::*;
+    use super::button_helpers::*;
+    use super::*;
+
+    /// Button masks to round-trip: every single bit, and a run of
+    /// pseudo-random ones from a fixed xorshift seed, so a failure repeats
+    fn masks() -> impl Iterator<Item = u32> {
+        let mut state = 0x2545_F491u32;
+        let random = core::iter::repeat_with(move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        });
+        [0, u32::MAX]
+            .into_iter()
+            .chain((0..32).map(|bit| 1 << bit))
+            .chain(random.take(100_000))
+    }
+
+    #[test]
+    fn raw_bytes_round_trip_every_mask() {
+        for mask in masks() {
+            let report = ButtonBoxHidReport { buttons: mask };
+            let decoded = ButtonBoxHidReport::from_raw_bytes(&report.as_bytes());
+            assert_eq!(decoded, Some(report), "mask {mask:#010x}");
+        }
+    }
+
+    #[test]
+    fn bytes_round_trip_the_wired_buttons() {
+        for mask in masks() {
+            let bytes = ButtonBoxHidReport { buttons: mask }.as_bytes();
+            let decoded = ButtonBoxHidReport::from_bytes(&bytes).expect("4 bytes decode");
+            let buttons = decoded.buttons;
+            assert_eq!(buttons, mask & BUTTON_MASK, "mask {mask:#010x}");
+            assert!(is_valid_button_mask(buttons));
+            assert_eq!(
+                extract_buttons(buttons),
+                (mask & BUTTON1_BIT != 0, mask & BUTTON2_BIT != 0)
+            );
+        }
+    }
+
+    #[test]
+    fn high_byte_goes_last_on_the_wire() {
+        // Button 1 and button 32: the lowest bit of the first byte and the
+        // top bit of the last one
+        const BUTTONS: u32 = 0x8000_0001;
+        let report = ButtonBoxHidReport { buttons: BUTTONS };
+        let bytes = report.as_bytes();
+        assert_eq!(bytes, [0x01, 0x00, 0x00, 0x80]);
+
+        let decoded = ButtonBoxHidReport::from_raw_bytes(&bytes).unwrap();
+        assert_eq!(decoded, report);
+        assert!(decoded.button_pressed(0) && decoded.button_pressed(31));
+
+        // Only button 1 is wired, and the high byte doesn't land on it
+        let decoded = ButtonBoxHidReport::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            decoded,
+            ButtonBoxHidReport {
+                buttons: BUTTON1_BIT
+            }
+        );
+        assert_eq!(decoded.as_bytes(), [0x01, 0x00, 0x00, 0x00]);
+        let decoded = ButtonBoxHidReport::from_bytes(&[0x00, 0x00, 0x00, 0x80]).unwrap();
+        assert_eq!(decoded, ButtonBoxHidReport::new());
+    }
+
+    #[test]
+    fn helpers_round_trip_both_buttons() {
+        for (button1, button2) in [(false, false), (true, false), (false, true), (true, true)] {
+            let mask = create_button_mask(button1, button2);
+            assert_eq!(extract_buttons(mask), (button1, button2));
+            let report = ButtonBoxHidReport::from_bytes(&mask.to_le_bytes()).unwrap();
+            assert_eq!(report.button1_pressed(), button1);
+            assert_eq!(report.button2_pressed(), button2);
+        }
     }
 }
@@ -0,0 +1,560 @@
+//! Input devices beyond plain buttons
+//!
+//! This holds the rotary encoder, whose detents are translated into button
+//! pulses by the main loop and whose optional push switch is a button of its
+//! own, the analog joystick and slider read through the ADC, and the
+//! four-way hat switch. An analog reading can also be thresholded into a
+//! button with `AnalogButton`, like the slider's optional button.
+
+use embedded_hal::digital::InputPin;
+use embedded_hal_0_2::adc::OneShot;
+
+use crate::bsp::hal::{
+    adc::AdcPin,
+    gpio::{
+        bank0::{Gpio26, Gpio27},
+        DynPinId, FunctionSio, Pin, PullNone, PullUp, SioInput,
+    },
+    Adc,
+};
+
+/// GPIO pin type for encoder phase inputs
+pub type EncoderPin = Pin<DynPinId, FunctionSio<SioInput>, PullUp>;
+
+// ADC pin types for the joystick axes, X on ADC0 and Y on ADC1
+pub type XAxisPin = AdcPin<Pin<Gpio26, FunctionSio<SioInput>, PullNone>>;
+pub type YAxisPin = AdcPin<Pin<Gpio27, FunctionSio<SioInput>, PullNone>>;
+/// ADC pin type for the slider, on ADC2
+#[cfg(feature = "slider")]
+pub type SliderPin =
+    AdcPin<Pin<crate::bsp::hal::gpio::bank0::Gpio28, FunctionSio<SioInput>, PullNone>>;
+
+/// GPIO pin type for hat switch direction inputs
+pub type HatPin = Pin<DynPinId, FunctionSio<SioInput>, PullUp>;
+
+/// Report value for an axis at rest in the middle of its travel
+pub const AXIS_CENTER: u8 = 0x80;
+
+/// Hat report value with no direction pressed, outside the 0-7 logical range
+/// so the host treats it as the null state
+pub const HAT_CENTERED: u8 = 8;
+
+/// Largest reading of the 12-bit ADC
+const ADC_MAX: u16 = 0x0FFF;
+
+/// Quadrature transitions per mechanical detent on common encoders
+const STEPS_PER_DETENT: i8 = 4;
+/// Time (ms) the encoder's push switch must stay unchanged before a new state
+/// is accepted, like `DEBOUNCE_MS` for the buttons
+const SWITCH_DEBOUNCE_MS: u32 = 5;
+
+/// Step contributed by each `(previous << 2) | current` phase transition
+///
+/// Valid Gray-code moves count as +1 (clockwise) or -1 (counter-clockwise).
+/// Staying put counts as 0, and so do impossible moves where both phases
+/// changed at once, so contact bounce and skipped samples never count as a tick.
+const TRANSITIONS: [i8; 16] = [
+    0, -1, 1, 0, //
+    1, 0, 0, -1, //
+    -1, 0, 0, 1, //
+    0, 1, -1, 0, //
+];
+
+/// Rotation direction of a single encoder detent
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Direction {
+    Clockwise,
+    CounterClockwise,
+}
+
+/// Step of an encoder's acceleration curve
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Acceleration {
+    /// A detent following the previous one in the same direction within this
+    /// time (ms) counts as `multiplier` increments
+    pub within_ms: u32,
+    pub multiplier: u8,
+}
+
+/// Calibration of one joystick axis, in raw 12-bit ADC counts
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct AxisConfig {
+    /// Reading with the stick at rest
+    pub center: u16,
+    /// Readings within this distance of `center` report as centered
+    pub deadzone: u16,
+    /// Readings at the ends of the stick's travel, reported as 0 and 255.
+    /// Readings past them are clamped.
+    pub min: u16,
+    pub max: u16,
+    /// Swap the ends, for a pot wired the other way round
+    pub invert: bool,
+}
+
+impl AxisConfig {
+    /// Whether the deadzone fits inside the travel, which must fit in the
+    /// ADC range
+    pub const fn is_valid(&self) -> bool {
+        self.max <= ADC_MAX
+            && self.min < self.center.saturating_sub(self.deadzone)
+            && self.center.saturating_add(self.deadzone) < self.max
+    }
+
+    /// Scale a raw sample to the report's 0-255 logical range
+    ///
+    /// Each side of the deadzone is stretched over its half of the report
+    /// range, so an off-center rest position still reaches both ends.
+    fn scale(&self, raw: u16) -> u8 {
+        let raw = raw.clamp(self.min, self.max);
+        let (offset, travel) = if raw > self.center {
+            (raw - self.center, self.max - self.center)
+        } else {
+            (self.center - raw, self.center - self.min)
+        };
+        if offset <= self.deadzone {
+            return AXIS_CENTER;
+        }
+
+        // How far past the deadzone the stick is, out of the rest of the way
+        let past = u32::from(offset - self.deadzone);
+        let span = u32::from(travel - self.deadzone);
+        let positive = (raw > self.center) != self.invert;
+        if positive {
+            let range = u32::from(u8::MAX - AXIS_CENTER);
+            AXIS_CENTER + (past * range / span) as u8
+        } else {
+            let range = u32::from(AXIS_CENTER);
+            AXIS_CENTER - (past * range / span) as u8
+        }
+    }
+}
+
+/// Levels at which an analog input read as a button changes state, in raw
+/// 12-bit ADC counts
+///
+/// The gap between them is the hysteresis: a reading has to rise to `on` to
+/// press the button and fall back to `off` to release it, so one hovering
+/// near either level can't make it chatter.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct AnalogThreshold {
+    pub on: u16,
+    pub off: u16,
+}
+
+impl AnalogThreshold {
+    /// Whether the release level is below the press level, and both are
+    /// readings the ADC can make
+    pub const fn is_valid(&self) -> bool {
+        self.off < self.on && self.on <= ADC_MAX
+    }
+}
+
+/// Analog input read as a button through an `AnalogThreshold`, a Schmitt
+/// trigger in software
+pub struct AnalogButton {
+    threshold: AnalogThreshold,
+    pressed: bool,
+}
+
+impl AnalogButton {
+    pub const fn new(threshold: AnalogThreshold) -> Self {
+        Self {
+            threshold,
+            pressed: false,
+        }
+    }
+
+    /// Feed a raw reading, and return whether the button is pressed
+    ///
+    /// A reading between the levels keeps the state it had.
+    pub const fn update(&mut self, raw: u16) -> bool {
+        if raw >= self.threshold.on {
+            self.pressed = true;
+        } else if raw <= self.threshold.off {
+            self.pressed = false;
+        }
+        self.pressed
+    }
+
+    pub const fn pressed(&self) -> bool {
+        self.pressed
+    }
+}
+
+/// Feed `trace` through an `AnalogButton` with `threshold`, and return its
+/// final state and how often it changed
+const fn run_analog_trace(threshold: AnalogThreshold, trace: &[u16]) -> (bool, u32) {
+    let mut button = AnalogButton::new(threshold);
+    let mut changes = 0;
+    let mut index = 0;
+    while index < trace.len() {
+        let was = button.pressed();
+        if button.update(trace[index]) != was {
+            changes += 1;
+        }
+        index += 1;
+    }
+    (button.pressed(), changes)
+}
+
+// A reading wandering around either level changes the state once, and one
+// that never gets past both levels doesn't change it at all, checked when
+// the crate is compiled
+const _: () = {
+    const THRESHOLD: AnalogThreshold = AnalogThreshold {
+        on: 3_000,
+        off: 2_600,
+    };
+    assert!(THRESHOLD.is_valid());
+
+    // Parked just under the press level, with ADC noise across it
+    const HOVER_ON: [u16; 10] = [
+        2_900, 2_980, 2_990, 3_010, 2_970, 3_020, 2_950, 3_005, 2_990, 2_960,
+    ];
+    let (pressed, changes) = run_analog_trace(THRESHOLD, &HOVER_ON);
+    assert!(pressed && changes == 1);
+
+    // Then parked around the release level
+    const HOVER_OFF: [u16; 10] = [
+        3_100, 2_700, 2_620, 2_590, 2_650, 2_580, 2_640, 2_610, 2_590, 2_630,
+    ];
+    let (pressed, changes) = run_analog_trace(THRESHOLD, &HOVER_OFF);
+    assert!(!pressed && changes == 2);
+
+    // Noise between the levels, after a press, never releases
+    const BETWEEN: [u16; 6] = [3_000, 2_700, 2_900, 2_650, 2_950, 2_700];
+    let (pressed, changes) = run_analog_trace(THRESHOLD, &BETWEEN);
+    assert!(pressed && changes == 1);
+
+    // Levels the trigger can't work with
+    assert!(!AnalogThreshold {
+        on: 2_000,
+        off: 2_000
+    }
+    .is_valid());
+    assert!(!AnalogThreshold { on: 4_096, off: 0 }.is_valid());
+};
+
+/// Calibration of the slider, whose travel is reported as a signed axis
+#[derive(Clone, Copy)]
+#[cfg(feature = "slider")]
+pub struct SliderConfig {
+    /// Readings at the ends of the slider's travel, reported as -127 and
+    /// 127. Readings past them are clamped.
+    pub min: u16,
+    pub max: u16,
+    /// Swap the ends, for a pot wired the other way round
+    pub invert: bool,
+    /// Levels at which the slider also reports `SLIDER_BUTTON`, on the raw
+    /// reading whichever way round it's wired; `None` for no button
+    pub button: Option<AnalogThreshold>,
+}
+
+#[cfg(feature = "slider")]
+impl SliderConfig {
+    /// Whether the travel is non-empty and fits in the ADC range, and so do
+    /// the button's levels
+    pub const fn is_valid(&self) -> bool {
+        let button_valid = match self.button {
+            Some(threshold) => threshold.is_valid(),
+            None => true,
+        };
+        self.min < self.max && self.max <= ADC_MAX && button_valid
+    }
+
+    /// Scale a raw sample linearly to the report's -127 to 127 logical range
+    fn scale(&self, raw: u16) -> i8 {
+        let past = u32::from(raw.clamp(self.min, self.max) - self.min);
+        let span = u32::from(self.max - self.min);
+        let value = (past * 254 / span) as i16 - 127;
+        if self.invert {
+            -value as i8
+        } else {
+            value as i8
+        }
+    }
+}
+
+/// What the encoder's rotation is reported as
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EncoderMode {
+    /// A press of the direction's button per increment, for a single report
+    /// each
+    Buttons,
+    /// Relative movement of the mouse interface's X and Y axes, by `(x, y)`
+    /// per clockwise increment and the opposite per counter-clockwise one
+    RelativeAxis { x: i8, y: i8 },
+}
+
+impl EncoderMode {
+    /// Whether rotation moves anything, as an axis mode with no movement
+    /// would silently drop it
+    pub const fn is_valid(&self) -> bool {
+        match *self {
+            Self::Buttons => true,
+            Self::RelativeAxis { x, y } => x != 0 || y != 0,
+        }
+    }
+}
+
+// An axis mode must move something
+const _: () = assert!(EncoderMode::Buttons.is_valid());
+const _: () = assert!(!EncoderMode::RelativeAxis { x: 0, y: 0 }.is_valid());
+
+/// What an encoder did since the last poll
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct EncoderEvent {
+    /// Direction of a detent that completed, if any
+    pub rotation: Option<Direction>,
+    /// Logical increments the detent counts as, 1 unless accelerated and 0
+    /// without a detent
+    pub increments: u8,
+    /// Debounced state of the push switch, `false` without one
+    pub pressed: bool,
+}
+
+/// Quadrature rotary encoder on two GPIO pins, with an optional push switch
+/// on a third
+pub struct Encoder {
+    pin_a: EncoderPin,
+    pin_b: EncoderPin,
+    switch: Option<EncoderPin>,
+    acceleration: &'static [Acceleration],
+    // Last sampled phase state, `A << 1 | B`
+    state: u8,
+    // Transitions accumulated towards the next detent
+    steps: i8,
+    // Direction and tick (ms) of the last detent, to time the next one
+    last_detent: Option<(Direction, u32)>,
+    // Last sampled and debounced switch states, and the tick (ms) the
+    // sampled one last changed
+    switch_raw: bool,
+    switch_pressed: bool,
+    switch_changed_ms: u32,
+}
+
+impl Encoder {
+    /// Decode the phases on `pin_a` and `pin_b`, and `switch` if the
+    /// encoder has a push switch
+    ///
+    /// `acceleration` is checked in order and the first step a detent is fast
+    /// enough for sets its multiplier, so list the shortest times first. An
+    /// empty curve turns acceleration off.
+    pub fn new(
+        pin_a: EncoderPin,
+        pin_b: EncoderPin,
+        switch: Option<EncoderPin>,
+        acceleration: &'static [Acceleration],
+    ) -> Self {
+        let mut encoder = Self {
+            pin_a,
+            pin_b,
+            switch,
+            acceleration,
+            state: 0,
+            steps: 0,
+            last_detent: None,
+            switch_raw: false,
+            switch_pressed: false,
+            switch_changed_ms: 0,
+        };
+        encoder.state = encoder.read_state();
+        encoder
+    }
+
+    fn read_state(&mut self) -> u8 {
+        // Phases are active low with pull-up resistors, like the buttons
+        let a = self.pin_a.is_low().unwrap_or(false) as u8;
+        let b = self.pin_b.is_low().unwrap_or(false) as u8;
+        (a << 1) | b
+    }
+
+    /// Sample the phases and the switch, reporting a direction once a full
+    /// detent has turned along with the switch state
+    ///
+    /// Must be called often enough to see every phase transition. The switch
+    /// is sampled and debounced on its own, so pressing it mid-turn never
+    /// disturbs the phase decoding.
+    pub fn poll(&mut self, now_ms: u32) -> EncoderEvent {
+        let rotation = self.poll_rotation();
+        let increments = match rotation {
+            Some(direction) => self.increments(direction, now_ms),
+            None => 0,
+        };
+        EncoderEvent {
+            rotation,
+            increments,
+            pressed: self.poll_switch(now_ms),
+        }
+    }
+
+    /// Logical increments for a detent in `direction` at `now_ms`, from the
+    /// time since the previous one
+    ///
+    /// Reversing always counts 1, so changing direction fast isn't amplified.
+    fn increments(&mut self, direction: Direction, now_ms: u32) -> u8 {
+        let previous = self.last_detent.replace((direction, now_ms));
+        let Some((last_direction, last_ms)) = previous else {
+            return 1;
+        };
+        if last_direction != direction {
+            return 1;
+        }
+
+        let interval = now_ms.wrapping_sub(last_ms);
+        self.acceleration
+            .iter()
+            .find(|step| interval <= step.within_ms)
+            .map_or(1, |step| step.multiplier.max(1))
+    }
+
+    fn poll_switch(&mut self, now_ms: u32) -> bool {
+        let Some(switch) = self.switch.as_mut() else {
+            return false;
+        };
+
+        // Active low with a pull-up, like the phases
+        let raw = switch.is_low().unwrap_or(false);
+        if raw != self.switch_raw {
+            self.switch_raw = raw;
+            self.switch_changed_ms = now_ms;
+        }
+        if now_ms.wrapping_sub(self.switch_changed_ms) >= SWITCH_DEBOUNCE_MS {
+            self.switch_pressed = raw;
+        }
+        self.switch_pressed
+    }
+
+    fn poll_rotation(&mut self) -> Option<Direction> {
+        let state = self.read_state();
+        let transition = ((self.state << 2) | state) as usize;
+        self.state = state;
+
+        // Half steps that reverse cancel out here rather than emitting a tick
+        self.steps += TRANSITIONS[transition];
+
+        if self.steps >= STEPS_PER_DETENT {
+            self.steps = 0;
+            Some(Direction::Clockwise)
+        } else if self.steps <= -STEPS_PER_DETENT {
+            self.steps = 0;
+            Some(Direction::CounterClockwise)
+        } else {
+            None
+        }
+    }
+}
+
+/// Two-axis analog thumbstick on a pair of ADC channels
+pub struct Joystick {
+    x: XAxisPin,
+    y: YAxisPin,
+    x_config: AxisConfig,
+    y_config: AxisConfig,
+}
+
+impl Joystick {
+    pub fn new(x: XAxisPin, y: YAxisPin, x_config: AxisConfig, y_config: AxisConfig) -> Self {
+        Self {
+            x,
+            y,
+            x_config,
+            y_config,
+        }
+    }
+
+    /// Sample both axes, calibrated and scaled to the report's 0-255 logical
+    /// range
+    ///
+    /// A failed conversion reads as centered rather than as a full deflection.
+    pub fn read(&mut self, adc: &mut Adc) -> (u8, u8) {
+        let x: Option<u16> = adc.read(&mut self.x).ok();
+        let y: Option<u16> = adc.read(&mut self.y).ok();
+        (
+            x.map_or(AXIS_CENTER, |x| self.x_config.scale(x)),
+            y.map_or(AXIS_CENTER, |y| self.y_config.scale(y)),
+        )
+    }
+}
+
+/// Slide pot or throttle lever on an ADC channel
+#[cfg(feature = "slider")]
+pub struct Slider {
+    pin: SliderPin,
+    config: SliderConfig,
+    button: Option<AnalogButton>,
+}
+
+#[cfg(feature = "slider")]
+impl Slider {
+    pub fn new(pin: SliderPin, config: SliderConfig) -> Self {
+        Self {
+            pin,
+            config,
+            button: config.button.map(AnalogButton::new),
+        }
+    }
+
+    /// Sample the slider, calibrated and scaled to the report's -127 to 127
+    /// logical range, and update its button from the same reading
+    ///
+    /// A failed conversion reads as the middle of the travel, and leaves the
+    /// button as it was.
+    pub fn read(&mut self, adc: &mut Adc) -> i8 {
+        let raw: Option<u16> = adc.read(&mut self.pin).ok();
+        if let (Some(raw), Some(button)) = (raw, self.button.as_mut()) {
+            button.update(raw);
+        }
+        raw.map_or(0, |raw| self.config.scale(raw))
+    }
+
+    /// Whether the slider's button is pressed, `false` without one
+    pub fn pressed(&self) -> bool {
+        self.button.as_ref().is_some_and(AnalogButton::pressed)
+    }
+}
+
+/// Four-way directional switch reported as a HID hat
+pub struct Hat {
+    up: HatPin,
+    right: HatPin,
+    down: HatPin,
+    left: HatPin,
+}
+
+impl Hat {
+    pub fn new(up: HatPin, right: HatPin, down: HatPin, left: HatPin) -> Self {
+        Self {
+            up,
+            right,
+            down,
+            left,
+        }
+    }
+
+    /// Read the switch as a hat value: 0 for north, counting clockwise in
+    /// 45 degree steps to 7 for north-west, or `HAT_CENTERED`
+    ///
+    /// Opposite directions pressed together cancel out, so an impossible
+    /// combination resolves to the nearest direction that can be reported,
+    /// e.g. up + down + right reads as right.
+    pub fn read(&mut self) -> u8 {
+        // Directions are active low with pull-up resistors, like the buttons
+        let up = self.up.is_low().unwrap_or(false) as i8;
+        let right = self.right.is_low().unwrap_or(false) as i8;
+        let down = self.down.is_low().unwrap_or(false) as i8;
+        let left = self.left.is_low().unwrap_or(false) as i8;
+
+        match (up - down, right - left) {
+            (1, 0) => 0,
+            (1, 1) => 1,
+            (0, 1) => 2,
+            (-1, 1) => 3,
+            (-1, 0) => 4,
+            (-1, -1) => 5,
+            (0, -1) => 6,
+            (1, -1) => 7,
+            _ => HAT_CENTERED,
+        }
+    }
+}
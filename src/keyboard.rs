@@ -0,0 +1,222 @@
+//! Keyboard HID mode, enabled with the `keyboard` feature
+//!
+//! Instead of a gamepad, the box enumerates as a keyboard and each pressed
+//! logical button sends the keycode assigned to it in `KEYMAP`. By default the
+//! report is a bitmap with a bit per key (N-key rollover), so every button can
+//! be held at once. The `keyboard-6kro` feature switches to the standard
+//! boot-compatible report instead, which holds up to six keys (6-key
+//! rollover) but works with BIOSes that only understand that format.
+//!
+//! Either way the interface declares itself a boot keyboard, and while the
+//! host has it in boot protocol (see `boot_protocol`) the N-key rollover
+//! report is sent in the boot format instead.
+
+pub use usbd_hid::descriptor::KeyboardReport;
+use usbd_hid::descriptor::KeyboardUsage;
+#[cfg(not(feature = "keyboard-6kro"))]
+use usbd_hid::descriptor::{generator_prelude::*, AsInputReport, SerializedDescriptor};
+
+/// Keyboard report sent by the box, N-key rollover or boot-compatible
+#[cfg(not(feature = "keyboard-6kro"))]
+pub type Report = NkroReport;
+/// Keyboard report sent by the box, N-key rollover or boot-compatible
+#[cfg(feature = "keyboard-6kro")]
+pub type Report = KeyboardReport;
+
+/// Keys covered by the N-key rollover bitmap, usages 0x00 to 0x7F
+///
+/// That's every key of a full-size keyboard up to F24; keycodes past it (other
+/// than the modifiers) can't be sent in N-key rollover mode.
+#[cfg(not(feature = "keyboard-6kro"))]
+const NKRO_KEYS: usize = 128;
+
+/// First and last modifier usages (Left Control to Right GUI), which are sent
+/// as the bits of the modifier byte rather than as keys
+#[cfg(not(feature = "keyboard-6kro"))]
+const MODIFIER_FIRST: u8 = 0xE0;
+#[cfg(not(feature = "keyboard-6kro"))]
+const MODIFIER_LAST: u8 = 0xE7;
+
+/// HID report descriptor of the N-key rollover keyboard
+///
+/// Written by hand since `gen_hid_descriptor` can't describe a bitmap of
+/// keys: one byte of modifier bits, one bit per key from usage 0x00 to 0x7F,
+/// and the usual five lock LEDs as output.
+#[cfg(not(feature = "keyboard-6kro"))]
+pub const NKRO_REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x06, // Usage (Keyboard)
+    0xA1, 0x01, // Collection (Application)
+    0x05, 0x07, //   Usage Page (Keyboard)
+    0x19, 0xE0, //   Usage Minimum (Left Control)
+    0x29, 0xE7, //   Usage Maximum (Right GUI)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x01, //   Logical Maximum (1)
+    0x75, 0x01, //   Report Size (1 bit)
+    0x95, 0x08, //   Report Count (8)
+    0x81, 0x02, //   Input (Data, Variable, Absolute)
+    0x19, 0x00, //   Usage Minimum (0x00)
+    0x29, 0x7F, //   Usage Maximum (0x7F)
+    0x95, 0x80, //   Report Count (128)
+    0x81, 0x02, //   Input (Data, Variable, Absolute), one bit per key
+    0x05, 0x08, //   Usage Page (LEDs)
+    0x19, 0x01, //   Usage Minimum (Num Lock)
+    0x29, 0x05, //   Usage Maximum (Kana)
+    0x95, 0x05, //   Report Count (5)
+    0x91, 0x02, //   Output (Data, Variable, Absolute)
+    0x75, 0x03, //   Report Size (3 bits)
+    0x95, 0x01, //   Report Count (1)
+    0x91, 0x01, //   Output (Constant), padding to a byte
+    0xC0, // End Collection
+];
+
+/// N-key rollover keyboard report: modifier bits, then a bit per key
+#[cfg(not(feature = "keyboard-6kro"))]
+#[derive(Clone, Copy, Default)]
+pub struct NkroReport {
+    pub modifier: u8,
+    // Bit `usage % 8` of byte `usage / 8` is set while that key is held
+    pub keys: [u8; NKRO_KEYS / 8],
+}
+
+#[cfg(not(feature = "keyboard-6kro"))]
+impl NkroReport {
+    /// Hold `key`, as a modifier bit or a bit of the key bitmap
+    ///
+    /// Keys past the bitmap are ignored.
+    fn press(&mut self, key: KeyboardUsage) {
+        let code = key as u8;
+        if (MODIFIER_FIRST..=MODIFIER_LAST).contains(&code) {
+            self.modifier |= 1 << (code - MODIFIER_FIRST);
+        } else if let Some(byte) = self.keys.get_mut(usize::from(code) / 8) {
+            *byte |= 1 << (code % 8);
+        }
+    }
+
+    /// The same keys as a boot protocol report
+    ///
+    /// With more than six keys held, every keycode slot reads
+    /// `ErrorRollOver` instead.
+    pub fn boot_report(&self) -> KeyboardReport {
+        let mut report = KeyboardReport::default();
+        report.modifier = self.modifier;
+        let pressed = (0..NKRO_KEYS)
+            .filter(|&code| self.keys[code / 8] & (1 << (code % 8)) != 0)
+            .map(|code| code as u8);
+
+        report.keycodes = button_box_firmware::boot_keycodes(pressed);
+        report
+    }
+}
+
+#[cfg(not(feature = "keyboard-6kro"))]
+impl SerializedDescriptor for NkroReport {
+    fn desc() -> &'static [u8] {
+        NKRO_REPORT_DESCRIPTOR
+    }
+}
+
+#[cfg(not(feature = "keyboard-6kro"))]
+impl Serialize for NkroReport {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut report = serializer.serialize_tuple(1 + self.keys.len())?;
+        report.serialize_element(&self.modifier)?;
+        for byte in &self.keys {
+            report.serialize_element(byte)?;
+        }
+        report.end()
+    }
+}
+
+#[cfg(not(feature = "keyboard-6kro"))]
+impl AsInputReport for NkroReport {}
+
+/// Keycode sent by each logical button, indexed by report bit
+///
+/// Buttons 1-26 type the letters `a` to `z` by default and buttons 27-32 the
+/// digits `1` to `6`, which makes them easy to check in any text editor.
+pub const KEYMAP: [KeyboardUsage; 32] = [
+    KeyboardUsage::KeyboardAa,
+    KeyboardUsage::KeyboardBb,
+    KeyboardUsage::KeyboardCc,
+    KeyboardUsage::KeyboardDd,
+    KeyboardUsage::KeyboardEe,
+    KeyboardUsage::KeyboardFf,
+    KeyboardUsage::KeyboardGg,
+    KeyboardUsage::KeyboardHh,
+    KeyboardUsage::KeyboardIi,
+    KeyboardUsage::KeyboardJj,
+    KeyboardUsage::KeyboardKk,
+    KeyboardUsage::KeyboardLl,
+    KeyboardUsage::KeyboardMm,
+    KeyboardUsage::KeyboardNn,
+    KeyboardUsage::KeyboardOo,
+    KeyboardUsage::KeyboardPp,
+    KeyboardUsage::KeyboardQq,
+    KeyboardUsage::KeyboardRr,
+    KeyboardUsage::KeyboardSs,
+    KeyboardUsage::KeyboardTt,
+    KeyboardUsage::KeyboardUu,
+    KeyboardUsage::KeyboardVv,
+    KeyboardUsage::KeyboardWw,
+    KeyboardUsage::KeyboardXx,
+    KeyboardUsage::KeyboardYy,
+    KeyboardUsage::KeyboardZz,
+    KeyboardUsage::Keyboard1Exclamation,
+    KeyboardUsage::Keyboard2At,
+    KeyboardUsage::Keyboard3Hash,
+    KeyboardUsage::Keyboard4Dollar,
+    KeyboardUsage::Keyboard5Percent,
+    KeyboardUsage::Keyboard6Caret,
+];
+
+/// Build a keyboard report pressing only `key`, or no key at all
+#[cfg(not(feature = "keyboard-6kro"))]
+pub fn key_report(key: Option<KeyboardUsage>) -> Report {
+    let mut report = NkroReport::default();
+    if let Some(key) = key {
+        report.press(key);
+    }
+    report
+}
+
+/// Build a keyboard report pressing the key of every set bit in `buttons`
+#[cfg(not(feature = "keyboard-6kro"))]
+pub fn keyboard_report(buttons: u32) -> Report {
+    let mut report = NkroReport::default();
+    for (_, &key) in KEYMAP
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| buttons & (1 << index) != 0)
+    {
+        report.press(key);
+    }
+    report
+}
+
+/// Build a keyboard report pressing only `key`, or no key at all
+#[cfg(feature = "keyboard-6kro")]
+pub fn key_report(key: Option<KeyboardUsage>) -> Report {
+    let mut report = KeyboardReport::default();
+    if let Some(key) = key {
+        report.keycodes[0] = key as u8;
+    }
+    report
+}
+
+/// Build a keyboard report pressing the key of every set bit in `buttons`
+///
+/// With more than six keys held, every keycode slot reads `ErrorRollOver`
+/// instead.
+#[cfg(feature = "keyboard-6kro")]
+pub fn keyboard_report(buttons: u32) -> Report {
+    let mut report = KeyboardReport::default();
+    let pressed = KEYMAP
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| buttons & (1 << index) != 0)
+        .map(|(_, &key)| key as u8);
+
+    report.keycodes = button_box_firmware::boot_keycodes(pressed);
+    report
+}
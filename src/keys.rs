@@ -0,0 +1,60 @@
+//! Keyboard keys reported next to the gamepad
+//!
+//! Logical buttons routed to the keyboard are reported through a keyboard
+//! HID interface of their own instead of the gamepad, so some buttons can
+//! type keys while the rest stay gamepad buttons. The buttons in `KEY_MAP`
+//! are routed there by default, and the host can route any other button
+//! there (or back) at runtime through the feature report. The `keyboard`
+//! feature instead turns the whole box into a keyboard, and leaves this
+//! interface out.
+
+pub use usbd_hid::descriptor::KeyboardReport;
+use usbd_hid::descriptor::KeyboardUsage;
+
+/// Logical buttons sent as keyboard keys by default, with the key each one
+/// sends
+///
+/// Button 11 isn't wired by default; map an input onto it (e.g. with the
+/// console's `set` command) to use it.
+pub const KEY_MAP: [(usize, KeyboardUsage); 1] =
+    [(crate::BUTTON_COUNT + 8, KeyboardUsage::KeyboardEscape)];
+
+/// Usages of the keys a button routed to the keyboard at runtime sends when
+/// `KEY_MAP` has no key for it: buttons 1-26 type `a` to `z`, and 27-32 the
+/// digits `1` to `6`
+const LETTER_A: u8 = KeyboardUsage::KeyboardAa as u8;
+const DIGIT_1: u8 = KeyboardUsage::Keyboard1Exclamation as u8;
+const LETTERS: usize = 26;
+
+/// Report bits of every button in `KEY_MAP`, the buttons routed to the
+/// keyboard by default
+pub const KEY_BUTTONS: u32 = button_box_firmware::mapped_buttons(&KEY_MAP);
+
+/// Key sent by logical `button` while it's routed to the keyboard
+fn key_of(button: usize) -> u8 {
+    match KEY_MAP.iter().find(|&&(mapped, _)| mapped == button) {
+        Some(&(_, key)) => key as u8,
+        None if button < LETTERS => LETTER_A + button as u8,
+        None => DIGIT_1 + (button - LETTERS) as u8,
+    }
+}
+
+/// Build a keyboard report holding the key of every button set in `buttons`
+///
+/// With more than six keys held, every keycode slot reads `ErrorRollOver`
+/// instead.
+pub fn key_map_report(buttons: u32) -> KeyboardReport {
+    let mut report = KeyboardReport::default();
+    let pressed = (0..crate::REPORT_BUTTONS)
+        .filter(|button| buttons & (1 << button) != 0)
+        .map(key_of);
+
+    report.keycodes = button_box_firmware::boot_keycodes(pressed);
+    report
+}
+
+// The letters and digits are consecutive usages, ending at the digit 6
+const _: () = assert!(KeyboardUsage::KeyboardZz as u8 == LETTER_A + LETTERS as u8 - 1);
+const _: () = assert!(
+    KeyboardUsage::Keyboard6Caret as u8 == DIGIT_1 + (crate::REPORT_BUTTONS - LETTERS) as u8 - 1
+);
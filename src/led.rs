@@ -0,0 +1,398 @@
+//! Indicator LEDs driven by the firmware or the host
+
+use embedded_hal::{
+    digital::{OutputPin, PinState},
+    pwm::SetDutyCycle,
+};
+
+use crate::bsp::hal::{
+    gpio::{DynPinId, FunctionSio, Pin, PullDown, SioOutput},
+    pwm::{Channel, FreeRunning, Pwm4, Slice, B},
+};
+
+/// GPIO pin type for LED outputs, erased so LEDs on any pin fit in one array
+pub type LedPin = Pin<DynPinId, FunctionSio<SioOutput>, PullDown>;
+
+/// Time (ms) the status LED spends on, then off, while the host hasn't
+/// configured the device yet
+const STATUS_BLINK_MS: u32 = 125;
+/// Time (ms) the status LED spends on, then off, while signalling a fault
+const FAULT_BLINK_MS: u32 = 40;
+/// Time (ms) of one full breath, dark to bright and back
+const BREATH_PERIOD_MS: u32 = 3_000;
+/// Brightness cap of the status LED while the box is idle
+const IDLE_BRIGHTNESS: u8 = 32;
+/// Time (ms) the status LED plays the identify pattern for
+const IDENTIFY_MS: u32 = 5_000;
+/// Period (ms) of the identify pattern: three short flashes, then a pause
+const IDENTIFY_PERIOD_MS: u32 = 1_000;
+/// Time (ms) of each flash of the identify pattern, and of the gap after it
+const IDENTIFY_FLASH_MS: u32 = 80;
+/// Time (ms) of a dot of the safe mode's SOS, and of the gap after each
+/// dot or dash
+const SOS_UNIT_MS: u32 = 150;
+/// Length of each on and off period of the SOS, in `SOS_UNIT_MS`: three
+/// dots, three dashes, three dots, with a longer gap between letters and
+/// before the pattern repeats
+const SOS: [(u32, u32); 9] = [
+    (1, 1),
+    (1, 1),
+    (1, 3),
+    (3, 1),
+    (3, 1),
+    (3, 3),
+    (1, 1),
+    (1, 1),
+    (1, 7),
+];
+/// Length of the whole SOS, in `SOS_UNIT_MS`
+const SOS_UNITS: u32 = {
+    let mut units = 0;
+    let mut index = 0;
+    while index < SOS.len() {
+        units += SOS[index].0 + SOS[index].1;
+        index += 1;
+    }
+    units
+};
+/// Time (ms) the activity LED stays lit after a report goes out
+#[cfg(not(feature = "slider"))]
+const ACTIVITY_BLINK_MS: u32 = 20;
+/// Time (ms) the activity LED spends on, then off, per blink of a count
+#[cfg(not(feature = "slider"))]
+const COUNT_BLINK_MS: u32 = 150;
+
+/// PWM channel of the onboard LED (GP25), channel B of the slice the rumble
+/// motor runs
+pub type StatusPwm = Channel<Slice<Pwm4, FreeRunning>, B>;
+
+/// Bank of LEDs set from the bits of a HID output report
+///
+/// Bit 0 of the report drives the first LED, bit 1 the second, and so on.
+pub struct LedBank<const N: usize> {
+    leds: [LedPin; N],
+}
+
+impl<const N: usize> LedBank<N> {
+    pub fn new(leds: [LedPin; N]) -> Self {
+        let mut bank = Self { leds };
+        bank.set(0);
+        bank
+    }
+
+    /// Light each LED whose bit is set in `bits`, and turn the rest off
+    pub fn set(&mut self, bits: u8) {
+        for (index, led) in self.leds.iter_mut().enumerate() {
+            let state = PinState::from(bits & (1 << index) != 0);
+            led.set_state(state).ok();
+        }
+    }
+}
+
+/// Indicator LEDs, each lit while the logical button it follows is pressed
+///
+/// Every LED is paired with a report bit, and the pins are erased to
+/// `LedPin`s, so any pin can follow any button and the pairing is plain data
+/// rather than part of the type. Only LEDs whose state changes are written.
+pub struct LedMap<const N: usize> {
+    leds: [(LedPin, usize); N],
+    lit: [bool; N],
+}
+
+impl<const N: usize> LedMap<N> {
+    pub fn new(mut leds: [(LedPin, usize); N]) -> Self {
+        for (led, _) in leds.iter_mut() {
+            led.set_low().ok();
+        }
+        Self {
+            leds,
+            lit: [false; N],
+        }
+    }
+
+    /// Light the LED of every button set in `buttons`, and turn the rest off
+    pub fn update(&mut self, buttons: u32) {
+        for ((led, button), lit) in self.leds.iter_mut().zip(self.lit.iter_mut()) {
+            let pressed = buttons & (1 << *button) != 0;
+            if pressed != *lit {
+                led.set_state(PinState::from(pressed)).ok();
+                *lit = pressed;
+            }
+        }
+    }
+}
+
+/// LED flashing briefly whenever a HID report is sent to the host
+///
+/// Reports sent in quick succession keep it lit, so a held stream of reports
+/// reads as a steady glow and single reports as short blinks. It can also
+/// blink out a count, e.g. the profile number, which reports don't disturb.
+#[cfg(not(feature = "slider"))]
+pub struct ActivityLed {
+    led: LedPin,
+    // Tick (ms) of the last report, while the LED is lit
+    lit_since: Option<u32>,
+    // Tick (ms) at which the count being blinked out started, and the count
+    counting: Option<(u32, u8)>,
+}
+
+#[cfg(not(feature = "slider"))]
+impl ActivityLed {
+    pub fn new(mut led: LedPin) -> Self {
+        led.set_low().ok();
+        Self {
+            led,
+            lit_since: None,
+            counting: None,
+        }
+    }
+
+    /// Light the LED for `ACTIVITY_BLINK_MS` from `now_ms`, unless it is
+    /// blinking out a count
+    pub fn blink(&mut self, now_ms: u32) {
+        if self.counting.is_none() {
+            self.led.set_high().ok();
+            self.lit_since = Some(now_ms);
+        }
+    }
+
+    /// Blink `count` times from `now_ms`, `COUNT_BLINK_MS` on and off each,
+    /// cutting short any count already being blinked
+    pub fn signal(&mut self, count: u8, now_ms: u32) {
+        self.lit_since = None;
+        self.counting = Some((now_ms, count));
+    }
+
+    /// Step the count being blinked, or turn the LED off once a blink has
+    /// run its course
+    pub fn update(&mut self, now_ms: u32) {
+        if let Some((since, count)) = self.counting {
+            let phase = now_ms.wrapping_sub(since) / COUNT_BLINK_MS;
+            let done = phase >= 2 * u32::from(count);
+            self.led
+                .set_state(PinState::from(!done && phase & 1 == 0))
+                .ok();
+            if done {
+                self.counting = None;
+            }
+        } else if let Some(since) = self.lit_since {
+            if now_ms.wrapping_sub(since) >= ACTIVITY_BLINK_MS {
+                self.led.set_low().ok();
+                self.lit_since = None;
+            }
+        }
+    }
+}
+
+/// LED dimmed by a PWM channel, which can breathe slowly in and out
+pub struct BreathingLed {
+    pwm: StatusPwm,
+    // Brightness every level is scaled to, full by default
+    max_brightness: u8,
+}
+
+impl BreathingLed {
+    /// Take over `pwm`, already routed to the LED pin and running
+    pub fn new(pwm: StatusPwm) -> Self {
+        let mut led = Self {
+            pwm,
+            max_brightness: u8::MAX,
+        };
+        led.set(false);
+        led
+    }
+
+    /// Scale all later levels down to at most `max_brightness`
+    pub fn set_max_brightness(&mut self, max_brightness: u8) {
+        self.max_brightness = max_brightness;
+    }
+
+    /// Fully on or off
+    pub fn set(&mut self, on: bool) {
+        self.set_brightness(if on { u8::MAX } else { 0 });
+    }
+
+    /// Brightness of the breath at `now_ms`
+    ///
+    /// A triangle ramp over `BREATH_PERIOD_MS`, squared so it lingers near
+    /// dark and swells like a sine instead of looking linear to the eye.
+    pub fn breathe(&mut self, now_ms: u32) {
+        let half = BREATH_PERIOD_MS / 2;
+        let phase = now_ms % BREATH_PERIOD_MS;
+        let ramp = if phase < half {
+            phase
+        } else {
+            BREATH_PERIOD_MS - phase
+        };
+        let level = ramp * u8::MAX as u32 / half;
+        self.set_brightness((level * level / u8::MAX as u32) as u8);
+    }
+
+    fn set_brightness(&mut self, brightness: u8) {
+        let scaled = brightness as u16 * self.max_brightness as u16 / u8::MAX as u16;
+        self.pwm
+            .set_duty_cycle_fraction(scaled, u8::MAX as u16)
+            .ok();
+    }
+}
+
+/// Identify pattern a host can ask for, to find which of several boxes is
+/// which
+///
+/// For `IDENTIFY_MS` it flashes three times a second, a rhythm none of the
+/// status LED's own patterns use. Asking again while it plays starts it over.
+pub struct Identify {
+    // Tick (ms) at which the pattern started, while it plays
+    since: Option<u32>,
+}
+
+impl Identify {
+    pub const fn new() -> Self {
+        Self { since: None }
+    }
+
+    /// Start the pattern at `now_ms`
+    pub fn start(&mut self, now_ms: u32) {
+        self.since = Some(now_ms);
+    }
+
+    /// Whether the LED is lit at `now_ms`, or `None` once the pattern is over
+    pub fn poll(&mut self, now_ms: u32) -> Option<bool> {
+        let elapsed = now_ms.wrapping_sub(self.since?);
+        if elapsed >= IDENTIFY_MS {
+            self.since = None;
+            return None;
+        }
+        let phase = elapsed % IDENTIFY_PERIOD_MS / IDENTIFY_FLASH_MS;
+        Some(phase < 6 && phase & 1 == 0)
+    }
+}
+
+/// Whether the SOS pattern has the LED lit at `now_ms`
+const fn sos_lit(now_ms: u32) -> bool {
+    let mut unit = now_ms / SOS_UNIT_MS % SOS_UNITS;
+    let mut index = 0;
+    while index < SOS.len() {
+        let (on, off) = SOS[index];
+        if unit < on {
+            return true;
+        }
+        if unit < on + off {
+            return false;
+        }
+        unit -= on + off;
+        index += 1;
+    }
+    false
+}
+
+// The pattern spells SOS in standard Morse timing, checked when the crate is
+// compiled
+const _: () = {
+    assert!(SOS_UNITS == 34);
+    // A dot, a gap, then the first dash after the letter gap
+    assert!(sos_lit(0) && !sos_lit(SOS_UNIT_MS));
+    assert!(!sos_lit(7 * SOS_UNIT_MS) && sos_lit(8 * SOS_UNIT_MS));
+    assert!(sos_lit(10 * SOS_UNIT_MS) && !sos_lit(11 * SOS_UNIT_MS));
+    // Dark through the word gap, then round again
+    assert!(!sos_lit(33 * SOS_UNIT_MS) && sos_lit(34 * SOS_UNIT_MS));
+};
+
+/// Onboard LED (GP25) showing whether the device has been enumerated
+///
+/// It blinks from power-up until the host configures the device. Once
+/// configured it breathes while the box is idle and lights solid while any
+/// button is held. A box that is powered but keeps blinking never got past
+/// enumeration. A hardware fault overrides all of these with a much faster
+/// flicker, and the LED is off while the host has suspended the bus. After a
+/// long time without input it keeps its pattern but dims. The identify
+/// pattern, while the host has asked for it, takes precedence over all of
+/// them, and the safe mode's SOS over everything.
+pub struct StatusLed {
+    led: BreathingLed,
+    identify: Identify,
+    safe_mode: bool,
+    fault: bool,
+    active: bool,
+    suspended: bool,
+    idle: bool,
+}
+
+impl StatusLed {
+    pub fn new(led: BreathingLed) -> Self {
+        let mut status = Self {
+            led,
+            identify: Identify::new(),
+            safe_mode: false,
+            fault: false,
+            active: false,
+            suspended: false,
+            idle: false,
+        };
+        status.update(false, 0);
+        status
+    }
+
+    /// Whether the LED is dimmed for an idle box
+    pub fn idle(&self) -> bool {
+        self.idle
+    }
+
+    /// Whether a fault is being signalled
+    pub fn fault(&self) -> bool {
+        self.fault
+    }
+
+    /// Signal a fault, or stop signalling it, from the next `update()`
+    pub fn set_fault(&mut self, fault: bool) {
+        self.fault = fault;
+    }
+
+    /// Go solid while a button is held, instead of breathing
+    pub fn set_active(&mut self, active: bool) {
+        self.active = active;
+    }
+
+    /// Dim while the box is idle, or back to full brightness
+    pub fn set_idle(&mut self, idle: bool) {
+        self.idle = idle;
+        let brightness = if idle { IDLE_BRIGHTNESS } else { u8::MAX };
+        self.led.set_max_brightness(brightness);
+    }
+
+    /// Blink SOS from the next `update()` on, for good
+    pub fn set_safe_mode(&mut self) {
+        self.safe_mode = true;
+        self.led.set_max_brightness(u8::MAX);
+    }
+
+    /// Play the identify pattern from `now_ms`
+    pub fn identify(&mut self, now_ms: u32) {
+        self.identify.start(now_ms);
+    }
+
+    /// Go dark while the bus is suspended, pausing the breath
+    pub fn set_suspended(&mut self, suspended: bool) {
+        self.suspended = suspended;
+    }
+
+    /// Breathing (or solid while active) if `configured`, otherwise the
+    /// blink phase at `now_ms`, or the fault flicker while a fault is set
+    pub fn update(&mut self, configured: bool, now_ms: u32) {
+        if self.safe_mode {
+            self.led.set(sos_lit(now_ms));
+        } else if let Some(lit) = self.identify.poll(now_ms) {
+            self.led.set(lit);
+        } else if self.fault {
+            self.led.set((now_ms / FAULT_BLINK_MS) & 1 == 0);
+        } else if self.suspended {
+            self.led.set(false);
+        } else if !configured {
+            self.led.set((now_ms / STATUS_BLINK_MS) & 1 == 0);
+        } else if self.active {
+            self.led.set(true);
+        } else {
+            self.led.breathe(now_ms);
+        }
+    }
+}
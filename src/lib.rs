@@ -0,0 +1,90 @@
+//! Report format and button handling of the button box, shared by the
+//! firmware and host tools
+//!
+//! The firmware binary takes its HID descriptor and its button state machine
+//! from here. Built with the `host` feature, the library links `std` and has
+//! none of the firmware's hardware dependencies, so host-side tools can
+//! decode the box's reports with the same code:
+//!
+//! ```sh
+//! cargo build --lib --features host --target x86_64-unknown-linux-gnu
+//! ```
+//!
+//! Its tests run on the host the same way:
+//!
+//! ```sh
+//! cargo test --lib --features host --target x86_64-unknown-linux-gnu
+//! ```
+#![cfg_attr(not(feature = "host"), no_std)]
+
+pub mod button_box;
+pub mod button_config;
+pub mod combo;
+pub mod debounce;
+pub mod hid_descriptor;
+pub mod macro_recorder;
+pub mod report_queue;
+pub mod stored_config;
+
+/// Buttons the report carries, one per bit of its `u32` button field
+pub const REPORT_BUTTONS: usize = u32::BITS as usize;
+
+/// Report bits of the logical buttons (0-based report bits) a button map
+/// lists, the first of each of its entries
+///
+/// The maps' buttons are numbered after the wired ones, so they move up as
+/// inputs are added; one past the report fails the build when used in a
+/// `const`.
+pub const fn mapped_buttons<T>(map: &[(usize, T)]) -> u32 {
+    let mut mask = 0;
+    let mut index = 0;
+    while index < map.len() {
+        assert!(
+            map[index].0 < REPORT_BUTTONS,
+            "a mapped button is past the 32 buttons of the report"
+        );
+        mask |= 1 << map[index].0;
+        index += 1;
+    }
+    mask
+}
+
+/// Keycode slots of a boot keyboard report
+pub const BOOT_KEYS: usize = 6;
+/// Keycode filling every slot of a boot keyboard report when more keys are
+/// held than it has slots for (`ErrorRollOver`)
+pub const ERROR_ROLL_OVER: u8 = 0x01;
+
+/// Keycode slots of a boot keyboard report holding the `pressed` keys
+///
+/// With more keys held than there are slots, every slot reads
+/// `ERROR_ROLL_OVER` instead, as the HID boot keyboard spec requires, so the
+/// host knows keys are missing rather than seeing some of them released.
+pub fn boot_keycodes(pressed: impl Iterator<Item = u8>) -> [u8; BOOT_KEYS] {
+    let mut slots = [0; BOOT_KEYS];
+    for (index, key) in pressed.enumerate() {
+        match slots.get_mut(index) {
+            Some(slot) => *slot = key,
+            None => return [ERROR_ROLL_OVER; BOOT_KEYS],
+        }
+    }
+    slots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boot_keycodes_fill_six_slots_then_roll_over() {
+        assert_eq!(
+            boot_keycodes([0x04, 0x05].into_iter()),
+            [0x04, 0x05, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            boot_keycodes(0x04..0x0A),
+            [0x04, 0x05, 0x06, 0x07, 0x08, 0x09]
+        );
+        assert_eq!(boot_keycodes(0x04..0x0B), [ERROR_ROLL_OVER; BOOT_KEYS]);
+    }
+}
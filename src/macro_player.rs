@@ -0,0 +1,157 @@
+//! Keystroke macros played back by a button, in keyboard mode
+//!
+//! Pressing a logical button listed in `MACROS` types its macro: each step
+//! holds one key for its duration, with every key released for `GAP_MS`
+//! between steps so repeated letters register as separate presses. While a
+//! macro plays, its keys replace the normal button keys in the report.
+
+use usbd_hid::descriptor::KeyboardUsage;
+
+/// Time (ms) all keys are released between two steps of a macro
+const GAP_MS: u32 = 10;
+
+/// One keystroke of a macro
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct MacroStep {
+    /// Key held down for the step, or `None` to only wait
+    pub key: Option<KeyboardUsage>,
+    /// Time (ms) the key is held
+    pub duration_ms: u32,
+}
+
+/// A sequence of keystrokes typed by one button press
+pub type Macro = &'static [MacroStep];
+
+/// What to do when a macro button is pressed while a macro is playing
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum MacroPolicy {
+    /// Drop the new press
+    Ignore,
+    /// Play the new macro once the current one ends; only one can wait, so
+    /// further presses are dropped
+    Queue,
+}
+
+/// Policy used by the player
+pub const MACRO_POLICY: MacroPolicy = MacroPolicy::Ignore;
+
+/// A step holding `key` for `duration_ms`
+const fn step(key: KeyboardUsage, duration_ms: u32) -> MacroStep {
+    MacroStep {
+        key: Some(key),
+        duration_ms,
+    }
+}
+
+/// A step holding no key for `duration_ms`
+const fn pause(duration_ms: u32) -> MacroStep {
+    MacroStep {
+        key: None,
+        duration_ms,
+    }
+}
+
+/// Types "hi", waits a moment, then presses Enter
+const GREETING: Macro = &[
+    step(KeyboardUsage::KeyboardHh, 20),
+    step(KeyboardUsage::KeyboardIi, 20),
+    pause(200),
+    step(KeyboardUsage::KeyboardEnter, 20),
+];
+
+/// Logical buttons that play a macro, with the macro each one plays
+///
+/// Button 10 isn't wired by default; map an input onto it (e.g. with the
+/// console's `set` command) to use it.
+pub const MACROS: [(usize, Macro); 1] = [(crate::BUTTON_COUNT + 7, GREETING)];
+
+/// Report bits of every button in `MACROS`
+pub const MACRO_BUTTONS: u32 = button_box_firmware::mapped_buttons(&MACROS);
+
+/// Position within the macro being played
+#[derive(Clone, Copy)]
+struct Playback {
+    steps: Macro,
+    step: usize,
+    // Whether the step's key is done and the gap after it is running
+    in_gap: bool,
+    // Tick (ms) at which the current key or gap started
+    since_ms: u32,
+}
+
+/// Plays macros over time, one report at a time
+pub struct MacroPlayer {
+    policy: MacroPolicy,
+    playing: Option<Playback>,
+    queued: Option<Macro>,
+}
+
+impl MacroPlayer {
+    pub fn new(policy: MacroPolicy) -> Self {
+        Self {
+            policy,
+            playing: None,
+            queued: None,
+        }
+    }
+
+    /// Start the macro of every button in `pressed` (press edges of logical
+    /// buttons) that has one, subject to the policy
+    pub fn trigger(&mut self, pressed: u32, now_ms: u32) {
+        for &(button, steps) in MACROS.iter() {
+            if pressed & (1 << button) == 0 || steps.is_empty() {
+                continue;
+            }
+            if self.playing.is_none() {
+                self.playing = Some(Playback {
+                    steps,
+                    step: 0,
+                    in_gap: false,
+                    since_ms: now_ms,
+                });
+            } else if self.policy == MacroPolicy::Queue && self.queued.is_none() {
+                self.queued = Some(steps);
+            } else {
+                defmt::debug!("Macro button {} ignored, a macro is playing", button + 1);
+            }
+        }
+    }
+
+    /// Whether a macro is playing, so its keys replace the button keys
+    pub fn is_playing(&self) -> bool {
+        self.playing.is_some()
+    }
+
+    /// Advance playback to `now_ms` and return the key to report, if any
+    pub fn poll(&mut self, now_ms: u32) -> Option<KeyboardUsage> {
+        let playback = self.playing.as_mut()?;
+        let elapsed = now_ms.wrapping_sub(playback.since_ms);
+
+        if !playback.in_gap && elapsed >= playback.steps[playback.step].duration_ms {
+            playback.in_gap = true;
+            playback.since_ms = now_ms;
+        } else if playback.in_gap && elapsed >= GAP_MS {
+            playback.step += 1;
+            playback.in_gap = false;
+            playback.since_ms = now_ms;
+
+            if playback.step == playback.steps.len() {
+                // Done; start the queued macro, if any, on the next poll
+                self.playing = self.queued.take().map(|steps| Playback {
+                    steps,
+                    step: 0,
+                    in_gap: false,
+                    since_ms: now_ms,
+                });
+                return None;
+            }
+        }
+
+        let playback = self.playing.as_ref()?;
+        if playback.in_gap {
+            None
+        } else {
+            playback.steps[playback.step].key
+        }
+    }
+}
@@ -0,0 +1,305 @@
+//! Button macros recorded from the console and played back by a button
+//!
+//! `record start` on the serial console starts capturing every change of the
+//! logical buttons with its `now_ms()` timestamp, and `record stop` ends it.
+//! Pressing the play button then replays the changes with their original
+//! timing, on top of the real inputs, the way the console's `press` command
+//! adds its presses. The recording lives in RAM only, so a reset clears it.
+//!
+//! The buffer holds `RECORD_LEN` changes. One that fills it ends the
+//! recording early with everything released, so a replay never leaves a
+//! button held.
+
+use heapless::Vec;
+
+/// Changes of the logical buttons a recording can hold, the last one always
+/// the release that ends it
+pub const RECORD_LEN: usize = 64;
+
+/// One recorded change: the time (ms) since the first change of the
+/// recording, and the buttons held from then on
+#[derive(Clone, Copy)]
+struct Edge {
+    offset_ms: u32,
+    buttons: u32,
+}
+
+/// State of the recorder
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Idle,
+    /// Recording, waiting for the first change
+    Armed,
+    /// Recording, with the tick (ms) of the first change
+    Recording {
+        start_ms: u32,
+    },
+    /// Recording until the next update, which adds the closing release
+    Stopping {
+        start_ms: u32,
+    },
+    /// Replaying from the tick (ms) it started at, up to the next edge
+    Playing {
+        start_ms: u32,
+        next: usize,
+    },
+}
+
+/// Records changes of the logical buttons, and replays them
+pub struct MacroRecorder {
+    // Logical button that replays the recording, never recorded itself so a
+    // replay can't start another
+    play_button: usize,
+    mode: Mode,
+    edges: Vec<Edge, RECORD_LEN>,
+    // Buttons at the last update, to find changes and the play press
+    last: u32,
+    // Buttons the replay holds
+    playing: u32,
+    // Changes held by a recording the buffer filled up, until taken
+    overflowed: Option<usize>,
+}
+
+impl MacroRecorder {
+    /// Recorder replaying on a press of logical button `play_button`
+    pub const fn new(play_button: usize) -> Self {
+        Self {
+            play_button,
+            mode: Mode::Idle,
+            edges: Vec::new(),
+            last: 0,
+            playing: 0,
+            overflowed: None,
+        }
+    }
+
+    /// Discard the recording and capture a new one from the next change,
+    /// ending any replay
+    pub fn start(&mut self) {
+        self.edges.clear();
+        self.playing = 0;
+        self.mode = Mode::Armed;
+    }
+
+    /// End the recording at the next update, and return how many changes it
+    /// holds so far, or `None` if none was running
+    pub fn stop(&mut self) -> Option<usize> {
+        match self.mode {
+            Mode::Armed => self.mode = Mode::Idle,
+            Mode::Recording { start_ms } => self.mode = Mode::Stopping { start_ms },
+            _ => return None,
+        }
+        Some(self.edges.len())
+    }
+
+    /// Whether the recorder needs updating even without a change of the
+    /// inputs, to end a recording or run a replay
+    pub fn pending(&self) -> bool {
+        matches!(self.mode, Mode::Stopping { .. } | Mode::Playing { .. })
+    }
+
+    /// Changes held by a recording that filled the buffer and stopped early
+    /// since the last call, for the caller to warn about
+    pub fn take_overflow(&mut self) -> Option<usize> {
+        self.overflowed.take()
+    }
+
+    /// Feed the logical `buttons` read at `now_ms`, and return the buttons
+    /// the replay holds on top of them
+    pub fn update(&mut self, buttons: u32, now_ms: u32) -> u32 {
+        let play = 1 << self.play_button;
+        let pressed = buttons & !self.last;
+        let recorded = buttons & !play;
+        let changed = recorded != self.last & !play;
+        self.last = buttons;
+
+        match self.mode {
+            Mode::Idle => {
+                if pressed & play != 0 && !self.edges.is_empty() {
+                    self.mode = Mode::Playing {
+                        start_ms: now_ms,
+                        next: 0,
+                    };
+                }
+            }
+            Mode::Armed => {
+                if changed {
+                    self.mode = Mode::Recording { start_ms: now_ms };
+                    self.record(recorded, now_ms, now_ms);
+                }
+            }
+            Mode::Recording { start_ms } => {
+                if changed {
+                    self.record(recorded, start_ms, now_ms);
+                }
+            }
+            Mode::Stopping { start_ms } => {
+                self.close(start_ms, now_ms);
+                self.mode = Mode::Idle;
+            }
+            Mode::Playing { .. } => {}
+        }
+
+        self.play(now_ms)
+    }
+
+    /// Add a change, or end the recording with a release if it's the last
+    /// one the buffer has room for
+    fn record(&mut self, buttons: u32, start_ms: u32, now_ms: u32) {
+        if self.edges.len() + 1 < RECORD_LEN {
+            self.edges
+                .push(Edge {
+                    offset_ms: now_ms.wrapping_sub(start_ms),
+                    buttons,
+                })
+                .ok();
+        } else {
+            self.overflowed = Some(self.edges.len());
+            self.close(start_ms, now_ms);
+            self.mode = Mode::Idle;
+        }
+    }
+
+    /// End the recording with everything released, unless it already is
+    fn close(&mut self, start_ms: u32, now_ms: u32) {
+        if self.edges.last().is_some_and(|edge| edge.buttons != 0) {
+            self.edges
+                .push(Edge {
+                    offset_ms: now_ms.wrapping_sub(start_ms),
+                    buttons: 0,
+                })
+                .ok();
+        }
+    }
+
+    /// Advance the replay to `now_ms`, and return the buttons it holds
+    fn play(&mut self, now_ms: u32) -> u32 {
+        let Mode::Playing { start_ms, mut next } = self.mode else {
+            return 0;
+        };
+        let elapsed = now_ms.wrapping_sub(start_ms);
+        while let Some(edge) = self
+            .edges
+            .get(next)
+            .filter(|edge| edge.offset_ms <= elapsed)
+        {
+            self.playing = edge.buttons;
+            next += 1;
+        }
+
+        self.mode = if next == self.edges.len() {
+            Mode::Idle
+        } else {
+            Mode::Playing { start_ms, next }
+        };
+        self.playing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAY_BUTTON: usize = 30;
+    const PLAY: u32 = 1 << PLAY_BUTTON;
+
+    /// Press the play button at `from_ms`, then read the recorder once a
+    /// millisecond with nothing else held until `end_ms`, and return each
+    /// change of the replayed buttons with the tick (ms) it was read at
+    fn replay(
+        recorder: &mut MacroRecorder,
+        from_ms: u32,
+        end_ms: u32,
+    ) -> std::vec::Vec<(u32, u32)> {
+        let mut changes = std::vec::Vec::new();
+        let mut held = 0;
+        for now_ms in from_ms..end_ms {
+            let buttons = if now_ms == from_ms { PLAY } else { 0 };
+            let playing = recorder.update(buttons, now_ms);
+            if playing != held {
+                changes.push((now_ms, playing));
+                held = playing;
+            }
+        }
+        changes
+    }
+
+    #[test]
+    fn replays_with_the_recorded_timing() {
+        let mut recorder = MacroRecorder::new(PLAY_BUTTON);
+        recorder.start();
+        for (buttons, now_ms) in [(0, 50), (0b1, 100), (0, 150), (0b10, 200), (0, 230)] {
+            recorder.update(buttons, now_ms);
+        }
+        assert_eq!(recorder.stop(), Some(4));
+        recorder.update(0, 240);
+        assert!(!recorder.pending());
+
+        // Offsets count from the first change, so the wait before it is
+        // dropped
+        assert_eq!(
+            replay(&mut recorder, 1_000, 1_200),
+            [(1_000, 0b1), (1_050, 0), (1_100, 0b10), (1_130, 0)]
+        );
+        // And it plays again from the start
+        assert_eq!(replay(&mut recorder, 2_000, 2_200).len(), 4);
+    }
+
+    #[test]
+    fn never_records_the_play_button() {
+        let mut recorder = MacroRecorder::new(PLAY_BUTTON);
+        recorder.start();
+        // The play button on its own isn't a change, and doesn't replay
+        assert_eq!(recorder.update(PLAY, 10), 0);
+        recorder.update(PLAY | 0b1, 20);
+        recorder.update(0b1, 25);
+        recorder.update(0, 40);
+        assert_eq!(recorder.stop(), Some(2));
+        recorder.update(0, 50);
+
+        assert_eq!(replay(&mut recorder, 100, 200), [(100, 0b1), (120, 0)]);
+    }
+
+    #[test]
+    fn stops_before_the_first_change_or_with_a_release() {
+        // Armed but nothing changed: there's nothing to replay
+        let mut recorder = MacroRecorder::new(PLAY_BUTTON);
+        recorder.start();
+        assert_eq!(recorder.stop(), Some(0));
+        assert!(!recorder.pending());
+        recorder.update(0b1, 10);
+        recorder.update(0, 20);
+        assert_eq!(replay(&mut recorder, 100, 200), []);
+        assert_eq!(recorder.stop(), None);
+
+        // Stopped while a button is held: the next update ends it released
+        recorder.start();
+        recorder.update(0b1, 300);
+        assert_eq!(recorder.stop(), Some(1));
+        assert!(recorder.pending());
+        recorder.update(0b1, 330);
+        assert_eq!(recorder.stop(), None);
+        assert_eq!(replay(&mut recorder, 400, 500), [(400, 0b1), (430, 0)]);
+    }
+
+    #[test]
+    fn a_full_recording_ends_released_and_reports_the_overflow() {
+        let mut recorder = MacroRecorder::new(PLAY_BUTTON);
+        recorder.start();
+        // A press or a release every 10ms, far more than fit
+        for step in 0..100 {
+            recorder.update(if step % 2 == 0 { 0b1 } else { 0 }, step * 10);
+        }
+        assert_eq!(recorder.take_overflow(), Some(RECORD_LEN - 1));
+        assert_eq!(recorder.take_overflow(), None);
+        // It already stopped on its own
+        assert_eq!(recorder.stop(), None);
+
+        // Every change up to the one that didn't fit, held there, then the
+        // release in its place
+        let changes = replay(&mut recorder, 10_000, 11_000);
+        assert_eq!(changes.len(), RECORD_LEN);
+        assert_eq!(changes[RECORD_LEN - 2], (10_620, 0b1));
+        assert_eq!(changes[RECORD_LEN - 1], (10_630, 0));
+    }
+}
@@ -1,122 +1,1148 @@
-//! USB HID Button Box with 2 buttons
+//! USB HID Button Box
 //!
-//! This implements a USB HID device that reports button states for a 2-button box.
+//! This implements a USB HID device that reports button states for a button box
+//! with a configurable number of buttons.
 #![no_std]
 #![no_main]
 
-mod hid_descriptor;
+#[cfg(feature = "keyboard")]
+mod boot_protocol;
+mod buzzer;
+mod clock;
+mod config;
+mod console;
+mod consumer;
+mod device_info;
+mod display;
+mod expander;
+mod feature_report;
+mod input;
+#[cfg(feature = "keyboard")]
+mod keyboard;
+#[cfg(not(feature = "keyboard"))]
+mod keys;
+mod led;
+#[cfg(feature = "keyboard")]
+mod macro_player;
+mod matrix;
+mod mouse;
+mod neopixel;
+mod panic_context;
+mod reset;
+mod rumble;
+mod serial_log;
+mod shift_register;
+mod telemetry;
+mod touch;
+
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use bsp::entry;
+use button_box_firmware::{
+    button_box::{ButtonBox, ButtonTiming, InputSource},
+    combo::{Combo, ComboTable},
+    debounce::DebounceMode,
+    hid_descriptor,
+    macro_recorder::MacroRecorder,
+    report_queue::{Delivery, ReportQueue},
+    REPORT_BUTTONS,
+};
 use defmt::*;
 use defmt_rtt as _;
-use embedded_hal::digital::InputPin;
-use panic_probe as _;
+use embedded_hal::{delay::DelayNs, digital::InputPin};
 
 // Provide an alias for our BSP so we can switch targets quickly.
 use rp_pico as bsp;
 
 use bsp::hal::{
-    clocks::{init_clocks_and_plls, Clock},
-    gpio::{FunctionSio, Pin, PullUp, SioInput},
-    pac,
+    adc::AdcPin,
+    clocks::Clock,
+    fugit::{ExtU32, RateExtU32},
+    gpio::{DynPinId, DynPullType, FunctionSio, Pin, SioInput},
+    pac::{self, interrupt},
+    pio::PIOExt,
+    pwm::Slices,
     sio::Sio,
     usb::UsbBus,
     watchdog::Watchdog,
+    Adc, Timer,
 };
 
 use usb_device::{class_prelude::*, prelude::*};
+#[cfg(feature = "keyboard")]
+use usbd_hid::descriptor::KeyboardUsage;
+#[cfg(feature = "keyboard")]
+use usbd_hid::hid_class::{
+    HidClassSettings, HidCountryCode, HidProtocol, HidSubClass, ProtocolModeConfig,
+};
 use usbd_hid::{descriptor::generator_prelude::*, hid_class::HIDClass};
+use usbd_serial::SerialPort;
 
-// HID Report descriptor for a 2-button gamepad
-#[gen_hid_descriptor(
-    (collection = APPLICATION, usage_page = GENERIC_DESKTOP, usage = GAMEPAD) = {
-        (collection = PHYSICAL, usage = POINTER) = {
-            (usage_page = BUTTON, usage_min = 0x01, usage_max = 0x02) = {
-                #[packed_bits 2] #[item_settings data,variable,absolute] buttons=input;
-            };
-            // Padding to align to byte boundary
-            #[packed_bits 6] #[item_settings constant,variable,absolute] padding=input;
-        };
+use buzzer::{Buzzer, BuzzerConfig, BuzzerPolicy};
+use config::{ButtonConfig, ButtonMode, ButtonPull, Config, Polarity, SwitchType};
+use console::{Command, Console};
+use consumer::{ConsumerControlReport, CONSUMER_BUTTONS};
+use expander::Expander;
+use feature_report::FeatureReport;
+use input::{
+    Acceleration, AxisConfig, Direction, Encoder, EncoderMode, Hat, Joystick, AXIS_CENTER,
+    HAT_CENTERED,
+};
+#[cfg(feature = "slider")]
+use input::{Slider, SliderConfig};
+#[cfg(not(feature = "slider"))]
+use led::ActivityLed;
+use led::{BreathingLed, LedBank, LedMap, LedPin, StatusLed};
+use matrix::{ColumnPin, Matrix, RowPin};
+use neopixel::Neopixel;
+use rumble::Rumble;
+use serial_log::{error, info, warn};
+use shift_register::ShiftRegister;
+use smart_leds::RGB8;
+use telemetry::Telemetry;
+use touch::{TouchConfig, TouchPads, TouchPin};
+
+/// Number of buttons wired directly to a pin of their own
+const DIRECT_BUTTONS: usize = 2;
+
+/// System clock frequency (Hz), the stock 125MHz by default
+///
+/// Raise it, up to 200MHz, to shorten the time each loop takes; the core
+/// voltage is raised to match past 133MHz. USB stays at 48MHz and every
+/// timing is in milliseconds from the crystal, so nothing else changes.
+/// The build fails for a clock out of bounds or one the PLL can't make
+/// exactly from the crystal, such as 199MHz; 133, 150, 180 and 200MHz work.
+const SYS_CLOCK_HZ: u32 = 125_000_000;
+
+const _: () = core::assert!(
+    clock::sys_pll_config(SYS_CLOCK_HZ).is_some(),
+    "SYS_CLOCK_HZ is out of bounds or can't be made from the crystal"
+);
+
+/// Rows and columns of the scanned button matrix, none wired by default
+const MATRIX_ROWS: usize = 0;
+const MATRIX_COLS: usize = 0;
+/// Hold buttons that might be ghosts at their last state, for a matrix wired
+/// without diodes
+const MATRIX_GHOST_FILTER: bool = false;
+
+/// Number of buttons on the MCP23017 I/O expander, on its first pins from
+/// GPA0 up; none by default
+const EXPANDER_BUTTONS: usize = 0;
+
+/// Number of buttons on the 74HC165 shift register, on its first inputs from
+/// A up; none by default
+const SHIFT_REGISTER_BUTTONS: usize = 0;
+
+/// Number of capacitive touch pads, each on a pin of its own; none by default
+const TOUCH_BUTTONS: usize = 0;
+/// Rise of a pad's discharge count over its untouched baseline that reads as
+/// a touch, and the lower rise it must fall under to release
+///
+/// A larger pad, or a thinner cover over it, gives a larger rise; raise both
+/// if a pad triggers without a touch, lower them if a touch is missed.
+const TOUCH: TouchConfig = TouchConfig {
+    on_percent: 30,
+    off_percent: 15,
+};
+
+const _: () = core::assert!(TOUCH.is_valid());
+
+/// Number of physical buttons wired to the box: the direct ones first, then
+/// the matrix buttons row by row, then the expander, shift register and
+/// touch buttons
+///
+/// The report carries `REPORT_BUTTONS` (32) buttons, so this can be raised
+/// without touching the HID descriptor, up to the 16 inputs the `u16` input
+/// masks hold.
+const BUTTON_COUNT: usize = DIRECT_BUTTONS
+    + MATRIX_ROWS * MATRIX_COLS
+    + EXPANDER_BUTTONS
+    + SHIFT_REGISTER_BUTTONS
+    + TOUCH_BUTTONS;
+
+// Every physical input is a bit of the `u16` input masks, mapped onto a
+// report bit
+const _: () = core::assert!(
+    BUTTON_COUNT <= u16::BITS as usize,
+    "BUTTON_COUNT exceeds the 16 inputs the input masks can hold"
+);
+
+/// Time (ms) a button input must stay unchanged before a new state is accepted
+const DEBOUNCE_MS: u32 = 5;
+/// Time (ms) a press must stay asserted before it is reported at all
+///
+/// Unlike `DEBOUNCE_MS` it only applies to presses, so `DEBOUNCE_MS` can be
+/// shortened for faster releases without letting noise spikes through as
+/// presses. Ticks are whole milliseconds, so a press is held for somewhere
+/// between `MIN_PRESS_MS - 1` and `MIN_PRESS_MS` before it is accepted.
+const MIN_PRESS_MS: u32 = 2;
+/// Debounce algorithm of every button input
+///
+/// The settle timer with `DEBOUNCE_MS` and `MIN_PRESS_MS` suits most switches.
+/// For one that chatters while held, try `DebounceMode::Integrator` with
+/// about as many samples (one a millisecond) as `DEBOUNCE_MS`; for rare long
+/// glitches `DebounceMode::ShiftRegister`, which needs every sample in a row
+/// to agree.
+const DEBOUNCE_MODE: DebounceMode = DebounceMode::Settle {
+    debounce_ms: DEBOUNCE_MS,
+    min_press_ms: MIN_PRESS_MS,
+};
+
+const _: () = core::assert!(DEBOUNCE_MODE.is_valid());
+
+/// What the encoder's rotation is reported as: pulses of
+/// `ENCODER_CW_BUTTON` and `ENCODER_CCW_BUTTON`, or with
+/// `EncoderMode::RelativeAxis` cursor movement on the mouse interface, e.g.
+/// `{ x: 4, y: 0 }` to move right 4 per clockwise increment
+const ENCODER_MODE: EncoderMode = EncoderMode::Buttons;
+
+const _: () = core::assert!(ENCODER_MODE.is_valid());
+/// Button pulsed for one report per clockwise encoder detent (button 3)
+const ENCODER_CW_BUTTON: usize = BUTTON_COUNT;
+/// Button pulsed for one report per counter-clockwise encoder detent (button 4)
+const ENCODER_CCW_BUTTON: usize = BUTTON_COUNT + 1;
+/// Encoder acceleration curve: a detent within `within_ms` of the previous
+/// one in the same direction pulses its button `multiplier` times
+///
+/// Checked in order, so the shortest time goes first. Slower detents pulse
+/// once; an empty curve turns acceleration off.
+const ENCODER_ACCELERATION: &[Acceleration] = &[
+    Acceleration {
+        within_ms: 30,
+        multiplier: 3,
+    },
+    Acceleration {
+        within_ms: 80,
+        multiplier: 2,
+    },
+];
+/// Button reported while the encoder's push switch is held (button 12)
+const ENCODER_SWITCH_BUTTON: usize = BUTTON_COUNT + 9;
+
+/// Calibration of the thumbstick axes, in raw ADC counts (0-4095)
+///
+/// The defaults suit a 10kΩ pot across 3V3, centered at mid-scale. Measure
+/// the readings at rest and at the ends of travel for a stick that sits
+/// off-center or doesn't reach the ends, and widen the deadzone if the
+/// report wobbles while the stick is at rest.
+const X_AXIS: AxisConfig = AxisConfig {
+    center: 2048,
+    deadzone: 64,
+    min: 0,
+    max: 4095,
+    invert: false,
+};
+const Y_AXIS: AxisConfig = AxisConfig {
+    center: 2048,
+    deadzone: 64,
+    min: 0,
+    max: 4095,
+    invert: false,
+};
+
+const _: () = core::assert!(X_AXIS.is_valid() && Y_AXIS.is_valid());
+
+/// Calibration of the slider with the `slider` feature, in raw ADC counts
+///
+/// The defaults suit a linear pot across 3V3; set `min` and `max` to the
+/// readings at the ends of travel if it doesn't reach the rails.
+///
+/// `button` thresholds the slider into `SLIDER_BUTTON` as well, e.g.
+/// `Some(input::AnalogThreshold { on: 3_600, off: 3_200 })` for a button at
+/// the top of the travel; keep a few hundred counts between the levels so ADC
+/// noise can't cross both.
+#[cfg(feature = "slider")]
+const SLIDER: SliderConfig = SliderConfig {
+    min: 0,
+    max: 4095,
+    invert: false,
+    button: None,
+};
+/// Button reported while the slider is past its button's level (button 16)
+#[cfg(feature = "slider")]
+const SLIDER_BUTTON: usize = BUTTON_COUNT + 13;
+
+#[cfg(feature = "slider")]
+const _: () = core::assert!(SLIDER.is_valid());
+#[cfg(feature = "slider")]
+const _: () = core::assert!(
+    SLIDER_BUTTON < REPORT_BUTTONS,
+    "too many buttons: SLIDER_BUTTON no longer fits the report"
+);
+
+/// Time (ms) a button must be held before it counts as a long press
+const LONG_PRESS_MS: u32 = 500;
+/// Button reported while button 1 is long-pressed (button 5)
+const BUTTON1_LONG_PRESS_BUTTON: usize = BUTTON_COUNT + 2;
+
+/// Time (ms) a repeating button must be held before it starts repeating
+const REPEAT_DELAY_MS: u32 = 400;
+/// Time (ms) between repeated presses of a held repeating button, at least 2
+const REPEAT_INTERVAL_MS: u32 = 100;
+
+/// Presses per second sent while a turbo button is held (1-500)
+const TURBO_RATE_HZ: u32 = 10;
+
+/// Time (ms) after a button is released in which pressing it again counts
+/// as a double tap
+const DOUBLE_TAP_MS: u32 = 250;
+/// Button reported while button 2 is held down on its second tap (button 9)
+const BUTTON2_DOUBLE_TAP_BUTTON: usize = BUTTON_COUNT + 6;
+
+const BUTTON_TIMING: ButtonTiming = ButtonTiming {
+    debounce: DEBOUNCE_MODE,
+    long_press_ms: LONG_PRESS_MS,
+    repeat_delay_ms: REPEAT_DELAY_MS,
+    repeat_interval_ms: REPEAT_INTERVAL_MS,
+    double_tap_ms: DOUBLE_TAP_MS,
+    turbo_period_ms: 1_000 / TURBO_RATE_HZ,
+};
+
+const _: () = core::assert!(BUTTON_TIMING.is_valid());
+
+/// Default logical buttons reported by each physical input, in `buttons` pin
+/// order, used until a configuration is saved to flash
+///
+/// Re-wiring only needs this table changed: swapping two `button` entries
+/// swaps the bits the two inputs report as.
+const BUTTON_MAP: [ButtonConfig; BUTTON_COUNT] = [
+    // GPIO14
+    ButtonConfig {
+        button: 0,
+        long_press: Some(BUTTON1_LONG_PRESS_BUTTON),
+        double_tap: None,
+        mode: ButtonMode::Momentary,
+        polarity: Polarity::ActiveLow,
+        pull: ButtonPull::Up,
+        switch: SwitchType::NormallyOpen,
+        group: 0,
+        solo: false,
+        hold_to_activate_ms: 0,
+        extra_buttons: 0,
+        tap_hold_ms: 0,
+        enabled: true,
+    },
+    // GPIO15
+    ButtonConfig {
+        button: 1,
+        long_press: None,
+        double_tap: Some(BUTTON2_DOUBLE_TAP_BUTTON),
+        mode: ButtonMode::Momentary,
+        polarity: Polarity::ActiveLow,
+        pull: ButtonPull::Up,
+        switch: SwitchType::NormallyOpen,
+        group: 0,
+        solo: false,
+        hold_to_activate_ms: 0,
+        extra_buttons: 0,
+        tap_hold_ms: 0,
+        enabled: true,
+    },
+];
+
+/// Number of button profiles the profile button cycles through
+const PROFILE_COUNT: usize = 2;
+/// Logical button that switches to the next profile instead of reporting
+/// (button 15); map an input onto it (e.g. with the console's `set` command)
+/// in every profile to use it
+const PROFILE_BUTTON: usize = BUTTON_COUNT + 12;
+
+/// Logical button that replays the console's macro recording (button 17)
+///
+/// Button 17 isn't wired by default; map an input onto it (e.g. with the
+/// console's `set` command) to use it. It's never recorded itself, so a
+/// replay can't start another.
+const PLAY_BUTTON: usize = BUTTON_COUNT + 14;
+
+const _: () = core::assert!(
+    PLAY_BUTTON < REPORT_BUTTONS,
+    "too many buttons: PLAY_BUTTON no longer fits the report"
+);
+
+/// Built-in mapping of each profile, used until a configuration is saved
+/// to flash; the stored or console-edited mapping replaces the first one
+const PROFILES: [[ButtonConfig; BUTTON_COUNT]; PROFILE_COUNT] = [BUTTON_MAP, {
+    // The second profile swaps what the two buttons report as
+    let mut map = BUTTON_MAP;
+    map[0].button = 1;
+    map[1].button = 0;
+    map
+}];
+
+/// Window (ms) in which all inputs of a combo must be pressed to fire it
+const COMBO_WINDOW_MS: u32 = 50;
+/// Button reported while buttons 1 and 2 are pressed together (button 8)
+const BUTTON1_2_COMBO_BUTTON: usize = BUTTON_COUNT + 5;
+
+const COMBO_COUNT: usize = 1;
+
+/// Chords of physical inputs that report a logical button of their own
+const COMBOS: [Combo; COMBO_COUNT] = [
+    // GPIO14 + GPIO15
+    Combo {
+        button_mask: 0b11,
+        output_bit: BUTTON1_2_COMBO_BUTTON,
+        suppress: true,
+    },
+];
+
+/// Number of LEDs on the WS2812 strip, lit by the first `PIXEL_COUNT`
+/// report buttons, one each
+const PIXEL_COUNT: usize = 16;
+
+const _: () = core::assert!(
+    PIXEL_COUNT <= REPORT_BUTTONS,
+    "PIXEL_COUNT exceeds the buttons the report carries"
+);
+/// Strip color of a button while pressed; released buttons are dark
+const PRESSED_COLOR: RGB8 = RGB8 { r: 0, g: 32, b: 16 };
+
+/// Number of indicator LEDs lit by the button each follows, none wired by
+/// default since every spare pin is taken
+const BUTTON_LEDS: usize = 0;
+
+/// Highest duty cycle (out of 255) the rumble motor is driven at, whatever
+/// the host asks for; lower it for a motor rated below the supply voltage
+const RUMBLE_MAX_INTENSITY: u8 = u8::MAX;
+
+/// Click the piezo buzzer plays on every button press; set `enabled` to
+/// false for a silent box
+const BUZZER: BuzzerConfig = BuzzerConfig {
+    enabled: true,
+    frequency_hz: 4_000,
+    duration_ms: 5,
+    policy: BuzzerPolicy::Retrigger,
+};
+
+const _: () = core::assert!(BUZZER.is_valid());
+
+/// Interval (ms) at which the host polls the HID endpoints for reports, the
+/// bInterval of their endpoint descriptors; 1 to 255
+///
+/// 1ms gives the lowest latency (up to 1000 reports per second) but has the
+/// host poll the box every frame. Longer intervals add up to that much input
+/// lag and leave more of the bus to other devices on the same hub, and suit
+/// embedded hosts that can't keep up with 1ms polling.
+const HID_POLL_MS: u8 = 1;
+/// Longest wait (us) between loop iterations, the one at an 8ms poll
+/// interval, since the encoder and the debouncer need sampling that often
+/// however slowly the host polls
+const MAX_LOOP_DELAY_US: u32 = 800;
+/// Time (us) the main loop waits between iterations, a tenth of the poll
+/// interval so every poll finds the latest state ready
+const LOOP_DELAY_US: u32 = if HID_POLL_MS as u32 * 100 < MAX_LOOP_DELAY_US {
+    HID_POLL_MS as u32 * 100
+} else {
+    MAX_LOOP_DELAY_US
+};
+
+// Full-speed interrupt endpoints take a bInterval of 1-255ms, which a `u8`
+// never exceeds
+const _: () = core::assert!(HID_POLL_MS >= 1);
+
+/// Minimum time (ms) between gamepad reports, 1 to 255
+///
+/// Button changes arriving faster, e.g. from turbo buttons or a bouncy input
+/// with debouncing turned down, wait in the report queue and go out one per
+/// interval, so even a press shorter than it reaches the host. Raising it to
+/// `HID_POLL_MS` or beyond paces the reports for a host that polls on time but
+/// is slow to handle them.
+const MIN_REPORT_INTERVAL_MS: u32 = 1;
+
+const _: () = core::assert!(MIN_REPORT_INTERVAL_MS >= 1 && MIN_REPORT_INTERVAL_MS <= 255);
+
+/// Time (ms) without a gamepad report after which the current one is sent
+/// again unchanged, for host software that drops a device it hasn't heard
+/// from in a while, e.g. `Some(1_000)`; `None` turns the heartbeat off
+///
+/// The repeat carries exactly what the last report did, so the host sees no
+/// new press or release, and any report sent for a change restarts the
+/// interval.
+const HEARTBEAT_MS: Option<u32> = None;
+
+const _: () = core::assert!(match HEARTBEAT_MS {
+    Some(interval_ms) => interval_ms >= MIN_REPORT_INTERVAL_MS,
+    None => true,
+});
+
+/// Gamepad reports held while the host isn't taking them, see `ReportQueue`
+const REPORT_QUEUE_LEN: usize = 8;
+
+/// Consecutive button reads with a failed pin before the status LED signals
+/// a hardware fault
+const READ_FAULT_THRESHOLD: u32 = 10;
+
+/// Physical inputs (bit per input, in `buttons` pin order) that, held at
+/// power-on, reboot into the USB bootloader: GPIO14 + GPIO15
+const BOOTSEL_COMBO: u16 = 0b11;
+/// Time (ms) `BOOTSEL_COMBO` must stay held from power-on to enter the
+/// bootloader
+///
+/// Only checked once at startup, so pressing the combo later never reboots.
+const BOOTSEL_HOLD_MS: u32 = 100;
+
+/// Time (ms) to wait after power-on before connecting to USB, for hubs that
+/// enumerate the box before the host is ready to bind it; 0 connects
+/// straight away
+const USB_STARTUP_DELAY_MS: u32 = 0;
+/// Time (ms) VBUS must read present without a break before connecting
+const VBUS_STABLE_MS: u32 = 10;
+/// Longest time (ms) to wait for VBUS to settle, after which the box
+/// connects anyway
+const VBUS_WAIT_MS: u32 = 1_000;
+
+/// Physical inputs (bit per input, in `buttons` pin order) that, held for
+/// `RESET_HOLD_MS` during normal operation, reset the board: GPIO14 + GPIO15
+///
+/// They don't count while any of them is held as part of a firing combo, so
+/// holding the `BUTTON1_2_COMBO_BUTTON` chord never resets the board. Press
+/// them more than `COMBO_WINDOW_MS` apart to reset.
+const RESET_COMBO: u16 = 0b11;
+/// Time (ms) `RESET_COMBO` must be held to reset the board
+const RESET_HOLD_MS: u32 = 3_000;
+/// Time (ms) to wait for the host to take the released reports before
+/// detaching, so a host that stopped polling can't hold off the reset
+const DETACH_FLUSH_MS: u32 = 20;
+/// Time (ms) to stay detached before resetting, long enough for the host to
+/// see the disconnect
+const DETACH_MS: u32 = 10;
+
+/// Logical buttons always reported on an interface of their own rather than
+/// the gamepad (or keyboard, in keyboard mode); the ones routed to the
+/// keyboard interface are picked at runtime, see `routed_buttons()`
+const ROUTED_BUTTONS: u32 = CONSUMER_BUTTONS | mouse::MOUSE_BUTTONS;
+
+// A button can only be routed to one interface
+const _: () = core::assert!(CONSUMER_BUTTONS & mouse::MOUSE_BUTTONS == 0);
+const _: () = core::assert!(ROUTED_BUTTONS & (1 << PROFILE_BUTTON) == 0);
+#[cfg(not(feature = "keyboard"))]
+const _: () = core::assert!(
+    ROUTED_BUTTONS & keys::KEY_BUTTONS == 0 && keys::KEY_BUTTONS & (1 << PROFILE_BUTTON) == 0
+);
+
+/// Logical buttons kept out of the gamepad report: the fixed routes, and the
+/// buttons `config` routes to the keyboard interface
+#[cfg(not(feature = "keyboard"))]
+fn routed_buttons(config: &Config) -> u32 {
+    ROUTED_BUTTONS | config.keyboard_buttons
+}
+/// Logical buttons kept out of the keyboard report, which has no keyboard
+/// interface next to it to route any more to
+#[cfg(feature = "keyboard")]
+fn routed_buttons(_config: &Config) -> u32 {
+    ROUTED_BUTTONS
+}
+
+/// Logical buttons something can report: the ones an input maps onto in
+/// `config` or another profile, the combo and encoder buttons, and the
+/// slider's, but not the profile button, which never reports
+///
+/// In raw mode the inputs report on their own bits instead of the mapping.
+fn reported_buttons(config: &Config) -> u32 {
+    let mut buttons = 1 << ENCODER_SWITCH_BUTTON;
+    if ENCODER_MODE == EncoderMode::Buttons {
+        buttons |= (1 << ENCODER_CW_BUTTON) | (1 << ENCODER_CCW_BUTTON);
     }
-)]
+    #[cfg(feature = "slider")]
+    {
+        buttons |= 1 << SLIDER_BUTTON;
+    }
+    if cfg!(feature = "raw") {
+        return buttons | ((1 << BUTTON_COUNT) - 1);
+    }
+
+    for combo in COMBOS {
+        buttons |= 1 << combo.output_bit;
+    }
+    for profile in core::iter::once(&config.buttons).chain(&PROFILES[1..]) {
+        for button in profile {
+            buttons |= (1 << button.button) | button.extra_buttons;
+            if let Some(long_press) = button.long_press {
+                buttons |= 1 << long_press;
+            }
+            if let Some(double_tap) = button.double_tap {
+                buttons |= 1 << double_tap;
+            }
+        }
+    }
+    buttons & !(1 << PROFILE_BUTTON)
+}
+
+// Every built-in profile's inputs have what their mode needs
+const _: () = {
+    let mut profile = 0;
+    while profile < PROFILE_COUNT {
+        let mut index = 0;
+        while index < BUTTON_COUNT {
+            core::assert!(
+                PROFILES[profile][index].is_valid(),
+                "a TapHold input needs a long_press button for its hold action"
+            );
+            index += 1;
+        }
+        profile += 1;
+    }
+};
+
+/// Logical buttons numbered after the wired ones, which move up as buttons are
+/// added and must still fit the report; the media key, key, mouse and macro
+/// maps check their own
+const EXTRA_BUTTONS: [usize; 7] = [
+    ENCODER_CW_BUTTON,
+    ENCODER_CCW_BUTTON,
+    BUTTON1_LONG_PRESS_BUTTON,
+    BUTTON1_2_COMBO_BUTTON,
+    BUTTON2_DOUBLE_TAP_BUTTON,
+    ENCODER_SWITCH_BUTTON,
+    PROFILE_BUTTON,
+];
+
+const _: () = {
+    let mut index = 0;
+    while index < EXTRA_BUTTONS.len() {
+        core::assert!(
+            EXTRA_BUTTONS[index] < REPORT_BUTTONS,
+            "too many buttons: the encoder, long-press and other extra buttons no longer fit the report"
+        );
+        index += 1;
+    }
+};
+
+/// Endpoints allocated by the USB classes, besides the control endpoint:
+/// the gamepad (IN and OUT), media keys (IN and OUT), the keyboard keys next
+/// to the gamepad (IN only), the mouse (IN only) and the CDC serial port
+/// (notification IN, data IN and OUT)
+const IN_ENDPOINTS: usize = 6;
+const OUT_ENDPOINTS: usize = 3;
+/// Endpoints the RP2040 has in each direction, besides the control endpoint
+const MAX_ENDPOINTS: usize = 15;
+
+const _: () = core::assert!(IN_ENDPOINTS <= MAX_ENDPOINTS && OUT_ENDPOINTS <= MAX_ENDPOINTS);
+
+/// Time (ms) without a watchdog feed before the board resets
+const WATCHDOG_TIMEOUT_MS: u32 = 1_000;
+/// Time (ms) the host may leave a report untaken while the device is
+/// configured before the USB stack is considered wedged: 5s, or ten times
+/// the poll and report intervals combined if that's longer, so a host that's
+/// still polling at the slowest polling and pacing takes a report well
+/// before it counts as stalled
+const USB_STALL_MS: u32 = {
+    let slowest = 10 * (HID_POLL_MS as u32 + MIN_REPORT_INTERVAL_MS);
+    if slowest > 5_000 {
+        slowest
+    } else {
+        5_000
+    }
+};
+
+/// Time (ms) the host has to configure the device after it connects, or
+/// after a bus reset, before the attempt counts as failed and the box
+/// reconnects
+const ENUMERATION_TIMEOUT_MS: u32 = 10_000;
+/// Failed enumeration attempts in a row after which the box gives up and
+/// stays in safe mode until reset
+const ENUMERATION_ATTEMPTS: u32 = 3;
+/// Time (ms) between loops while the host has suspended the bus, instead of
+/// `LOOP_DELAY_US`
+const SUSPENDED_LOOP_MS: u32 = 1;
+/// Time (ms) without any input change after which the box goes idle: the
+/// loop slows down to `IDLE_LOOP_MS` and the status LED dims, until the next
+/// input change
+const IDLE_TIMEOUT_MS: u32 = 60_000;
+/// Time (ms) between loops while idle, instead of `LOOP_DELAY_US`
+///
+/// USB is still polled every loop. The host allows a control request 50ms
+/// before timing it out, so this stays well below that.
+const IDLE_LOOP_MS: u32 = 5;
+
+const _: () = core::assert!(IDLE_LOOP_MS < 50);
+
+/// Time (ms) the bus must have been suspended before a button press may
+/// signal remote wakeup
+///
+/// The device sees the suspend after 3ms of bus idle, and USB 2.0 (7.1.7.7)
+/// only allows resume signalling after 5ms of idle, so this adds a margin.
+const REMOTE_WAKEUP_MIN_SUSPEND_MS: u32 = 5;
+
+/// Gamepad report with up to 32 buttons, an X/Y stick, a hat switch, a
+/// slider, 8 host-controlled LEDs and a rumble motor
+///
+/// The descriptor is the hand-written `HID_REPORT_DESCRIPTOR`, so the input
+/// fields are serialized here in the order it declares them.
+#[derive(Clone, Copy)]
 pub struct ButtonBoxReport {
-    pub buttons: u8,
-    pub padding: u8,
+    // Little-endian button bits
+    pub buttons: [u8; 4],
+    pub x: u8,
+    pub y: u8,
+    // Hat direction, 0 (north) to 7 clockwise, or `HAT_CENTERED`
+    pub hat: u8,
+    // Signed slider position, 0 in the middle of its travel or without one
+    pub slider: i8,
+    // Output report from the host: one bit per status LED, then the rumble
+    // intensity
+    pub leds: u8,
+    pub rumble: u8,
 }
 
-// GPIO pin type aliases for button inputs
-type Button1Pin = Pin<bsp::hal::gpio::bank0::Gpio23, FunctionSio<SioInput>, PullUp>;
-type Button2Pin = Pin<bsp::hal::gpio::bank0::Gpio15, FunctionSio<SioInput>, PullUp>;
+impl SerializedDescriptor for ButtonBoxReport {
+    fn desc() -> &'static [u8] {
+        hid_descriptor::HID_REPORT_DESCRIPTOR
+    }
+}
 
-struct ButtonBox {
-    button1: Button1Pin,
-    button2: Button2Pin,
-    last_report: ButtonBoxReport,
+impl Serialize for ButtonBoxReport {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // `leds` and `rumble` are output fields, so it isn't part of the input report
+        let mut report = serializer.serialize_tuple(Self::INPUT_LEN)?;
+        for byte in &self.buttons {
+            report.serialize_element(byte)?;
+        }
+        report.serialize_element(&self.x)?;
+        report.serialize_element(&self.y)?;
+        report.serialize_element(&self.hat)?;
+        report.serialize_element(&self.slider)?;
+        report.end()
+    }
 }
 
-impl ButtonBox {
-    fn new(button1: Button1Pin, button2: Button2Pin) -> Self {
+impl AsInputReport for ButtonBoxReport {}
+
+impl ButtonBoxReport {
+    /// Bytes `serialize()` writes: the buttons, X, Y, the hat and the slider
+    const INPUT_LEN: usize = 8;
+    /// Bytes of the output report: the LED bits and the rumble intensity
+    const OUTPUT_LEN: usize = 2;
+
+    const fn new(buttons: u32) -> Self {
         Self {
-            button1,
-            button2,
-            last_report: ButtonBoxReport {
-                buttons: 0,
-                padding: 0,
-            },
+            buttons: buttons.to_le_bytes(),
+            x: hid_descriptor::axis_byte(AXIS_CENTER),
+            y: hid_descriptor::axis_byte(AXIS_CENTER),
+            hat: HAT_CENTERED,
+            slider: 0,
+            leds: 0,
+            rumble: 0,
         }
     }
 
-    fn read_buttons(&mut self) -> ButtonBoxReport {
-        let mut buttons = 0u8;
+    const fn buttons(&self) -> u32 {
+        u32::from_le_bytes(self.buttons)
+    }
+}
+
+// The descriptor sent to the host must describe the reports as this type
+// writes and reads them, so a change to either that the other misses fails
+// the build
+const _: () = {
+    use hid_descriptor::{report_len, HID_REPORT_DESCRIPTOR, INPUT_ITEM, OUTPUT_ITEM};
+    core::assert!(
+        report_len(HID_REPORT_DESCRIPTOR, INPUT_ITEM) == ButtonBoxReport::INPUT_LEN,
+        "HID_REPORT_DESCRIPTOR's input report doesn't match ButtonBoxReport::serialize()"
+    );
+    core::assert!(
+        report_len(HID_REPORT_DESCRIPTOR, OUTPUT_ITEM) == ButtonBoxReport::OUTPUT_LEN,
+        "HID_REPORT_DESCRIPTOR's output report doesn't match ButtonBoxReport"
+    );
+};
+
+// The firmware's report puts the buttons in the same byte order the host
+// library decodes, little-endian with button 1 in bit 0 of the first byte,
+// here with bits set in the high bytes too
+const _: () = {
+    const BUTTONS: u32 = 0x8421_0003;
+    let report = ButtonBoxReport::new(BUTTONS);
+    core::assert!(report.buttons() == BUTTONS);
+    let bytes = report.buttons;
+    core::assert!(bytes[0] == 0x03 && bytes[1] == 0x00 && bytes[2] == 0x21 && bytes[3] == 0x84);
+    match hid_descriptor::ButtonBoxHidReport::from_raw_bytes(&bytes) {
+        Some(decoded) => core::assert!(decoded.buttons == BUTTONS),
+        None => core::panic!(),
+    }
+};
+
+/// Button state a gamepad report is built from: the buttons, and in keyboard
+/// mode the macro's progress too
+#[cfg(not(feature = "keyboard"))]
+type ReportState = u32;
+#[cfg(feature = "keyboard")]
+type ReportState = (u32, (bool, Option<KeyboardUsage>));
 
-        // Read button states (buttons are active low with pull-up resistors)
-        if self.button1.is_low().unwrap_or(false) {
-            buttons |= 0x01; // Button 1
+/// Set from the GPIO edge interrupt whenever any button input changes
+///
+/// Starts set so the initial button state is read on the first loop.
+static BUTTONS_DIRTY: AtomicBool = AtomicBool::new(true);
+
+// GPIO pin type for button inputs, erased so buttons on any pin fit in one array
+type ButtonPin = Pin<DynPinId, FunctionSio<SioInput>, DynPullType>;
+
+/// Inputs read by scanning rather than from a pin each: the matrix buttons,
+/// then the expander, shift register and touch buttons
+struct ScannedInputs {
+    matrix: Matrix<MATRIX_ROWS, MATRIX_COLS>,
+    expander: Expander<EXPANDER_BUTTONS>,
+    shift_register: ShiftRegister<SHIFT_REGISTER_BUTTONS>,
+    touch: TouchPads<TOUCH_BUTTONS>,
+}
+
+impl InputSource for ScannedInputs {
+    fn read(&mut self, now_ms: u32) -> u16 {
+        let expander_offset = MATRIX_ROWS * MATRIX_COLS;
+        let shift_register_offset = expander_offset + EXPANDER_BUTTONS;
+        let touch_offset = shift_register_offset + SHIFT_REGISTER_BUTTONS;
+        self.matrix.scan()
+            | (self.expander.read() << expander_offset)
+            | ((self.shift_register.read() as u16) << shift_register_offset)
+            | (self.touch.read(now_ms) << touch_offset)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.matrix.is_empty()
+            && self.expander.is_empty()
+            && self.shift_register.is_empty()
+            && self.touch.is_empty()
+    }
+}
+
+/// The box's button state machine, over every input it's built with
+type Buttons =
+    ButtonBox<BUTTON_COUNT, DIRECT_BUTTONS, PROFILE_COUNT, COMBO_COUNT, ButtonPin, ScannedInputs>;
+
+/// Milliseconds since boot, from the free-running 1MHz `timer`
+///
+/// Every time-based feature compares ticks from this, so they stay accurate
+/// however long a loop takes. It wraps after about 49 days, so compare ticks
+/// with `wrapping_sub()`.
+fn now_ms(timer: &Timer) -> u32 {
+    (timer.get_counter().ticks() / 1_000) as u32
+}
+
+/// Reboot into the RP2040's USB bootloader, to flash new firmware as a UF2
+/// file, if `BOOTSEL_COMBO` is held for `BOOTSEL_HOLD_MS` from power-on
+///
+/// Returns as soon as any input of the combo reads released, so a normal
+/// boot isn't delayed. The raw inputs are read, as debouncing would only
+/// hide a combo that is held throughout anyway.
+fn check_bootsel_combo(button_box: &mut Buttons, timer: &Timer) {
+    let start = now_ms(timer);
+    while now_ms(timer).wrapping_sub(start) < BOOTSEL_HOLD_MS {
+        if button_box.read_raw(now_ms(timer)) & BOOTSEL_COMBO != BOOTSEL_COMBO {
+            return;
         }
-        if self.button2.is_low().unwrap_or(false) {
-            buttons |= 0x02; // Button 2
+    }
+
+    info!("Bootloader combo held, rebooting to USB boot");
+    bsp::hal::rom_data::reset_to_usb_boot(0, 0);
+}
+
+/// Hold off connecting to USB for `USB_STARTUP_DELAY_MS`, then until `vbus`
+/// (the Pico's VBUS sense pin) has read present for `VBUS_STABLE_MS`
+///
+/// A board powered from somewhere other than its USB port may never see VBUS,
+/// so the wait gives up after `VBUS_WAIT_MS`.
+fn wait_for_usb_bus(vbus: &mut impl InputPin, timer: &Timer) {
+    let mut delay = *timer;
+    if USB_STARTUP_DELAY_MS != 0 {
+        info!(
+            "Waiting {}ms before connecting to USB",
+            USB_STARTUP_DELAY_MS
+        );
+        delay.delay_ms(USB_STARTUP_DELAY_MS);
+    }
+
+    let start = now_ms(timer);
+    let mut present_since = start;
+    loop {
+        let now = now_ms(timer);
+        if !vbus.is_high().unwrap_or(false) {
+            present_since = now;
+        } else if now.wrapping_sub(present_since) >= VBUS_STABLE_MS {
+            return;
+        }
+        if now.wrapping_sub(start) >= VBUS_WAIT_MS {
+            warn!("VBUS not stable, connecting anyway");
+            return;
         }
+    }
+}
+
+/// Connect the pull-up on D+, which tells the host a device is attached, or
+/// disconnect it so the host sees the device unplugged
+fn set_usb_pullup(connected: bool) {
+    // SAFETY: only the pull-up bit is changed, which the USB stack doesn't
+    // touch after enabling the bus
+    unsafe {
+        (*pac::USBCTRL_REGS::ptr())
+            .sie_ctrl()
+            .modify(|_, w| w.pullup_en().bit(connected));
+    }
+}
+
+/// Disconnect from the bus for `DETACH_MS` and connect again, so the host
+/// starts enumerating the device over
+fn reconnect_usb(timer: &Timer) {
+    set_usb_pullup(false);
+    let mut delay = *timer;
+    delay.delay_ms(DETACH_MS);
+    set_usb_pullup(true);
+}
+
+/// Give up on USB for good: disconnect from the bus, stop the motor, and
+/// blink SOS on the status LED until the board is reset
+///
+/// The watchdog is still fed, so safe mode lasts until a power cycle or the
+/// RUN pin rather than cycling through watchdog resets.
+fn safe_mode(
+    timer: &Timer,
+    watchdog: &mut Watchdog,
+    status_led: &mut StatusLed,
+    rumble: &mut Rumble,
+    buzzer: &mut Buzzer,
+) -> ! {
+    set_usb_pullup(false);
+    rumble.set(0);
+    status_led.set_safe_mode();
+    let mut delay = *timer;
+    loop {
+        let now_ms = now_ms(timer);
+        watchdog.feed();
+        // Let a click that was sounding finish
+        buzzer.update(now_ms);
+        status_led.update(false, now_ms);
+        delay.delay_ms(SUSPENDED_LOOP_MS);
+    }
+}
+
+/// Report sent on `hid`: the gamepad report, or the keyboard one in
+/// keyboard mode
+#[cfg(not(feature = "keyboard"))]
+type HidReport = ButtonBoxReport;
+#[cfg(feature = "keyboard")]
+type HidReport = keyboard::Report;
+
+/// Every class of the USB device, so the main loop and `detach_usb()` poll
+/// the same list
+struct UsbInterfaces<'a> {
+    // Answers the host's feature report reads and mapping writes of the
+    // gamepad interface
+    feature_report: FeatureReport,
+    // Tracks the protocol the host has the keyboard in
+    #[cfg(feature = "keyboard")]
+    boot_protocol: boot_protocol::BootProtocol,
+    // The gamepad, or the keyboard in keyboard mode
+    hid: HIDClass<'a, UsbBus>,
+    consumer_hid: HIDClass<'a, UsbBus>,
+    #[cfg(not(feature = "keyboard"))]
+    keys_hid: HIDClass<'a, UsbBus>,
+    mouse_hid: HIDClass<'a, UsbBus>,
+    serial: SerialPort<'a, UsbBus>,
+}
+
+impl UsbInterfaces<'_> {
+    /// Poll `usb_dev` with every class
+    ///
+    /// The feature report goes first so it sees GET_REPORT before `hid`
+    /// rejects it, as does the protocol tracking in keyboard mode.
+    fn poll(&mut self, usb_dev: &mut UsbDevice<UsbBus>) {
+        #[cfg(not(feature = "keyboard"))]
+        usb_dev.poll(&mut [
+            &mut self.feature_report,
+            &mut self.hid,
+            &mut self.consumer_hid,
+            &mut self.keys_hid,
+            &mut self.mouse_hid,
+            &mut self.serial,
+        ]);
+        #[cfg(feature = "keyboard")]
+        usb_dev.poll(&mut [
+            &mut self.feature_report,
+            &mut self.boot_protocol,
+            &mut self.hid,
+            &mut self.consumer_hid,
+            &mut self.mouse_hid,
+            &mut self.serial,
+        ]);
+    }
 
-        ButtonBoxReport {
-            buttons,
-            padding: 0,
+    /// Push `report` on `hid`, in the boot format while the host has an
+    /// N-key rollover keyboard in boot protocol
+    fn push_report(&mut self, report: &HidReport) -> usb_device::Result<usize> {
+        #[cfg(all(feature = "keyboard", not(feature = "keyboard-6kro")))]
+        if self.boot_protocol.is_boot() {
+            return self.hid.push_input(&report.boot_report());
         }
+        self.hid.push_input(report)
     }
+}
 
-    fn has_changed(&mut self) -> bool {
-        let current_report = self.read_buttons();
-        let changed = current_report.buttons != self.last_report.buttons;
-        self.last_report = current_report;
-        changed
+/// Release every button on the host, then disconnect from the bus
+///
+/// An all-released report goes out on every HID interface: `hid` (in the
+/// boot format if the host asked for it), the consumer, key and mouse
+/// interfaces, with every class kept polled for up to `DETACH_FLUSH_MS` so
+/// the host takes them. Then the pull-up on D+ is turned off and the host
+/// sees the device unplugged, rather than a report cut off by the reset.
+fn detach_usb(usb_dev: &mut UsbDevice<UsbBus>, usb: &mut UsbInterfaces, timer: &Timer) {
+    let start = now_ms(timer);
+    // Interfaces still to take their released report: the gamepad (or
+    // keyboard), consumer, keys and mouse, a bit each
+    #[cfg(not(feature = "keyboard"))]
+    let mut pending = 0b1111u8;
+    #[cfg(feature = "keyboard")]
+    let mut pending = 0b1011u8;
+    let still = mouse::MouseReport {
+        buttons: 0,
+        x: 0,
+        y: 0,
+        wheel: 0,
+        pan: 0,
+    };
+    while pending != 0 && now_ms(timer).wrapping_sub(start) < DETACH_FLUSH_MS {
+        usb.poll(usb_dev);
+        if pending & 0b0001 != 0 && usb.push_report(&released_report()).is_ok() {
+            pending &= !0b0001;
+        }
+        if pending & 0b0010 != 0
+            && usb
+                .consumer_hid
+                .push_input(&consumer::consumer_report(0))
+                .is_ok()
+        {
+            pending &= !0b0010;
+        }
+        #[cfg(not(feature = "keyboard"))]
+        if pending & 0b0100 != 0 && usb.keys_hid.push_input(&keys::key_map_report(0)).is_ok() {
+            pending &= !0b0100;
+        }
+        if pending & 0b1000 != 0 && usb.mouse_hid.push_input(&still).is_ok() {
+            pending &= !0b1000;
+        }
     }
 
-    fn get_report(&self) -> ButtonBoxReport {
-        self.last_report
+    set_usb_pullup(false);
+    let mut delay = *timer;
+    delay.delay_ms(DETACH_MS);
+}
+
+/// Report with every button released, on the interface `hid` carries
+#[cfg(not(feature = "keyboard"))]
+fn released_report() -> ButtonBoxReport {
+    ButtonBoxReport::new(0)
+}
+
+#[cfg(feature = "keyboard")]
+fn released_report() -> keyboard::Report {
+    keyboard::key_report(None)
+}
+
+/// Carry out a console command against the runtime configuration
+///
+/// `bootloader` is set for a command that needs the USB device torn down,
+/// which the main loop owns.
+fn run_command(
+    command: Command,
+    console: &mut Console,
+    config: &mut Config,
+    button_box: &mut Buttons,
+    invert_report: &mut bool,
+    bootloader: &mut bool,
+    serial: &mut SerialPort<UsbBus>,
+) {
+    match command {
+        Command::Set {
+            input,
+            button,
+            extra_buttons,
+        } => {
+            config.buttons[input].button = button;
+            config.buttons[input].extra_buttons = extra_buttons;
+            button_box.set_config(config.buttons);
+            console::write_str(serial, "ok\r\n");
+        }
+        Command::Enable { input, enabled } => {
+            config.buttons[input].enabled = enabled;
+            button_box.set_config(config.buttons);
+            if enabled {
+                console::write_str(serial, "input enabled\r\n");
+            } else {
+                console::write_str(serial, "input disabled\r\n");
+            }
+        }
+        Command::Dump => console::write_config(serial, config),
+        Command::Save => {
+            config::save_config(config);
+            console::write_str(serial, "saved\r\n");
+        }
+        Command::Diag => console.start_diagnostics(),
+        Command::Version => console::write_version(serial),
+        Command::Bootloader => {
+            console::write_str(serial, "rebooting to bootloader\r\n");
+            *bootloader = true;
+        }
+        Command::Counts => console::write_counts(serial, button_box.press_counts()),
+        Command::ResetCounts => {
+            button_box.reset_press_counts();
+            console::write_str(serial, "counts reset\r\n");
+        }
+        Command::Press {
+            button,
+            duration_ms,
+        } => {
+            button_box.simulate_press(button, duration_ms);
+            console::write_str(serial, "pressed\r\n");
+        }
+        Command::Record { start: true } => {
+            button_box.start_recording();
+            console::write_str(serial, "recording\r\n");
+        }
+        Command::Record { start: false } => match button_box.stop_recording() {
+            Some(changes) => console::write_recorded(serial, changes),
+            None => console::write_str(serial, "not recording\r\n"),
+        },
+        Command::InvertReport => {
+            *invert_report = !*invert_report;
+            if *invert_report {
+                console::write_str(serial, "report inverted\r\n");
+            } else {
+                console::write_str(serial, "report normal\r\n");
+            }
+        }
     }
 }
 
 #[entry]
 fn main() -> ! {
-    info!("Button Box starting...");
+    info!(
+        "Button Box starting, firmware {}",
+        device_info::FIRMWARE_VERSION
+    );
 
     let mut pac = pac::Peripherals::take().unwrap();
-    let core = pac::CorePeripherals::take().unwrap();
+
+    // Read why the box reset before the watchdog is restarted, and catch a
+    // sagging supply from here on
+    let reset_reason = reset::reset_reason(&pac.VREG_AND_CHIP_RESET, &pac.WATCHDOG);
+    info!("Last reset: {}", reset_reason.description());
+    reset::enable_brown_out(&mut pac.VREG_AND_CHIP_RESET);
+
     let mut watchdog = Watchdog::new(pac.WATCHDOG);
     let sio = Sio::new(pac.SIO);
 
-    // External high-speed crystal on the pico board is 12Mhz
-    let external_xtal_freq_hz = 12_000_000u32;
-    let clocks = init_clocks_and_plls(
-        external_xtal_freq_hz,
+    // System clock at `SYS_CLOCK_HZ` from the Pico's 12MHz crystal, and USB
+    // at 48MHz whatever that is
+    let clocks = clock::init_clocks(
         pac.XOSC,
         pac.CLOCKS,
         pac.PLL_SYS,
         pac.PLL_USB,
+        &mut pac.VREG_AND_CHIP_RESET,
         &mut pac.RESETS,
         &mut watchdog,
     )
     .ok()
     .unwrap();
+    info!("System clock {}MHz", SYS_CLOCK_HZ / 1_000_000);
 
     let pins = bsp::Pins::new(
         pac.IO_BANK0,
@@ -125,13 +1151,167 @@ fn main() -> ! {
         &mut pac.RESETS,
     );
 
-    // Configure button pins with pull-up resistors
-    // Button 1 on GPIO14, Button 2 on GPIO15
-    let button1 = pins.gpio14.into_pull_up_input();
-    let button2 = pins.gpio15.into_pull_up_input();
+    // Configure button pins as inputs, in `BUTTON_MAP` order. They start
+    // pulled up; `ButtonBox::new()` switches each to its configured pull.
+    let buttons: [ButtonPin; DIRECT_BUTTONS] = [
+        pins.gpio14
+            .into_pull_up_input()
+            .into_dyn_pin()
+            .into_pull_type(),
+        pins.gpio15
+            .into_pull_up_input()
+            .into_dyn_pin()
+            .into_pull_type(),
+    ];
+
+    // Button matrix rows and columns, e.g. rows on GPIO18 and GPIO19 and
+    // columns on GPIO20 and GPIO21, in place of the host LEDs, for a 2x2 keypad
+    let rows: [RowPin; MATRIX_ROWS] = [];
+    let columns: [ColumnPin; MATRIX_COLS] = [];
+    let matrix = Matrix::new(rows, columns, MATRIX_GHOST_FILTER);
+
+    // MCP23017 expander on I2C0, SDA on GPIO0 and SCL on GPIO1
+    let i2c = bsp::hal::I2C::i2c0(
+        pac.I2C0,
+        pins.gpio0.reconfigure(),
+        pins.gpio1.reconfigure(),
+        400.kHz(),
+        &mut pac.RESETS,
+        &clocks.system_clock,
+    );
+    let expander = Expander::new(i2c);
+
+    // SSD1306 OLED on I2C1, SDA on GPIO6 and SCL on GPIO7
+    let display_i2c = bsp::hal::I2C::i2c1(
+        pac.I2C1,
+        pins.gpio6.reconfigure(),
+        pins.gpio7.reconfigure(),
+        400.kHz(),
+        &mut pac.RESETS,
+        &clocks.system_clock,
+    );
+    let mut display = display::Display::new(display_i2c);
+
+    // 74HC165 shift register: SH/LD on GPIO2, CLK on GPIO3, QH on GPIO4
+    let shift_register = ShiftRegister::new(
+        pins.gpio2.into_push_pull_output().into_dyn_pin(),
+        pins.gpio3.into_push_pull_output().into_dyn_pin(),
+        pins.gpio4.into_pull_up_input().into_dyn_pin(),
+    );
+
+    // Capacitive touch pads, each with a 1MΩ resistor to ground, e.g. on
+    // GPIO18-GPIO21 in place of the host LEDs. Their baselines are measured
+    // here, so keep clear of them while the box starts.
+    let touch_pads: [TouchPin; TOUCH_BUTTONS] = [];
+    let touch = TouchPads::new(touch_pads, TOUCH);
+
+    // Create button box instance with the mapping stored in flash as the
+    // first profile
+    let mut config = config::load_config();
+    let mut profiles = PROFILES;
+    profiles[0] = config.buttons;
+    let combos = ComboTable::new(COMBOS, COMBO_WINDOW_MS);
+    let scanned = ScannedInputs {
+        matrix,
+        expander,
+        shift_register,
+        touch,
+    };
+    let mut button_box = ButtonBox::new(
+        buttons,
+        scanned,
+        combos,
+        profiles,
+        PROFILE_BUTTON,
+        BUTTON_TIMING,
+        MacroRecorder::new(PLAY_BUTTON),
+    );
+
+    // Free-running 1MHz timer, the millisecond tick for every time-based
+    // feature and the loop delay
+    let timer = Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
+
+    // The pulls are applied now, so the inputs can be checked for the
+    // bootloader combo before anything else starts
+    check_bootsel_combo(&mut button_box, &timer);
+    button_box.enable_interrupts();
+
+    // Rotary encoder phases A and B on GPIO16 and GPIO17, and its push
+    // switch on GPIO9 (pass `None` for an encoder without one)
+    let mut encoder = Encoder::new(
+        pins.gpio16.into_pull_up_input().into_dyn_pin(),
+        pins.gpio17.into_pull_up_input().into_dyn_pin(),
+        Some(pins.gpio9.into_pull_up_input().into_dyn_pin()),
+        ENCODER_ACCELERATION,
+    );
+
+    // Hat switch up, right, down and left on GPIO10-GPIO13
+    let mut hat = Hat::new(
+        pins.gpio10.into_pull_up_input().into_dyn_pin(),
+        pins.gpio11.into_pull_up_input().into_dyn_pin(),
+        pins.gpio12.into_pull_up_input().into_dyn_pin(),
+        pins.gpio13.into_pull_up_input().into_dyn_pin(),
+    );
+
+    // Host-controlled status LEDs on GPIO18-GPIO21
+    let mut led_bank = LedBank::new([
+        pins.gpio18.into_push_pull_output().into_dyn_pin(),
+        pins.gpio19.into_push_pull_output().into_dyn_pin(),
+        pins.gpio20.into_push_pull_output().into_dyn_pin(),
+        pins.gpio21.into_push_pull_output().into_dyn_pin(),
+    ]);
+
+    // Indicator LEDs, each with the logical button it follows, e.g. GPIO18-GPIO21
+    // for buttons 1-4 in place of the host LEDs
+    let button_leds: [(LedPin, usize); BUTTON_LEDS] = [];
+    let mut led_map = LedMap::new(button_leds);
+
+    // USB activity LED on GPIO28, flashed by every gamepad report sent,
+    // unless the pin reads the slider
+    #[cfg(not(feature = "slider"))]
+    let mut activity_led = ActivityLed::new(pins.gpio28.into_push_pull_output().into_dyn_pin());
+
+    // Rumble motor transistor on GPIO8, driven by PWM slice 4 channel A, and
+    // the onboard LED on GPIO25 dimmed by channel B of the same slice
+    let pwm_slices = Slices::new(pac.PWM, &mut pac.RESETS);
+    let mut pwm4 = pwm_slices.pwm4;
+    rumble::start_slice(&mut pwm4, clocks.system_clock.freq().to_Hz());
+    pwm4.channel_a.output_to(pins.gpio8);
+    pwm4.channel_b.output_to(pins.led);
+    let mut rumble = Rumble::new(pwm4.channel_a, RUMBLE_MAX_INTENSITY);
+
+    // The onboard LED blinks until the host configures the device
+    let mut status_led = StatusLed::new(BreathingLed::new(pwm4.channel_b));
 
-    // Create button box instance
-    let mut button_box = ButtonBox::new(button1, button2);
+    // Piezo buzzer on GPIO5, driven by PWM slice 2 channel B
+    let mut pwm2 = pwm_slices.pwm2;
+    pwm2.channel_b.output_to(pins.gpio5);
+    let mut buzzer = Buzzer::new(pwm2, BUZZER, clocks.system_clock.freq().to_Hz());
+
+    // WS2812 strip data on GPIO22, driven by a PIO0 state machine
+    let (mut pio, sm0, _, _, _) = pac.PIO0.split(&mut pac.RESETS);
+    let mut strip: Neopixel<PIXEL_COUNT> = Neopixel::new(
+        pins.gpio22.into_function(),
+        &mut pio,
+        sm0,
+        clocks.system_clock.freq(),
+    );
+
+    // Thumbstick X on GPIO26 (ADC0) and Y on GPIO27 (ADC1)
+    let mut adc = Adc::new(pac.ADC, &mut pac.RESETS);
+    let mut joystick = Joystick::new(
+        AdcPin::new(pins.gpio26.into_floating_input()).unwrap(),
+        AdcPin::new(pins.gpio27.into_floating_input()).unwrap(),
+        X_AXIS,
+        Y_AXIS,
+    );
+
+    // Slider on GPIO28 (ADC2)
+    #[cfg(feature = "slider")]
+    let mut slider = Slider::new(
+        AdcPin::new(pins.gpio28.into_floating_input()).unwrap(),
+        SLIDER,
+    );
 
     // Set up USB
     let usb_bus = UsbBusAllocator::new(UsbBus::new(
@@ -142,49 +1322,775 @@ fn main() -> ! {
         &mut pac.RESETS,
     ));
 
-    // Create HID class
-    let mut hid = HIDClass::new(&usb_bus, ButtonBoxReport::desc(), 1);
+    // Create HID class, as a gamepad or, with the `keyboard` feature, a keyboard
+    #[cfg(not(feature = "keyboard"))]
+    let descriptor = ButtonBoxReport::desc();
+    #[cfg(not(feature = "keyboard"))]
+    let hid = HIDClass::new(&usb_bus, descriptor, HID_POLL_MS);
+    // The keyboard declares itself a boot keyboard so BIOSes pick it up.
+    // `BootProtocol` tracks the protocol instead of `HIDClass`, which is
+    // forced to boot mode only so it never blocks a report.
+    #[cfg(feature = "keyboard")]
+    let hid = HIDClass::new_with_settings(
+        &usb_bus,
+        keyboard::Report::desc(),
+        HID_POLL_MS,
+        HidClassSettings {
+            subclass: HidSubClass::Boot,
+            protocol: HidProtocol::Keyboard,
+            config: ProtocolModeConfig::ForceBoot,
+            locale: HidCountryCode::NotSupported,
+        },
+    );
+    #[cfg(feature = "keyboard")]
+    let boot_protocol = boot_protocol::BootProtocol::new(0);
+
+    // Answers the host's feature report reads and mapping writes of the
+    // gamepad interface
+    let feature_report = FeatureReport::new(0);
+
+    // Media keys go through their own HID interface next to the gamepad
+    let consumer_hid = HIDClass::new(&usb_bus, ConsumerControlReport::desc(), HID_POLL_MS);
+
+    // Buttons in `keys::KEY_MAP` type keys on a keyboard interface next to
+    // the gamepad. It has no LEDs, so only needs an IN endpoint.
+    #[cfg(not(feature = "keyboard"))]
+    let keys_hid = HIDClass::new_ep_in(&usb_bus, keys::KeyboardReport::desc(), HID_POLL_MS);
+
+    // Buttons in `mouse::MOUSE_MAP` move the cursor through a mouse interface
+    let mouse_hid = HIDClass::new_ep_in(&usb_bus, mouse::MouseReport::desc(), HID_POLL_MS);
+    let mut mouse = mouse::Mouse::new();
+
+    // Create CDC serial class for the configuration console
+    let serial = SerialPort::new(&usb_bus);
+
+    let mut usb = UsbInterfaces {
+        feature_report,
+        #[cfg(feature = "keyboard")]
+        boot_protocol,
+        hid,
+        consumer_hid,
+        #[cfg(not(feature = "keyboard"))]
+        keys_hid,
+        mouse_hid,
+        serial,
+    };
+    let mut console = Console::new();
+
+    // Read before the builder, which keeps the string borrowed for as long as
+    // the device exists
+    let serial_number = device_info::serial_number();
+    info!("Serial number: {}", serial_number.as_str());
+
+    // Building the device connects the D+ pull-up, so the host only sees the
+    // box once the bus has settled
+    let mut vbus = pins.vbus_detect.into_floating_input();
+    wait_for_usb_bus(&mut vbus, &timer);
 
     // Create USB device
-    let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x16c0, 0x27dd))
-        .strings(&[StringDescriptors::default()
-            .manufacturer("Button Box Co")
-            .product("2-Button Box")
-            .serial_number("001")])
-        .unwrap()
-        .device_class(0x00) // Use interface-specific class
-        .build();
+    let mut usb_dev =
+        UsbDeviceBuilder::new(&usb_bus, UsbVidPid(device_info::VID, device_info::PID))
+            .strings(&[StringDescriptors::default()
+                .manufacturer(device_info::MANUFACTURER)
+                .product(device_info::PRODUCT)
+                .serial_number(&serial_number)])
+            .unwrap()
+            .composite_with_iads() // HID and CDC interfaces in one device
+            .supports_remote_wakeup(true)
+            .build();
 
-    let mut delay = cortex_m::delay::Delay::new(core.SYST, clocks.system_clock.freq().to_Hz());
+    // Loop pacing waits on the same timer as `now_ms()`, rather than counting
+    // SysTick cycles
+    let mut delay = timer;
+
+    // Button edges are delivered through the bank 0 GPIO interrupt
+    unsafe {
+        pac::NVIC::unmask(pac::Interrupt::IO_IRQ_BANK0);
+    }
+
+    // Reset the board if the main loop stops running, but not while halted
+    // in a debugger
+    watchdog.pause_on_debug(true);
+    watchdog.start(WATCHDOG_TIMEOUT_MS.millis());
 
     info!("Button Box ready!");
 
+    // Logs each press and release with its timestamp
+    let mut telemetry = Telemetry::new();
+    // Plays the macro of each macro button pressed, and the macro buttons
+    // held on the last loop, to find their press edges
+    #[cfg(feature = "keyboard")]
+    let mut macro_player = macro_player::MacroPlayer::new(macro_player::MACRO_POLICY);
+    #[cfg(feature = "keyboard")]
+    let mut macro_held = 0u32;
+    // Macro playing and key carried by the last report
+    #[cfg(feature = "keyboard")]
+    let mut macro_state = (false, None);
+    // Protocol the keyboard was in on the last loop, to log switches
+    #[cfg(feature = "keyboard")]
+    let mut was_boot = false;
+    // Encoder button bits carried by the last report, cleared once it's sent
+    let mut encoder_pulse = 0u32;
+    // Direction and number of encoder increments still to be pulsed
+    let mut encoder_queue = (Direction::Clockwise, 0u8);
+    // Encoder switch button bit carried by the last report
+    let mut encoder_switch = 0u32;
+    // Axis values carried by the last report
+    let mut axes = (AXIS_CENTER, AXIS_CENTER);
+    #[cfg(feature = "slider")]
+    let mut slider_position = 0;
+    #[cfg(feature = "slider")]
+    let mut slider_button = 0u32;
+    // Hat direction carried by the last report
+    let mut hat_direction = HAT_CENTERED;
+    // Media key buttons carried by the last consumer report
+    let mut consumer_buttons = 0u32;
+    // Key buttons carried by the last report of the keyboard next to the
+    // gamepad
+    #[cfg(not(feature = "keyboard"))]
+    let mut key_buttons = 0u32;
+    // Profile last signalled on the activity LED
+    let mut shown_profile = 0;
+    // Debounced inputs as of the last buzzer click check
+    let mut clicked_inputs = 0u16;
+    // Tick (ms) of the last input change, to time the idle timeout
+    let mut last_input_ms = 0u32;
+    // Whether the gamepad state changed since the last report was pushed,
+    // and the tick (ms) at which the host last took one
+    let mut report_due = false;
+    let mut report_sent_ms = 0u32.wrapping_sub(MIN_REPORT_INTERVAL_MS);
+    // Whether the reported buttons are inverted, a diagnostic toggled from
+    // the console
+    let mut invert_report = false;
+    // Tick (ms) since which the host hasn't taken a queued gamepad report,
+    // while one is waiting
+    let mut blocked_since: Option<u32> = None;
+    // Reports built but not taken by the host yet, oldest first, so a
+    // transition made while it's busy waits instead of being overwritten
+    let mut report_queue: ReportQueue<HidReport, ReportState, REPORT_QUEUE_LEN> =
+        ReportQueue::new();
+    // Whether the bus was suspended on the previous loop, and the tick (ms)
+    // at which the current suspend began
+    let mut was_suspended = false;
+    let mut suspended_since = 0u32;
+    // Whether a button changed during the current suspend
+    let mut wake_host = false;
+    // Whether the device was in the post-reset `Default` state on the
+    // previous loop (as it is before the first enumeration), and whether an
+    // all-released report must go out before the current state
+    let mut was_reset = true;
+    let mut release_pending = false;
+    // Tick (ms) at which the reset combo was pressed, while it is held
+    let mut reset_held_since: Option<u32> = None;
+    // Tick (ms) at which the current attempt at enumeration began, until the
+    // host configures the device, and the attempts in a row that timed out
+    let mut enumerating_since = Some(now_ms(&timer));
+    let mut enumeration_failures = 0u32;
+
     loop {
-        // Poll USB device
-        if usb_dev.poll(&mut [&mut hid]) {
-            // Check if buttons have changed
-            if button_box.has_changed() {
-                let report = button_box.get_report();
-                info!("Button state changed: {}", report.buttons);
-
-                // Send HID report
-                match hid.push_input(&report) {
+        let now_ms = now_ms(&timer);
+
+        // A configured host that stops taking reports means the USB stack is
+        // wedged, so stop feeding the watchdog and let it reset the board.
+        // While suspended the host takes none, so that doesn't count.
+        let usb_stalled = usb_dev.state() == UsbDeviceState::Configured
+            && blocked_since.is_some_and(|since| now_ms.wrapping_sub(since) >= USB_STALL_MS);
+        if usb_stalled {
+            error!("USB stopped responding, resetting");
+            loop {
+                cortex_m::asm::wfi();
+            }
+        }
+        watchdog.feed();
+
+        usb.poll(&mut usb_dev);
+
+        // A host that doesn't configure the device in time gets it
+        // reconnected, and once that has failed `ENUMERATION_ATTEMPTS` times
+        // in a row the box stops trying, rather than looping silently
+        if usb_dev.state() == UsbDeviceState::Configured {
+            enumerating_since = None;
+            enumeration_failures = 0;
+        } else if enumerating_since
+            .is_some_and(|since| now_ms.wrapping_sub(since) >= ENUMERATION_TIMEOUT_MS)
+        {
+            enumeration_failures += 1;
+            if enumeration_failures >= ENUMERATION_ATTEMPTS {
+                error!(
+                    "USB not configured after {} attempts, entering safe mode",
+                    enumeration_failures
+                );
+                safe_mode(
+                    &timer,
+                    &mut watchdog,
+                    &mut status_led,
+                    &mut rumble,
+                    &mut buzzer,
+                );
+            }
+            warn!(
+                "USB not configured within {}ms, reconnecting (attempt {} of {})",
+                ENUMERATION_TIMEOUT_MS,
+                enumeration_failures + 1,
+                ENUMERATION_ATTEMPTS
+            );
+            reconnect_usb(&timer);
+            enumerating_since = Some(now_ms.wrapping_add(DETACH_MS));
+        }
+
+        // The host ignores reports while the bus is suspended, so none are
+        // sent, and the current state is sent again on resume to re-sync it
+        let suspended = usb_dev.state() == UsbDeviceState::Suspend;
+        let resumed = was_suspended && !suspended;
+        if suspended != was_suspended {
+            if suspended {
+                info!("USB suspended");
+                suspended_since = now_ms;
+            } else {
+                info!("USB resumed");
+            }
+            was_suspended = suspended;
+        }
+
+        // A bus reset (the host rebooting, or the cable re-plugged) or a
+        // resume may leave the host holding buttons from before it, so the
+        // first report once the host is back releases everything, and the
+        // current state follows it
+        let reset = usb_dev.state() == UsbDeviceState::Default;
+        if reset && !was_reset {
+            info!("USB reset");
+            // The host enumerates the device again after a reset
+            enumerating_since.get_or_insert(now_ms);
+            release_pending = true;
+            report_queue.clear();
+            blocked_since = None;
+            // Nothing is held on the other interfaces after a reset, so
+            // whatever is held now is sent again once configured
+            consumer_buttons = 0;
+            #[cfg(not(feature = "keyboard"))]
+            {
+                key_buttons = 0;
+            }
+        }
+        was_reset = reset;
+        // Reports from before the suspend are stale too, the release and the
+        // current state replace them
+        if resumed {
+            release_pending = true;
+            report_queue.clear();
+            blocked_since = None;
+        }
+
+        let read_fault = button_box.failed_reads() >= READ_FAULT_THRESHOLD;
+        if read_fault != status_led.fault() {
+            error!(
+                "Button read fault: {}, {} errors",
+                read_fault,
+                button_box.read_errors()
+            );
+        }
+        panic_context::record(button_box.inputs(), button_box.buttons(), now_ms);
+        status_led.set_fault(read_fault);
+        status_led.set_active(button_box.inputs() != 0);
+        status_led.set_suspended(suspended);
+        status_led.update(usb_dev.state() == UsbDeviceState::Configured, now_ms);
+        #[cfg(not(feature = "slider"))]
+        activity_led.update(now_ms);
+
+        // Pass queued log messages on to the serial port, then handle
+        // configuration commands typed on it
+        serial_log::flush(&mut usb.serial);
+        match console.poll(&mut usb.serial) {
+            Some(Ok(command)) => {
+                let was_inverted = invert_report;
+                let mut bootloader = false;
+                run_command(
+                    command,
+                    &mut console,
+                    &mut config,
+                    &mut button_box,
+                    &mut invert_report,
+                    &mut bootloader,
+                    &mut usb.serial,
+                );
+                // Show the host the flipped state straight away
+                report_due |= invert_report != was_inverted;
+
+                // Release everything and detach first, like the reset combo,
+                // so the host sees a clean unplug rather than the bus going
+                // quiet mid-report
+                if bootloader {
+                    info!("Bootloader requested, rebooting to USB boot");
+                    detach_usb(&mut usb_dev, &mut usb, &timer);
+                    bsp::hal::rom_data::reset_to_usb_boot(0, 0);
+                }
+            }
+            Some(Err(message)) => {
+                console::write_str(&mut usb.serial, "error: ");
+                console::write_str(&mut usb.serial, message);
+                console::write_str(&mut usb.serial, "\r\n");
+            }
+            None => {}
+        }
+
+        // Blink the status LED if the host asked the box to identify itself
+        if usb.feature_report.take_identify() {
+            info!("Host asked the box to identify itself");
+            status_led.identify(now_ms);
+        }
+
+        // Apply a mapping the host wrote through the feature report
+        if let Some(mapping) = usb.feature_report.take_mapping() {
+            info!(
+                "Host mapped input {} to button {}",
+                mapping.input,
+                mapping.button + 1
+            );
+            config.buttons[mapping.input].button = mapping.button;
+            config.buttons[mapping.input].extra_buttons = 0;
+            button_box.set_config(config.buttons);
+            if mapping.save {
+                config::save_config(&config);
+            }
+        }
+
+        // And a routing, which the reports pick up as they're next built
+        let mut rerouted = false;
+        if let Some(route) = usb.feature_report.take_route() {
+            if route.keyboard {
+                info!("Host routed button {} to the keyboard", route.button + 1);
+                config.keyboard_buttons |= 1 << route.button;
+            } else {
+                info!("Host routed button {} to the gamepad", route.button + 1);
+                config.keyboard_buttons &= !(1 << route.button);
+            }
+            if route.save {
+                config::save_config(&config);
+            }
+            rerouted = true;
+        }
+
+        // Apply any output report the host has sent: the LED bits, then the
+        // rumble intensity (the keyboard report only has the LED byte)
+        let mut output = [0u8; ButtonBoxReport::OUTPUT_LEN];
+        if let Ok(len) = usb.hid.pull_raw_output(&mut output) {
+            if len > 0 {
+                debug!("LED output report: {=u8:#04x}", output[0]);
+                led_bank.set(output[0]);
+            }
+            if len > 1 {
+                debug!("Rumble intensity: {=u8}", output[1]);
+                rumble.set(output[1]);
+            }
+        }
+        // A host that can't send output reports can't stop the motor either
+        if usb_dev.state() != UsbDeviceState::Configured {
+            rumble.ramp_down(now_ms);
+        }
+
+        // A rerouted button that's held leaves one report and joins the other
+        let mut send_report = resumed || rerouted;
+
+        // Only read the pins after an edge, or while a change or event is pending
+        if BUTTONS_DIRTY.load(Ordering::Acquire) || button_box.needs_update() {
+            // Clear before reading so an edge during the read is not lost
+            BUTTONS_DIRTY.store(false, Ordering::Release);
+
+            // Check if buttons have changed. The state machine has no log of
+            // its own, so its failures are reported from here
+            let changed = button_box.has_changed(now_ms);
+            for index in 0..DIRECT_BUTTONS {
+                if button_box.failed_pins() & (1 << index) != 0 {
+                    warn!(
+                        "Button {} read failed ({} errors)",
+                        index + 1,
+                        button_box.read_errors()
+                    );
+                }
+            }
+            if let Some(changes) = button_box.take_recording_overflow() {
+                warn!("Macro recording full after {} changes, stopped", changes);
+            }
+            if changed {
+                let buttons = button_box.buttons();
+                debug!("Button state changed: {=u32:#010x}", buttons);
+                telemetry.log_buttons(buttons, now_ms);
+                usb.feature_report
+                    .set_buttons(buttons & !routed_buttons(&config));
+                send_report = true;
+                wake_host |= suspended;
+
+                // Light the strip LED of every pressed button
+                for index in 0..PIXEL_COUNT {
+                    let pressed = buttons & (1 << index) != 0;
+                    let color = if pressed {
+                        PRESSED_COLOR
+                    } else {
+                        RGB8::default()
+                    };
+                    strip.set_led(index, color);
+                }
+                strip.show();
+                led_map.update(buttons);
+            }
+        }
+
+        // Blink the activity LED once per profile number on a switch, and
+        // read the inputs through the new mapping straight away
+        if button_box.profile() != shown_profile {
+            shown_profile = button_box.profile();
+            info!("Profile {} active", shown_profile + 1);
+            #[cfg(not(feature = "slider"))]
+            activity_led.signal(shown_profile as u8 + 1, now_ms);
+            BUTTONS_DIRTY.store(true, Ordering::Release);
+        }
+
+        // Redraw a box of the OLED while it lags behind the buttons
+        display.update(button_box.buttons(), now_ms);
+
+        // Click on every new press of a physical input
+        let inputs = button_box.inputs();
+        if inputs & !clicked_inputs != 0 {
+            buzzer.click(now_ms);
+        }
+        clicked_inputs = inputs;
+        buzzer.update(now_ms);
+
+        // Wake a sleeping host on a button change, if it allowed the box to,
+        // waiting out the minimum suspend first. The report goes out once
+        // the bus is back.
+        wake_host &= suspended;
+        let suspended_ms = now_ms.wrapping_sub(suspended_since);
+        if wake_host
+            && usb_dev.remote_wakeup_enabled()
+            && suspended_ms >= REMOTE_WAKEUP_MIN_SUSPEND_MS
+        {
+            info!("Signalling remote wakeup");
+            usb_dev.bus().remote_wakeup();
+            wake_host = false;
+        }
+
+        // Re-send the state in the new format when the host switches protocol
+        #[cfg(feature = "keyboard")]
+        if usb.boot_protocol.is_boot() != was_boot {
+            was_boot = usb.boot_protocol.is_boot();
+            if was_boot {
+                info!("Host switched the keyboard to boot protocol");
+            } else {
+                info!("Keyboard back in report protocol");
+            }
+            send_report = true;
+        }
+
+        // Macros type over many reports, so advance playback every loop
+        #[cfg(feature = "keyboard")]
+        {
+            let held = button_box.buttons() & macro_player::MACRO_BUTTONS;
+            macro_player.trigger(held & !macro_held, now_ms);
+            macro_held = held;
+
+            let key = macro_player.poll(now_ms);
+            let state = (macro_player.is_playing(), key);
+            if state != macro_state {
+                macro_state = state;
+                send_report = true;
+            }
+        }
+
+        // Each encoder increment becomes a press of its button for a single
+        // report, then a release, so an accelerated detent queues several,
+        // or in axis mode moves the mouse. The push switch is reported for
+        // as long as it's held.
+        let event = encoder.poll(now_ms);
+        if let (EncoderMode::RelativeAxis { x, y }, Some(direction)) =
+            (ENCODER_MODE, event.rotation)
+        {
+            let increments = match direction {
+                Direction::Clockwise => i16::from(event.increments),
+                Direction::CounterClockwise => -i16::from(event.increments),
+            };
+            mouse.add_encoder(increments * i16::from(x), increments * i16::from(y));
+        } else if let Some(direction) = event.rotation {
+            // Reversing drops increments still queued the other way
+            if encoder_queue.0 != direction {
+                encoder_queue = (direction, 0);
+            }
+            encoder_queue.1 = encoder_queue.1.saturating_add(event.increments);
+        }
+        // A pulse only starts or ends once every report before it has gone
+        // out, so each one is set in a transmitted report and released in
+        // the next, however slowly the host polls; increments that come in
+        // meanwhile wait in the queue
+        let transmitted = report_queue.is_empty() && !report_due;
+        let pulse = if !transmitted {
+            encoder_pulse
+        } else if encoder_pulse != 0 || encoder_queue.1 == 0 {
+            0
+        } else {
+            encoder_queue.1 -= 1;
+            match encoder_queue.0 {
+                Direction::Clockwise => 1 << ENCODER_CW_BUTTON,
+                Direction::CounterClockwise => 1 << ENCODER_CCW_BUTTON,
+            }
+        };
+        if pulse != encoder_pulse {
+            encoder_pulse = pulse;
+            send_report = true;
+        }
+        let switch = if event.pressed {
+            1 << ENCODER_SWITCH_BUTTON
+        } else {
+            0
+        };
+        if switch != encoder_switch {
+            encoder_switch = switch;
+            send_report = true;
+        }
+
+        // Report the stick whenever it moves to a new position
+        let position = joystick.read(&mut adc);
+        if position != axes {
+            axes = position;
+            send_report = true;
+        }
+
+        // And the slider whenever it moves
+        #[cfg(feature = "slider")]
+        {
+            let position = slider.read(&mut adc);
+            if position != slider_position {
+                slider_position = position;
+                send_report = true;
+            }
+            let button = if slider.pressed() {
+                1 << SLIDER_BUTTON
+            } else {
+                0
+            };
+            if button != slider_button {
+                slider_button = button;
+                send_report = true;
+            }
+        }
+
+        // Report the hat whenever it points somewhere new
+        let direction = hat.read();
+        if direction != hat_direction {
+            hat_direction = direction;
+            send_report = true;
+        }
+
+        // Send a consumer report whenever a media key button changes, retrying
+        // on later loops until the host takes it
+        let media = button_box.buttons() & CONSUMER_BUTTONS;
+        if !suspended && (media != consumer_buttons || resumed) {
+            match usb
+                .consumer_hid
+                .push_input(&consumer::consumer_report(media))
+            {
+                Ok(_) => {
+                    debug!("Consumer report sent: {=u32:#010x}", media);
+                    consumer_buttons = media;
+                }
+                Err(UsbError::WouldBlock) => {}
+                Err(_e) => {
+                    warn!("Failed to send consumer report");
+                }
+            }
+        }
+
+        // Likewise a keyboard report whenever a mapped key button changes
+        #[cfg(not(feature = "keyboard"))]
+        {
+            let pressed = button_box.buttons() & config.keyboard_buttons;
+            if !suspended && (pressed != key_buttons || resumed) {
+                match usb.keys_hid.push_input(&keys::key_map_report(pressed)) {
                     Ok(_) => {
-                        debug!("HID report sent successfully");
-                    }
-                    Err(UsbError::WouldBlock) => {
-                        // Host not ready, will try again next loop
+                        debug!("Key report sent: {=u32:#010x}", pressed);
+                        key_buttons = pressed;
                     }
+                    Err(UsbError::WouldBlock) => {}
                     Err(_e) => {
-                        warn!("Failed to send HID report");
+                        warn!("Failed to send key report");
                     }
                 }
             }
         }
 
-        // Small delay to prevent overwhelming the USB bus
-        delay.delay_us(100);
+        // Movement is relative, so a step the host doesn't take is simply
+        // retried on a later loop rather than queued
+        let held = button_box.buttons() & mouse::MOUSE_BUTTONS;
+        if let Some(report) = mouse.poll(held, now_ms).filter(|_| !suspended) {
+            match usb.mouse_hid.push_input(&report) {
+                Ok(_) => mouse.sent(now_ms),
+                Err(UsbError::WouldBlock) => {}
+                Err(_e) => {
+                    warn!("Failed to send mouse report");
+                }
+            }
+        }
+
+        // Holding the reset combo long enough detaches from USB and resets
+        // the board. Its inputs held as a firing combo are reporting that
+        // combo's button instead, so they don't count.
+        let reset_held = button_box.inputs() & RESET_COMBO == RESET_COMBO
+            && button_box.combo_inputs() & RESET_COMBO == 0;
+        if reset_held {
+            let held_since = *reset_held_since.get_or_insert(now_ms);
+            if now_ms.wrapping_sub(held_since) >= RESET_HOLD_MS {
+                info!("Reset combo held, resetting");
+                detach_usb(&mut usb_dev, &mut usb, &timer);
+                cortex_m::peripheral::SCB::sys_reset();
+            }
+        } else {
+            reset_held_since = None;
+        }
+
+        // Any input change resets the idle timeout and wakes an idle box
+        if send_report && !resumed {
+            last_input_ms = now_ms;
+        }
+        let idle = now_ms.wrapping_sub(last_input_ms) >= IDLE_TIMEOUT_MS;
+        if idle != status_led.idle() {
+            if idle {
+                info!("No input for {}ms, going idle", IDLE_TIMEOUT_MS);
+            } else {
+                info!("Input again, waking from idle");
+            }
+            status_led.set_idle(idle);
+        }
+
+        // Queue a report of every change, but only one all-released report
+        // at a time, which goes out first and alone
+        report_due |= send_report || release_pending;
+        let configured = usb_dev.state() == UsbDeviceState::Configured;
+        // With nothing waiting to go out, the last report sent is the current
+        // state, so the heartbeat repeats it
+        let heartbeat = configured
+            && !report_due
+            && report_queue.is_empty()
+            && HEARTBEAT_MS
+                .is_some_and(|interval_ms| now_ms.wrapping_sub(report_sent_ms) >= interval_ms);
+        if heartbeat {
+            debug!("No report for a while, sending a heartbeat");
+            report_due = true;
+        }
+        if report_due
+            && !suspended
+            && (configured || !release_pending)
+            && (report_queue.is_empty() || !release_pending)
+        {
+            let releasing = release_pending;
+            report_due = false;
+
+            // Media key and key buttons are only reported on their own
+            // interfaces
+            let routed = routed_buttons(&config);
+            let mut buttons = (button_box.buttons() | encoder_pulse | encoder_switch) & !routed;
+            #[cfg(feature = "slider")]
+            {
+                buttons |= slider_button & !routed;
+            }
+            // Only the buttons this report carries are flipped, so the routed
+            // ones and the bits nothing reports stay clear
+            if invert_report {
+                buttons ^= reported_buttons(&config) & !routed;
+            }
+
+            #[cfg(not(feature = "keyboard"))]
+            let report = if releasing {
+                ButtonBoxReport::new(0)
+            } else {
+                let mut report = ButtonBoxReport::new(buttons);
+                report.x = hid_descriptor::axis_byte(axes.0);
+                report.y = hid_descriptor::axis_byte(axes.1);
+                report.hat = hat_direction;
+                #[cfg(feature = "slider")]
+                {
+                    report.slider = slider_position;
+                }
+                report
+            };
+            // The keyboard report has no axes or hat, only the buttons' keycodes,
+            // or the macro's key while one plays
+            #[cfg(feature = "keyboard")]
+            let report = match macro_state {
+                _ if releasing => keyboard::key_report(None),
+                (true, key) => keyboard::key_report(key),
+                (false, _) => keyboard::keyboard_report(buttons & !macro_player::MACRO_BUTTONS),
+            };
+
+            #[cfg(not(feature = "keyboard"))]
+            let state = buttons;
+            #[cfg(feature = "keyboard")]
+            let state = (buttons, macro_state);
+            // A change of the analog fields alone, or one with no room left,
+            // updates the newest report rather than queueing another
+            report_queue.push(report, state, releasing);
+        }
+
+        // Send the oldest report once the report interval has passed, and
+        // keep it at the front of the queue until the host takes it. Nothing
+        // goes out while suspended, and the resume replaces the queue.
+        let interval_passed = now_ms.wrapping_sub(report_sent_ms) >= MIN_REPORT_INTERVAL_MS;
+        let sent = if interval_passed && !suspended {
+            report_queue.send(|report| match usb.push_report(report) {
+                Ok(_) => Delivery::Taken,
+                Err(UsbError::WouldBlock) => Delivery::Busy,
+                Err(_) => Delivery::Failed,
+            })
+        } else {
+            None
+        };
+        match sent {
+            Some((Delivery::Taken, releasing)) => {
+                debug!("HID report sent successfully");
+                blocked_since = None;
+                report_sent_ms = now_ms;
+                #[cfg(not(feature = "slider"))]
+                activity_led.blink(now_ms);
+                if releasing {
+                    debug!("All-released report sent");
+                    release_pending = false;
+                    report_due = true;
+                }
+            }
+            Some((Delivery::Busy, _)) => {
+                // Host not ready, push the same report again next loop
+                blocked_since.get_or_insert(now_ms);
+            }
+            Some((Delivery::Failed, _)) => {
+                warn!("Failed to send HID report");
+                blocked_since = None;
+            }
+            None => {}
+        }
+        // With nothing left to send, the host isn't holding anything up
+        if report_queue.is_empty() {
+            blocked_since = None;
+        }
+
+        if suspended {
+            delay.delay_ms(SUSPENDED_LOOP_MS);
+        } else if idle {
+            delay.delay_ms(IDLE_LOOP_MS);
+        } else {
+            // Small delay to prevent overwhelming the USB bus
+            delay.delay_us(LOOP_DELAY_US);
+        }
     }
 }
 
+#[interrupt]
+fn IO_IRQ_BANK0() {
+    // The pins are owned by the main loop, so acknowledge the edges through the
+    // raw registers. Level bits are read-only, so writing ones only clears edges.
+    let io_bank0 = unsafe { &*pac::IO_BANK0::ptr() };
+    for intr in io_bank0.intr_iter() {
+        intr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+    }
+
+    BUTTONS_DIRTY.store(true, Ordering::Release);
+}
+
 // End of file
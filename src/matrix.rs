@@ -0,0 +1,142 @@
+//! Scanned key matrix, for more buttons than there are spare pins
+//!
+//! Buttons sit at the intersections of row outputs and column inputs. Each
+//! row is pulled low in turn while the others are held high, and any column
+//! reading low has its button at that row pressed.
+//!
+//! Every switch should have a diode in series (anode on the column, cathode
+//! on the row). Without them, three buttons pressed on the corners of a
+//! rectangle also connect the fourth corner through the other three and it
+//! reads as a ghost press, and two buttons in one column short a high row to
+//! the low one.
+//!
+//! For a matrix built without diodes, the ghost filter catches the first
+//! problem: whenever two rows read two or more pressed columns in common, the
+//! buttons on those corners can't be told apart from ghosts, so they keep the
+//! state they had before the rectangle formed. The short still needs a
+//! resistor in series with every row pin.
+
+use embedded_hal::digital::{InputPin, OutputPin};
+
+use crate::bsp::hal::gpio::{DynPinId, FunctionSio, Pin, PullDown, PullUp, SioInput, SioOutput};
+
+/// GPIO pin type for the driven matrix rows
+pub type RowPin = Pin<DynPinId, FunctionSio<SioOutput>, PullDown>;
+/// GPIO pin type for the sensed matrix columns, pulled up so idle columns read high
+pub type ColumnPin = Pin<DynPinId, FunctionSio<SioInput>, PullUp>;
+
+/// CPU cycles to wait after selecting a row before reading the columns, about
+/// 2us at the default 125MHz system clock
+///
+/// Gives the column pull-ups time to recharge the wiring after the previous
+/// row released it, so a press never shows up one row late.
+const SETTLE_CYCLES: u32 = 250;
+
+/// Matrix of `R` rows by `C` columns of buttons
+pub struct Matrix<const R: usize, const C: usize> {
+    rows: [RowPin; R],
+    columns: [ColumnPin; C],
+    // Whether ambiguous buttons are held at their last state, and the state
+    // the last scan returned
+    ghost_filter: bool,
+    last: u16,
+}
+
+impl<const R: usize, const C: usize> Matrix<R, C> {
+    /// Drive `rows` and sense `columns`, with the ghost filter on if
+    /// `ghost_filter`, for a matrix without diodes
+    pub fn new(rows: [RowPin; R], columns: [ColumnPin; C], ghost_filter: bool) -> Self {
+        let mut matrix = Self {
+            rows,
+            columns,
+            ghost_filter,
+            last: 0,
+        };
+        for row in matrix.rows.iter_mut() {
+            row.set_high().ok();
+        }
+        matrix
+    }
+
+    /// Whether the matrix has no buttons at all
+    pub fn is_empty(&self) -> bool {
+        R == 0 || C == 0
+    }
+
+    /// Scan every row and return the pressed buttons, bit `row * C + column`
+    ///
+    /// With the ghost filter on, buttons that may be ghosts report their
+    /// previous state instead.
+    pub fn scan(&mut self) -> u16 {
+        let mut pressed = 0u16;
+
+        for (row_index, row) in self.rows.iter_mut().enumerate() {
+            row.set_low().ok();
+            cortex_m::asm::delay(SETTLE_CYCLES);
+
+            for (column_index, column) in self.columns.iter_mut().enumerate() {
+                if column.is_low().unwrap_or(false) {
+                    pressed |= 1 << (row_index * C + column_index);
+                }
+            }
+
+            row.set_high().ok();
+        }
+
+        if self.ghost_filter {
+            pressed = filter_ghosts(pressed, self.last, R, C);
+        }
+        self.last = pressed;
+        pressed
+    }
+}
+
+/// Buttons of a `rows` by `columns` scan `pressed` that sit on the corners of
+/// a rectangle of presses, and so might be ghosts
+const fn ambiguous(pressed: u16, rows: usize, columns: usize) -> u16 {
+    let row_mask = ((1u32 << columns) - 1) as u16;
+    let mut ambiguous = 0;
+    let mut first = 0;
+    while first < rows {
+        let mut second = first + 1;
+        while second < rows {
+            let common =
+                (pressed >> (first * columns)) & (pressed >> (second * columns)) & row_mask;
+            if common.count_ones() >= 2 {
+                ambiguous |= (common << (first * columns)) | (common << (second * columns));
+            }
+            second += 1;
+        }
+        first += 1;
+    }
+    ambiguous
+}
+
+/// `pressed` with the ambiguous buttons held at their state in `last`
+const fn filter_ghosts(pressed: u16, last: u16, rows: usize, columns: usize) -> u16 {
+    let ambiguous = ambiguous(pressed, rows, columns);
+    (pressed & !ambiguous) | (last & ambiguous)
+}
+
+// A diode-less 2x2 matrix, buttons A B on the first row and C D below them:
+// pressing A, B then D ghosts C, which must never be reported
+const _: () = {
+    const A: u16 = 0b0001;
+    const B: u16 = 0b0010;
+    const C: u16 = 0b0100;
+    const D: u16 = 0b1000;
+
+    // A and B alone read cleanly
+    let last = filter_ghosts(A | B, 0, 2, 2);
+    assert!(last == A | B);
+    // With D pressed as well all four corners read pressed; only the two
+    // known presses are kept, and D waits with the phantom C
+    let last = filter_ghosts(A | B | C | D, last, 2, 2);
+    assert!(last == A | B);
+    // Releasing B breaks the rectangle, so D shows up and C stays released
+    let last = filter_ghosts(A | D, last, 2, 2);
+    assert!(last == A | D);
+
+    // Two presses in a row of a wider matrix are never ambiguous
+    assert!(ambiguous(0b0000_0111, 2, 4) == 0);
+};
@@ -0,0 +1,146 @@
+//! Mouse movement reports
+//!
+//! Logical buttons listed in `MOUSE_MAP` nudge the mouse cursor through a HID
+//! mouse interface of their own instead of the gamepad. A held button moves
+//! the cursor by its delta every `MOVE_INTERVAL_MS`, speeding up the longer it
+//! is held, and several held buttons add up. A host polling slower than that
+//! gets the steps it missed in one report, so the cursor keeps its speed.
+//!
+//! With `EncoderMode::RelativeAxis` the encoder's rotation moves the cursor
+//! too: it adds up between reports, and goes out with the next one the host
+//! takes, so a fast spin moves the cursor as far as a slow one.
+
+pub use usbd_hid::descriptor::MouseReport;
+
+/// Logical buttons that move the mouse, with the cursor delta (X, Y) each one
+/// moves by per step
+///
+/// Buttons 13 and 14 aren't wired by default; map an input onto them (e.g.
+/// with the console's `set` command) to use them.
+pub const MOUSE_MAP: [(usize, (i8, i8)); 2] = [
+    // Right
+    (crate::BUTTON_COUNT + 10, (2, 0)),
+    // Left
+    (crate::BUTTON_COUNT + 11, (-2, 0)),
+];
+
+/// Report bits of every button in `MOUSE_MAP`
+pub const MOUSE_BUTTONS: u32 = button_box_firmware::mapped_buttons(&MOUSE_MAP);
+
+/// Time (ms) between movement steps while a mouse button is held
+const MOVE_INTERVAL_MS: u32 = 10;
+/// Time (ms) a mouse button must be held to reach full speed
+const ACCELERATION_MS: u32 = 1_000;
+/// Multiple of the configured delta moved per step at full speed
+const MAX_SPEED: i16 = 4;
+/// Most missed steps added into one report, enough to fill the axis range
+/// with the smallest delta
+const MAX_STEPS: u32 = i8::MAX as u32;
+
+/// Turns held mouse buttons into a stream of movement reports
+pub struct Mouse {
+    // Tick (ms) at which the cursor started moving, while it is
+    moving_since: Option<u32>,
+    // Tick (ms) of the last movement step the host took
+    last_step: u32,
+    // Encoder movement not yet reported
+    encoder: (i16, i16),
+    // What the report from `poll()` carried: whether a button step, and how
+    // much of the encoder movement
+    polled_step: bool,
+    polled_encoder: (i16, i16),
+}
+
+impl Mouse {
+    pub fn new() -> Self {
+        Self {
+            moving_since: None,
+            last_step: 0,
+            encoder: (0, 0),
+            polled_step: false,
+            polled_encoder: (0, 0),
+        }
+    }
+
+    /// Add encoder movement `(x, y)` to the next report
+    pub fn add_encoder(&mut self, x: i16, y: i16) {
+        self.encoder.0 = self.encoder.0.saturating_add(x);
+        self.encoder.1 = self.encoder.1.saturating_add(y);
+    }
+
+    /// The report to send for the held logical `buttons`, if a movement step
+    /// is due or the encoder moved
+    ///
+    /// The first step is due as soon as a button goes down. Call `sent()`
+    /// once the host takes the report, or the step is retried next loop.
+    /// Encoder movement past what one report carries is left for the next.
+    pub fn poll(&mut self, buttons: u32, now_ms: u32) -> Option<MouseReport> {
+        let step = self.step(buttons, now_ms);
+        self.polled_step = step.is_some();
+        let (step_x, step_y) = step.unwrap_or((0, 0));
+
+        // The encoder fills whatever range of the axis the step leaves
+        let fill = |step: i8, encoder: i16| {
+            encoder.clamp(
+                i16::from(i8::MIN) - i16::from(step),
+                i16::from(i8::MAX) - i16::from(step),
+            )
+        };
+        self.polled_encoder = (fill(step_x, self.encoder.0), fill(step_y, self.encoder.1));
+        if step.is_none() && self.polled_encoder == (0, 0) {
+            return None;
+        }
+
+        Some(MouseReport {
+            buttons: 0,
+            x: (i16::from(step_x) + self.polled_encoder.0) as i8,
+            y: (i16::from(step_y) + self.polled_encoder.1) as i8,
+            wheel: 0,
+            pan: 0,
+        })
+    }
+
+    /// The movement step of the held `buttons`, if one is due
+    fn step(&mut self, buttons: u32, now_ms: u32) -> Option<(i8, i8)> {
+        let (x, y) = MOUSE_MAP
+            .iter()
+            .filter(|(button, _)| buttons & (1 << button) != 0)
+            .fold((0i16, 0i16), |(x, y), &(_, (dx, dy))| {
+                (x + i16::from(dx), y + i16::from(dy))
+            });
+
+        if x == 0 && y == 0 {
+            self.moving_since = None;
+            return None;
+        }
+        let since = *self.moving_since.get_or_insert_with(|| {
+            self.last_step = now_ms.wrapping_sub(MOVE_INTERVAL_MS);
+            now_ms
+        });
+        if now_ms.wrapping_sub(self.last_step) < MOVE_INTERVAL_MS {
+            return None;
+        }
+
+        // Ramp linearly from the configured delta up to `MAX_SPEED` times it,
+        // for every step due since the host took the last one; the range of
+        // a report caps how many add up
+        let held_ms = now_ms.wrapping_sub(since).min(ACCELERATION_MS);
+        let speed = 1 + (held_ms * (MAX_SPEED as u32 - 1) / ACCELERATION_MS) as i16;
+        let due_ms = now_ms.wrapping_sub(self.last_step);
+        let steps = (due_ms / MOVE_INTERVAL_MS).min(MAX_STEPS) as i32;
+        let step = |delta: i16| {
+            (i32::from(delta * speed) * steps).clamp(i8::MIN.into(), i8::MAX.into()) as i8
+        };
+
+        Some((step(x), step(y)))
+    }
+
+    /// Record that the host took the report from `poll()`
+    pub fn sent(&mut self, now_ms: u32) {
+        if self.polled_step {
+            self.last_step = now_ms;
+        }
+        self.encoder.0 -= self.polled_encoder.0;
+        self.encoder.1 -= self.polled_encoder.1;
+    }
+}
@@ -0,0 +1,60 @@
+//! WS2812 ("NeoPixel") RGB LED strip driven from the PIO
+//!
+//! One state machine of PIO0 generates the 800kHz WS2812 waveform, so the CPU
+//! only has to push a word per LED. The PIO runs from the system clock and
+//! never touches the USB PLL, so it can't disturb USB timing.
+
+use smart_leds::{SmartLedsWrite, RGB8};
+use ws2812_pio::Ws2812Direct;
+
+use crate::bsp::hal::{
+    fugit::HertzU32,
+    gpio::{bank0::Gpio22, FunctionPio0, Pin, PullDown},
+    pac::PIO0,
+    pio::{UninitStateMachine, PIO, SM0},
+};
+
+/// Data pin of the strip, handed to PIO0
+pub type StripPin = Pin<Gpio22, FunctionPio0, PullDown>;
+
+/// Strip of `N` RGB LEDs
+///
+/// Colors are buffered by `set_led()` and sent together by `show()`. The
+/// WS2812 latches after 60us without data, so `show()` must not be called
+/// more often than that.
+pub struct Neopixel<const N: usize> {
+    driver: Ws2812Direct<PIO0, SM0, StripPin>,
+    colors: [RGB8; N],
+}
+
+impl<const N: usize> Neopixel<N> {
+    /// Load the WS2812 program into `pio` and run it on `sm`, with
+    /// `clock_freq` the system clock frequency it derives its timing from
+    pub fn new(
+        pin: StripPin,
+        pio: &mut PIO<PIO0>,
+        sm: UninitStateMachine<(PIO0, SM0)>,
+        clock_freq: HertzU32,
+    ) -> Self {
+        let mut strip = Self {
+            driver: Ws2812Direct::new(pin, pio, sm, clock_freq),
+            colors: [RGB8::default(); N],
+        };
+        strip.show();
+        strip
+    }
+
+    /// Set the color of LED `index`, shown on the next `show()`
+    ///
+    /// Indices past the end of the strip are ignored.
+    pub fn set_led(&mut self, index: usize, rgb: RGB8) {
+        if let Some(color) = self.colors.get_mut(index) {
+            *color = rgb;
+        }
+    }
+
+    /// Send the buffered colors to the strip
+    pub fn show(&mut self) {
+        self.driver.write(self.colors.iter().copied()).ok();
+    }
+}
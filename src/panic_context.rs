@@ -0,0 +1,55 @@
+//! Panic handler logging what the box was doing when it crashed
+//!
+//! Like `panic-probe`, it prints the panic message over defmt and ends in a
+//! HardFault so `probe-rs` shows a backtrace. Before that it logs the last
+//! button state and tick the main loop recorded with `record()`, so a crash
+//! can be matched with what was being pressed.
+//!
+//! The handler only reads atomics and formats through defmt, so it neither
+//! allocates nor takes a lock the panicking code might hold.
+
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering},
+};
+
+/// Firmware name, identifying the build in the crash log with its version
+const FIRMWARE: &str = env!("CARGO_PKG_NAME");
+
+// Latest state recorded by the main loop
+static INPUTS: AtomicU16 = AtomicU16::new(0);
+static BUTTONS: AtomicU32 = AtomicU32::new(0);
+static NOW_MS: AtomicU32 = AtomicU32::new(0);
+
+/// Record the debounced physical `inputs` and reported logical `buttons` at
+/// `now_ms`, for the panic handler to log
+pub fn record(inputs: u16, buttons: u32, now_ms: u32) {
+    INPUTS.store(inputs, Ordering::Relaxed);
+    BUTTONS.store(buttons, Ordering::Relaxed);
+    NOW_MS.store(now_ms, Ordering::Relaxed);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    static PANICKED: AtomicBool = AtomicBool::new(false);
+
+    cortex_m::interrupt::disable();
+
+    // A panic while logging the first one goes straight to the fault
+    if !PANICKED.load(Ordering::Relaxed) {
+        PANICKED.store(true, Ordering::Relaxed);
+
+        defmt::error!("{}", defmt::Display2Format(info));
+        defmt::error!(
+            "Panic in {=str} {=str} at {=u32}ms: inputs {=u16:#06x}, buttons {=u32:#010x}",
+            FIRMWARE,
+            crate::device_info::FIRMWARE_VERSION,
+            NOW_MS.load(Ordering::Relaxed),
+            INPUTS.load(Ordering::Relaxed),
+            BUTTONS.load(Ordering::Relaxed),
+        );
+    }
+
+    // The udf instruction raises a HardFault, where the debugger stops
+    cortex_m::asm::udf();
+}
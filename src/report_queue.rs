@@ -0,0 +1,182 @@
+//! Reports waiting for the host to take them
+//!
+//! The host takes an input report on its own schedule, so a report built
+//! while the last one is still waiting is queued behind it rather than
+//! overwriting it, and a press and release made between two polls both go
+//! out. Each report is queued with the state it was built from, the buttons
+//! it carries; a change that leaves the state alone, like a moved axis,
+//! updates the newest report instead of queueing another.
+
+use heapless::Deque;
+
+/// What became of offering a report to the host
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Delivery {
+    /// The host took the report
+    Taken,
+    /// The host isn't ready for another report, so it's offered again later
+    Busy,
+    /// The report can't be sent, and is dropped
+    Failed,
+}
+
+/// Report waiting to be taken by the host, with the state it was built from
+/// and whether it's the all-released report
+struct QueuedReport<R, S> {
+    report: R,
+    state: S,
+    releasing: bool,
+}
+
+/// Up to `N` reports of type `R`, each built from a state `S`, oldest first
+pub struct ReportQueue<R, S, const N: usize> {
+    reports: Deque<QueuedReport<R, S>, N>,
+}
+
+impl<R, S: PartialEq, const N: usize> ReportQueue<R, S, N> {
+    pub const fn new() -> Self {
+        Self {
+            reports: Deque::new(),
+        }
+    }
+
+    /// Whether every report has been taken
+    pub fn is_empty(&self) -> bool {
+        self.reports.is_empty()
+    }
+
+    /// Drop every report not yet taken
+    pub fn clear(&mut self) {
+        self.reports.clear();
+    }
+
+    /// Queue `report`, built from `state`, or the all-released report if
+    /// `releasing` is set
+    ///
+    /// A report with the same state as the newest one, or one with no room
+    /// left, updates the newest report rather than queueing another, so the
+    /// final state always gets through but the briefest transitions may
+    /// not. The all-released report is never merged with another.
+    pub fn push(&mut self, report: R, state: S, releasing: bool) {
+        let full = self.reports.is_full();
+        let queued = QueuedReport {
+            report,
+            state,
+            releasing,
+        };
+        match self.reports.back_mut() {
+            Some(back) if !back.releasing && !releasing && (back.state == queued.state || full) => {
+                *back = queued
+            }
+            // There's room, since a full queue always has a back
+            _ => {
+                self.reports.push_back(queued).ok();
+            }
+        }
+    }
+
+    /// Offer the oldest report to `send`, keeping it at the front of the
+    /// queue until it's taken or fails
+    ///
+    /// Returns what became of it and whether it's the all-released report,
+    /// or `None` if the queue is empty.
+    pub fn send(&mut self, send: impl FnOnce(&R) -> Delivery) -> Option<(Delivery, bool)> {
+        let front = self.reports.front()?;
+        let releasing = front.releasing;
+        let delivery = send(&front.report);
+        if delivery != Delivery::Busy {
+            self.reports.pop_front();
+        }
+        Some((delivery, releasing))
+    }
+}
+
+impl<R, S: PartialEq, const N: usize> Default for ReportQueue<R, S, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Take every report out of `queue`, oldest first, with whether each is
+    /// the all-released one
+    fn drain<const N: usize>(queue: &mut ReportQueue<u32, u32, N>) -> Vec<(u32, bool)> {
+        let mut sent = Vec::new();
+        loop {
+            let mut taken = None;
+            let Some((_, releasing)) = queue.send(|&report| {
+                taken = Some(report);
+                Delivery::Taken
+            }) else {
+                return sent;
+            };
+            sent.push((taken.unwrap(), releasing));
+        }
+    }
+
+    #[test]
+    fn press_and_release_both_go_out_while_the_host_is_busy() {
+        let mut queue = ReportQueue::<u32, u32, 8>::new();
+        queue.push(0b1, 0b1, false);
+        queue.push(0, 0, false);
+
+        // The press is offered again for as long as the host is busy
+        let mut offered = Vec::new();
+        for _ in 0..5 {
+            let delivery = queue.send(|&report| {
+                offered.push(report);
+                Delivery::Busy
+            });
+            assert_eq!(delivery, Some((Delivery::Busy, false)));
+        }
+        assert_eq!(offered, [0b1; 5]);
+
+        let delivery = queue.send(|&report| {
+            offered.push(report);
+            Delivery::Taken
+        });
+        assert_eq!(delivery, Some((Delivery::Taken, false)));
+        assert_eq!(offered, [0b1; 6]);
+
+        // Then the release, which wasn't merged into the press
+        assert_eq!(drain(&mut queue), [(0, false)]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn merges_same_state_and_overflow_but_never_the_release() {
+        let mut queue = ReportQueue::<u32, u32, 2>::new();
+        // The same buttons with a new axis position update the report
+        queue.push(0x10, 0b1, false);
+        queue.push(0x11, 0b1, false);
+        // A full queue updates its newest report
+        queue.push(0x20, 0b10, false);
+        queue.push(0x30, 0b11, false);
+        assert_eq!(drain(&mut queue), [(0x11, false), (0x30, false)]);
+
+        // The all-released report neither merges into the one before it nor
+        // takes in the one after it, whatever their state
+        queue.push(0x40, 0b1, false);
+        queue.push(0, 0b1, true);
+        assert_eq!(drain(&mut queue), [(0x40, false), (0, true)]);
+        queue.push(0, 0b1, true);
+        queue.push(0x50, 0b1, false);
+        assert_eq!(drain(&mut queue), [(0, true), (0x50, false)]);
+    }
+
+    #[test]
+    fn a_failed_report_is_dropped() {
+        let mut queue = ReportQueue::<u32, u32, 8>::new();
+        queue.push(0b1, 0b1, false);
+        queue.push(0, 0, false);
+        assert_eq!(
+            queue.send(|_| Delivery::Failed),
+            Some((Delivery::Failed, false))
+        );
+        assert_eq!(drain(&mut queue), [(0, false)]);
+        assert_eq!(queue.send(|_| Delivery::Taken), None);
+    }
+}
@@ -0,0 +1,93 @@
+//! Brown-out detection, and the cause of the last reset
+//!
+//! The RP2040 records chip-level resets in `CHIP_RESET` and watchdog resets
+//! in the watchdog's `REASON` register. Both are read once at startup so the
+//! log says why the box came up, which helps tell a power problem from a
+//! firmware hang.
+//!
+//! The brown-out detector shares the power-on reset's flag, so a brown-out
+//! and plugging the box in can't be told apart; frequent power-on resets
+//! with the cable left in point at the supply. A reset from
+//! `SCB::sys_reset()` only resets the cores, so it isn't recorded, and the
+//! cause of the reset before it is reported again.
+
+use crate::bsp::hal::pac::{VREG_AND_CHIP_RESET, WATCHDOG};
+
+/// Brown-out threshold (mV) of the core supply, from 473mV in 43mV steps
+///
+/// 860mV is the chip's own default, and sits well below the 1.1V core
+/// voltage (or 1.15V above `clock::STOCK_MAX_HZ`).
+const BROWN_OUT_MV: u32 = 860;
+const BROWN_OUT_MIN_MV: u32 = 473;
+const BROWN_OUT_STEP_MV: u32 = 43;
+
+// The threshold must be one of the 16 settings of the detector
+const _: () = {
+    assert!(BROWN_OUT_MV >= BROWN_OUT_MIN_MV);
+    assert!((BROWN_OUT_MV - BROWN_OUT_MIN_MV).is_multiple_of(BROWN_OUT_STEP_MV));
+    assert!((BROWN_OUT_MV - BROWN_OUT_MIN_MV) / BROWN_OUT_STEP_MV <= 0x0F);
+};
+
+/// What caused the last reset
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ResetReason {
+    /// The supply came up, or dipped below the brown-out threshold
+    PowerOnOrBrownOut,
+    /// The RUN pin was pulled low
+    RunPin,
+    /// A debugger restarted the chip through the debug port
+    DebugPort,
+    /// The watchdog wasn't fed in time
+    WatchdogTimeout,
+    /// The watchdog was triggered on purpose
+    WatchdogForced,
+    /// No reset cause is recorded
+    Unknown,
+}
+
+impl ResetReason {
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::PowerOnOrBrownOut => "power-on or brown-out",
+            Self::RunPin => "RUN pin",
+            Self::DebugPort => "debug port",
+            Self::WatchdogTimeout => "watchdog timeout",
+            Self::WatchdogForced => "watchdog forced",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+/// Cause of the last reset, to be read before the watchdog is restarted
+///
+/// The watchdog is checked first: `REASON` is cleared by every other reset,
+/// while `CHIP_RESET` keeps its flag from before through a watchdog reset.
+pub fn reset_reason(vreg: &VREG_AND_CHIP_RESET, watchdog: &WATCHDOG) -> ResetReason {
+    let reason = watchdog.reason().read();
+    if reason.timer().bit_is_set() {
+        return ResetReason::WatchdogTimeout;
+    }
+    if reason.force().bit_is_set() {
+        return ResetReason::WatchdogForced;
+    }
+
+    let chip_reset = vreg.chip_reset().read();
+    if chip_reset.had_por().bit_is_set() {
+        ResetReason::PowerOnOrBrownOut
+    } else if chip_reset.had_run().bit_is_set() {
+        ResetReason::RunPin
+    } else if chip_reset.had_psm_restart().bit_is_set() {
+        ResetReason::DebugPort
+    } else {
+        ResetReason::Unknown
+    }
+}
+
+/// Enable the brown-out detector at `BROWN_OUT_MV`, so a sagging supply
+/// resets the chip cleanly instead of leaving it running unreliably
+pub fn enable_brown_out(vreg: &mut VREG_AND_CHIP_RESET) {
+    let vsel = ((BROWN_OUT_MV - BROWN_OUT_MIN_MV) / BROWN_OUT_STEP_MV) as u8;
+    vreg.bod()
+        // SAFETY: the threshold is checked to fit the 4-bit field above
+        .write(|w| unsafe { w.vsel().bits(vsel) }.en().set_bit());
+}
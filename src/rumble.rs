@@ -0,0 +1,80 @@
+//! Vibration motor driven from the host's rumble output byte
+//!
+//! The motor hangs off a transistor switched by PWM slice 4 channel A
+//! (GPIO8). The slice runs at about 20kHz so the motor doesn't whine, and
+//! its duty cycle follows the intensity the host last sent. Channel B of the
+//! same slice dims the onboard LED.
+
+use embedded_hal::pwm::SetDutyCycle;
+
+use crate::bsp::hal::pwm::{Channel, FreeRunning, Pwm4, Slice, A};
+
+/// PWM slice type driving the motor
+pub type RumblePwm = Slice<Pwm4, FreeRunning>;
+/// PWM channel of `RumblePwm` wired to the motor
+pub type RumbleChannel = Channel<RumblePwm, A>;
+
+/// PWM frequency of the motor, above hearing
+const PWM_HZ: u32 = 20_000;
+/// Time (ms) per intensity step while ramping down
+const RAMP_STEP_MS: u32 = 1;
+
+/// Run `pwm` at the motor's 20kHz from a `sys_clock_hz` counter clock,
+/// before its channels are handed out
+pub fn start_slice(pwm: &mut RumblePwm, sys_clock_hz: u32) {
+    // Up to 200MHz / 20kHz = 10000 counts, which fits the 16-bit counter
+    pwm.set_top((sys_clock_hz / PWM_HZ - 1) as u16);
+    pwm.set_div_int(1);
+    pwm.enable();
+}
+
+/// Rumble motor on a PWM channel
+pub struct Rumble {
+    pwm: RumbleChannel,
+    // Intensities above this are clamped, for motors rated below the supply
+    max_intensity: u8,
+    intensity: u8,
+    // Tick (ms) of the last ramp-down step
+    last_step_ms: u32,
+}
+
+impl Rumble {
+    /// Take over `pwm`, already routed to the motor pin and started with
+    /// `start_slice()`, starting with the motor off
+    pub fn new(pwm: RumbleChannel, max_intensity: u8) -> Self {
+        let mut rumble = Self {
+            pwm,
+            max_intensity,
+            intensity: 0,
+            last_step_ms: 0,
+        };
+        rumble.apply();
+        rumble
+    }
+
+    /// Run the motor at `intensity` (0 = off, 255 = full), clamped to the
+    /// maximum intensity
+    pub fn set(&mut self, intensity: u8) {
+        self.intensity = intensity.min(self.max_intensity);
+        self.apply();
+    }
+
+    /// Step the motor down towards off, one intensity step per `RAMP_STEP_MS`
+    ///
+    /// Called every loop while the host can't send output reports, so a
+    /// motor left running by a host that went away always winds down.
+    pub fn ramp_down(&mut self, now_ms: u32) {
+        if self.intensity == 0 || now_ms.wrapping_sub(self.last_step_ms) < RAMP_STEP_MS {
+            return;
+        }
+        self.last_step_ms = now_ms;
+        self.intensity -= 1;
+        self.apply();
+    }
+
+    fn apply(&mut self) {
+        self.pwm
+            .set_duty_cycle_fraction(self.intensity as u16, u8::MAX as u16)
+            .ok();
+    }
+}
@@ -0,0 +1,131 @@
+//! Log messages mirrored to the USB serial port
+//!
+//! The `info!`, `warn!` and `error!` macros here replace defmt's for the
+//! firmware's own messages. Each message still goes out over defmt RTT, and
+//! is also queued as a line of text for the serial console, so the log can
+//! be read without a probe. `flush()` feeds the queue to the port from the
+//! main loop, as fast as the host reads it.
+//!
+//! The macros take `core::fmt` format strings rather than defmt's, since the
+//! text is formatted on the device. Debug messages stay defmt only.
+
+use core::{cell::RefCell, fmt::Write};
+
+use cortex_m::interrupt::Mutex;
+use heapless::{Deque, String};
+use usb_device::bus::UsbBus;
+use usbd_serial::SerialPort;
+
+/// Bytes of log text held for the serial port, about twenty lines
+const BUFFER_LEN: usize = 1024;
+/// Longest message logged, longer ones are cut short
+const LINE_LEN: usize = 96;
+
+/// Severity of a logged message
+#[derive(Clone, Copy)]
+pub enum Level {
+    Info,
+    Warn,
+    Error,
+}
+
+/// Log text waiting for the serial port, and the number of messages dropped
+/// since the last flush because it was full
+struct Queue {
+    bytes: Deque<u8, BUFFER_LEN>,
+    dropped: u32,
+}
+
+static QUEUE: Mutex<RefCell<Queue>> = Mutex::new(RefCell::new(Queue {
+    bytes: Deque::new(),
+    dropped: 0,
+}));
+
+/// Log `args` at `level` over defmt and queue it for the serial port
+///
+/// A message that doesn't fit in the queue is dropped from the serial log
+/// (but not from defmt), so a host that never opens the port can't stall
+/// anything.
+pub fn log(level: Level, args: core::fmt::Arguments) {
+    let mut line = String::<LINE_LEN>::new();
+    write!(line, "{args}").ok();
+
+    let prefix = match level {
+        Level::Info => {
+            defmt::info!("{=str}", line.as_str());
+            "[INFO] "
+        }
+        Level::Warn => {
+            defmt::warn!("{=str}", line.as_str());
+            "[WARN] "
+        }
+        Level::Error => {
+            defmt::error!("{=str}", line.as_str());
+            "[ERROR] "
+        }
+    };
+
+    cortex_m::interrupt::free(|cs| {
+        let mut queue = QUEUE.borrow(cs).borrow_mut();
+        let len = prefix.len() + line.len() + 2;
+        if queue.bytes.capacity() - queue.bytes.len() < len {
+            queue.dropped += 1;
+            return;
+        }
+        for &byte in prefix
+            .as_bytes()
+            .iter()
+            .chain(line.as_bytes())
+            .chain(b"\r\n")
+        {
+            queue.bytes.push_back(byte).ok();
+        }
+    });
+}
+
+/// Send as much of the queued log as the serial port takes right now
+pub fn flush<B: UsbBus>(serial: &mut SerialPort<B>) {
+    cortex_m::interrupt::free(|cs| {
+        let mut queue = QUEUE.borrow(cs).borrow_mut();
+
+        // Note the gap once there's room for the note itself
+        if queue.dropped > 0 && queue.bytes.is_empty() {
+            let mut note = String::<40>::new();
+            write!(note, "[{} log messages dropped]\r\n", queue.dropped).ok();
+            if serial.write(note.as_bytes()).is_ok() {
+                queue.dropped = 0;
+            }
+        }
+
+        while !queue.bytes.is_empty() {
+            let (front, _) = queue.bytes.as_slices();
+            let Ok(written) = serial.write(front) else {
+                // Endpoint busy or host not reading, try again next loop
+                return;
+            };
+            for _ in 0..written {
+                queue.bytes.pop_front();
+            }
+        }
+    });
+}
+
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        $crate::serial_log::log($crate::serial_log::Level::Info, format_args!($($arg)*))
+    };
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)*) => {
+        $crate::serial_log::log($crate::serial_log::Level::Warn, format_args!($($arg)*))
+    };
+}
+
+macro_rules! log_error {
+    ($($arg:tt)*) => {
+        $crate::serial_log::log($crate::serial_log::Level::Error, format_args!($($arg)*))
+    };
+}
+
+pub(crate) use {log_error as error, log_info as info, log_warn as warn};
@@ -0,0 +1,74 @@
+//! 74HC165 parallel-in serial-out shift register, for 8 buttons on 3 pins
+//!
+//! Pulsing SH/LD low latches all eight inputs at once, and each clock then
+//! shifts the next one out on QH, input H first. CLK INH must be tied low.
+//! Buttons connect each input to ground with a pull-up resistor to 3V3, so a
+//! low input reads as pressed.
+
+use embedded_hal::digital::{InputPin, OutputPin};
+
+use crate::bsp::hal::gpio::{DynPinId, FunctionSio, Pin, PullDown, PullUp, SioInput, SioOutput};
+
+/// GPIO pin type for the SH/LD and CLK outputs
+pub type ShiftOutputPin = Pin<DynPinId, FunctionSio<SioOutput>, PullDown>;
+/// GPIO pin type for the QH input, pulled up so a missing chip reads as all
+/// buttons released
+pub type ShiftDataPin = Pin<DynPinId, FunctionSio<SioInput>, PullUp>;
+
+/// CPU cycles each pulse and gap is held for, about 200ns at the default
+/// 125MHz system clock
+///
+/// The 74HC165 needs pulses of 100ns or more at 3.3V; this leaves margin for
+/// the rise time of long wires to the chip.
+const PULSE_CYCLES: u32 = 25;
+
+/// The first `N` inputs of a 74HC165, A to H
+pub struct ShiftRegister<const N: usize> {
+    latch: ShiftOutputPin,
+    clock: ShiftOutputPin,
+    data: ShiftDataPin,
+}
+
+impl<const N: usize> ShiftRegister<N> {
+    const INPUT_MASK: u8 = ((1u16 << N) - 1) as u8;
+
+    pub fn new(mut latch: ShiftOutputPin, mut clock: ShiftOutputPin, data: ShiftDataPin) -> Self {
+        const { assert!(N <= 8) };
+        // SH/LD idles high (shifting), CLK idles low
+        latch.set_high().ok();
+        clock.set_low().ok();
+        Self { latch, clock, data }
+    }
+
+    /// Whether the shift register has no buttons at all
+    pub fn is_empty(&self) -> bool {
+        N == 0
+    }
+
+    /// Latch and shift out the inputs, returning the pressed buttons, bit `n`
+    /// for input `n` (A = bit 0)
+    pub fn read(&mut self) -> u8 {
+        if self.is_empty() {
+            return 0;
+        }
+
+        self.latch.set_low().ok();
+        cortex_m::asm::delay(PULSE_CYCLES);
+        self.latch.set_high().ok();
+        cortex_m::asm::delay(PULSE_CYCLES);
+
+        let mut levels = 0u8;
+        for _ in 0..8 {
+            let high = self.data.is_high().unwrap_or(true);
+            levels = (levels << 1) | high as u8;
+
+            self.clock.set_high().ok();
+            cortex_m::asm::delay(PULSE_CYCLES);
+            self.clock.set_low().ok();
+            cortex_m::asm::delay(PULSE_CYCLES);
+        }
+
+        // H came out first and ended up in bit 7; buttons are active low
+        !levels & Self::INPUT_MASK
+    }
+}
@@ -0,0 +1,433 @@
+//! Layout of the button configuration stored in flash
+//!
+//! A stored configuration starts with a magic number and a layout version,
+//! and ends with a CRC-32 of everything before it; if they don't match, it
+//! doesn't load. A config written in an older layout is migrated on load.
+//! Where it's stored is up to the firmware; this only turns a `Config` into
+//! bytes and back.
+
+use crate::{
+    button_config::{ButtonConfig, ButtonMode, ButtonPull, Polarity, SwitchType},
+    REPORT_BUTTONS,
+};
+
+/// Marks a stored config, "BBCF" little-endian
+const MAGIC: u32 = 0x4643_4242;
+/// Layout version written by `Config::to_bytes()`, bumped whenever the layout
+/// changes
+pub const CONFIG_VERSION: u8 = 4;
+/// Stored in place of `long_press` or `double_tap` when a button has none
+const NO_BUTTON: u8 = 0xFF;
+/// Stored values of each `ButtonMode`
+const MODE_MOMENTARY: u8 = 0;
+const MODE_TOGGLE: u8 = 1;
+const MODE_REPEAT: u8 = 2;
+const MODE_TURBO: u8 = 3;
+const MODE_TAP_HOLD: u8 = 4;
+/// Bits of the stored wiring byte: the polarity, the pull, then the switch type
+const WIRING_ACTIVE_HIGH: u8 = 0x01;
+const WIRING_PULL_SHIFT: u32 = 1;
+const WIRING_PULL_MASK: u8 = 0x03 << WIRING_PULL_SHIFT;
+const WIRING_NORMALLY_CLOSED: u8 = 0x08;
+/// Bits of the stored group byte: the group id, then the solo flag
+const GROUP_ID_MASK: u8 = 0x7F;
+const GROUP_SOLO: u8 = 0x80;
+/// Stored values of each `ButtonPull`
+const PULL_UP: u8 = 0;
+const PULL_DOWN: u8 = 1;
+const PULL_NONE: u8 = 2;
+/// Bits of the stored flags byte, zero for a button with every flag off
+const FLAG_DISABLED: u8 = 0x01;
+
+const MAGIC_LEN: usize = 4;
+const HEADER_LEN: usize = MAGIC_LEN + 1;
+const BUTTON_LEN: usize = 15;
+
+/// Bytes per button of version 0, the layout from before the version byte,
+/// which ends at the tap-hold time and has no header past the magic number
+const V0_BUTTON_LEN: usize = 12;
+/// Bytes per button of version 1, the same records as version 0
+const V1_BUTTON_LEN: usize = 12;
+/// Bytes per button of version 2, which ends at the flags and holds only the
+/// low 16 extra buttons
+const V2_BUTTON_LEN: usize = 13;
+
+/// Runtime configuration of the button box's `N` physical inputs
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Config<const N: usize> {
+    /// How each physical input is reported, in `buttons` pin order
+    pub buttons: [ButtonConfig; N],
+    /// Logical buttons (a mask of report bits) reported on the keyboard
+    /// interface instead of the gamepad; unused with the `keyboard` feature,
+    /// which has no gamepad
+    pub keyboard_buttons: u32,
+}
+
+impl<const N: usize> Config<N> {
+    /// The routing mask follows the buttons, then the CRC
+    const ROUTING_OFFSET: usize = HEADER_LEN + N * BUTTON_LEN;
+    const CRC_OFFSET: usize = Self::ROUTING_OFFSET + 4;
+    /// Bytes of a stored config in the current layout, which every older
+    /// layout fits within
+    pub const LEN: usize = Self::CRC_OFFSET + 4;
+
+    const V0_CRC_OFFSET: usize = MAGIC_LEN + N * V0_BUTTON_LEN;
+    const V1_CRC_OFFSET: usize = HEADER_LEN + N * V1_BUTTON_LEN;
+    const V2_CRC_OFFSET: usize = HEADER_LEN + N * V2_BUTTON_LEN;
+    /// Version 3 has the current button records, but no routing mask
+    const V3_CRC_OFFSET: usize = Self::ROUTING_OFFSET;
+
+    /// Write the config in the current layout to the first `LEN` bytes of
+    /// `bytes`
+    pub fn to_bytes(&self, bytes: &mut [u8]) {
+        let bytes = &mut bytes[..Self::LEN];
+        bytes[..MAGIC_LEN].copy_from_slice(&MAGIC.to_le_bytes());
+        bytes[MAGIC_LEN] = CONFIG_VERSION;
+
+        for (index, button) in self.buttons.iter().enumerate() {
+            let offset = HEADER_LEN + index * BUTTON_LEN;
+            bytes[offset] = button.button as u8;
+            bytes[offset + 1] = button.long_press.map_or(NO_BUTTON, |b| b as u8);
+            bytes[offset + 2] = match button.mode {
+                ButtonMode::Momentary => MODE_MOMENTARY,
+                ButtonMode::Toggle => MODE_TOGGLE,
+                ButtonMode::Repeat => MODE_REPEAT,
+                ButtonMode::Turbo => MODE_TURBO,
+                ButtonMode::TapHold => MODE_TAP_HOLD,
+            };
+            bytes[offset + 3] = button.double_tap.map_or(NO_BUTTON, |b| b as u8);
+            let pull = match button.pull {
+                ButtonPull::Up => PULL_UP,
+                ButtonPull::Down => PULL_DOWN,
+                ButtonPull::None => PULL_NONE,
+            };
+            let polarity = match button.polarity {
+                Polarity::ActiveLow => 0,
+                Polarity::ActiveHigh => WIRING_ACTIVE_HIGH,
+            };
+            let switch = match button.switch {
+                SwitchType::NormallyOpen => 0,
+                SwitchType::NormallyClosed => WIRING_NORMALLY_CLOSED,
+            };
+            bytes[offset + 4] = polarity | (pull << WIRING_PULL_SHIFT) | switch;
+            let solo = if button.solo { GROUP_SOLO } else { 0 };
+            bytes[offset + 5] = (button.group & GROUP_ID_MASK) | solo;
+            bytes[offset + 6..offset + 8]
+                .copy_from_slice(&button.hold_to_activate_ms.to_le_bytes());
+            // The extra buttons are split around the fields added since
+            // they were 16 bits, low half first
+            let extra = button.extra_buttons.to_le_bytes();
+            bytes[offset + 8..offset + 10].copy_from_slice(&extra[..2]);
+            bytes[offset + 10..offset + 12].copy_from_slice(&button.tap_hold_ms.to_le_bytes());
+            bytes[offset + 12] = if button.enabled { 0 } else { FLAG_DISABLED };
+            bytes[offset + 13..offset + 15].copy_from_slice(&extra[2..]);
+        }
+
+        bytes[Self::ROUTING_OFFSET..Self::CRC_OFFSET]
+            .copy_from_slice(&self.keyboard_buttons.to_le_bytes());
+
+        let crc = crc32(&bytes[..Self::CRC_OFFSET]);
+        bytes[Self::CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    /// Read a config stored in any known layout from the first `LEN` bytes
+    /// of `bytes`, and return it with the layout version it was stored in
+    ///
+    /// The settings an older layout predates keep their value in `defaults`.
+    /// Returns `None` for bytes that aren't a stored config, a corrupt one,
+    /// or one of an unknown (newer) layout.
+    pub fn from_bytes(bytes: &[u8], defaults: Self) -> Option<(Self, u8)> {
+        let bytes = bytes.get(..Self::LEN)?;
+        let magic = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        if magic != MAGIC {
+            return None;
+        }
+
+        // The layout is told apart by which CRC matches, so a config cut
+        // short by a power loss mid-write matches none and is rejected
+        let (version, header_len, button_len) = match bytes[MAGIC_LEN] {
+            CONFIG_VERSION if crc_matches(bytes, Self::CRC_OFFSET) => {
+                (CONFIG_VERSION, HEADER_LEN, BUTTON_LEN)
+            }
+            // Version 3 only lacks the routing mask after the records
+            3 if crc_matches(bytes, Self::V3_CRC_OFFSET) => (3, HEADER_LEN, BUTTON_LEN),
+            2 if crc_matches(bytes, Self::V2_CRC_OFFSET) => (2, HEADER_LEN, V2_BUTTON_LEN),
+            1 if crc_matches(bytes, Self::V1_CRC_OFFSET) => (1, HEADER_LEN, V1_BUTTON_LEN),
+            // Version 0 has no version byte, the first record starts there
+            _ if crc_matches(bytes, Self::V0_CRC_OFFSET) => (0, MAGIC_LEN, V0_BUTTON_LEN),
+            _ => return None,
+        };
+
+        let mut config = defaults;
+        for (index, button) in config.buttons.iter_mut().enumerate() {
+            let record = migrate_button(bytes, header_len, button_len, index);
+            button.button = valid_button(record[0])?;
+            button.long_press = match record[1] {
+                NO_BUTTON => None,
+                long_press => Some(valid_button(long_press)?),
+            };
+            button.mode = match record[2] {
+                MODE_MOMENTARY => ButtonMode::Momentary,
+                MODE_TOGGLE => ButtonMode::Toggle,
+                MODE_REPEAT => ButtonMode::Repeat,
+                MODE_TURBO => ButtonMode::Turbo,
+                MODE_TAP_HOLD => ButtonMode::TapHold,
+                _ => return None,
+            };
+            button.double_tap = match record[3] {
+                NO_BUTTON => None,
+                double_tap => Some(valid_button(double_tap)?),
+            };
+            let wiring = record[4];
+            button.polarity = match wiring & WIRING_ACTIVE_HIGH {
+                0 => Polarity::ActiveLow,
+                _ => Polarity::ActiveHigh,
+            };
+            button.pull = match (wiring & WIRING_PULL_MASK) >> WIRING_PULL_SHIFT {
+                PULL_UP => ButtonPull::Up,
+                PULL_DOWN => ButtonPull::Down,
+                PULL_NONE => ButtonPull::None,
+                _ => return None,
+            };
+            button.switch = match wiring & WIRING_NORMALLY_CLOSED {
+                0 => SwitchType::NormallyOpen,
+                _ => SwitchType::NormallyClosed,
+            };
+            button.group = record[5] & GROUP_ID_MASK;
+            button.solo = record[5] & GROUP_SOLO != 0;
+            button.hold_to_activate_ms = u16::from_le_bytes([record[6], record[7]]);
+            button.extra_buttons =
+                u32::from_le_bytes([record[8], record[9], record[13], record[14]]);
+            button.tap_hold_ms = u16::from_le_bytes([record[10], record[11]]);
+            button.enabled = record[12] & FLAG_DISABLED == 0;
+            if !button.is_valid() {
+                return None;
+            }
+        }
+
+        // Older layouts keep the default routing
+        if version == CONFIG_VERSION {
+            let routing = &bytes[Self::ROUTING_OFFSET..Self::CRC_OFFSET];
+            config.keyboard_buttons =
+                u32::from_le_bytes([routing[0], routing[1], routing[2], routing[3]]);
+        }
+        Some((config, version))
+    }
+}
+
+/// Whether the CRC stored at `crc_offset` matches the bytes before it
+fn crc_matches(bytes: &[u8], crc_offset: usize) -> bool {
+    let stored_crc = u32::from_le_bytes([
+        bytes[crc_offset],
+        bytes[crc_offset + 1],
+        bytes[crc_offset + 2],
+        bytes[crc_offset + 3],
+    ]);
+    stored_crc == crc32(&bytes[..crc_offset])
+}
+
+/// Button `index` of a config laid out with `header_len` and `button_len`,
+/// as a record of the current layout
+///
+/// Fields are only ever appended to the record, so an older one is a prefix
+/// of the current one, and the fields it lacks are zero, their default.
+fn migrate_button(
+    bytes: &[u8],
+    header_len: usize,
+    button_len: usize,
+    index: usize,
+) -> [u8; BUTTON_LEN] {
+    let offset = header_len + index * button_len;
+    let mut button = [0u8; BUTTON_LEN];
+    button[..button_len].copy_from_slice(&bytes[offset..offset + button_len]);
+    button
+}
+
+/// Reject logical buttons the report has no bit for
+fn valid_button(button: u8) -> Option<usize> {
+    let button = button as usize;
+    (button < REPORT_BUTTONS).then_some(button)
+}
+
+/// CRC-32 (IEEE 802.3, reflected) of `data`
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sectors as saved by each layout version, for two inputs: a solo,
+    // active-high toggle with no pull and a long-press button, and a
+    // normally-closed tap-hold with a hold and a double-tap button
+
+    /// Saved before the layout had a version byte, 12 bytes a button
+    #[rustfmt::skip]
+    const V0: &[u8] = &[
+        0x42, 0x42, 0x43, 0x46,
+        0x02, 0x04, 0x01, 0xFF, 0x05, 0x83, 0x14, 0x00, 0x00, 0x01, 0x2C, 0x01,
+        0x01, 0x07, 0x04, 0x06, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x5D, 0x3E, 0x30, 0x40,
+    ];
+
+    /// Version 1, the same records after a version byte
+    #[rustfmt::skip]
+    const V1: &[u8] = &[
+        0x42, 0x42, 0x43, 0x46, 0x01,
+        0x02, 0x04, 0x01, 0xFF, 0x05, 0x83, 0x14, 0x00, 0x00, 0x01, 0x2C, 0x01,
+        0x01, 0x07, 0x04, 0x06, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x54, 0x4C, 0xD2, 0xAD,
+    ];
+
+    /// Version 2, with the flags byte
+    #[rustfmt::skip]
+    const V2: &[u8] = &[
+        0x42, 0x42, 0x43, 0x46, 0x02,
+        0x02, 0x04, 0x01, 0xFF, 0x05, 0x83, 0x14, 0x00, 0x00, 0x01, 0x2C, 0x01, 0x01,
+        0x01, 0x07, 0x04, 0x06, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x42, 0x21, 0xF6, 0x79,
+    ];
+
+    /// Version 3, with the high half of the extra buttons
+    #[rustfmt::skip]
+    const V3: &[u8] = &[
+        0x42, 0x42, 0x43, 0x46, 0x03,
+        0x02, 0x04, 0x01, 0xFF, 0x05, 0x83, 0x14, 0x00, 0x00, 0x01, 0x2C, 0x01, 0x01, 0x02, 0x00,
+        0x01, 0x07, 0x04, 0x06, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x5D, 0x05, 0xA1, 0x83,
+    ];
+
+    /// Version 4, the current layout, with the routing mask
+    #[rustfmt::skip]
+    const V4: &[u8] = &[
+        0x42, 0x42, 0x43, 0x46, 0x04,
+        0x02, 0x04, 0x01, 0xFF, 0x05, 0x83, 0x14, 0x00, 0x00, 0x01, 0x2C, 0x01, 0x01, 0x02, 0x00,
+        0x01, 0x07, 0x04, 0x06, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x08, 0x00, 0x00,
+        0xB9, 0xA9, 0x1F, 0xD0,
+    ];
+
+    const BUTTON: ButtonConfig = ButtonConfig {
+        button: 0,
+        long_press: None,
+        double_tap: None,
+        mode: ButtonMode::Momentary,
+        polarity: Polarity::ActiveLow,
+        pull: ButtonPull::Up,
+        switch: SwitchType::NormallyOpen,
+        group: 0,
+        solo: false,
+        hold_to_activate_ms: 0,
+        extra_buttons: 0,
+        tap_hold_ms: 0,
+        enabled: true,
+    };
+
+    const DEFAULTS: Config<2> = Config {
+        buttons: [BUTTON; 2],
+        keyboard_buttons: 0x0000_0030,
+    };
+
+    /// `stored` as read back from flash: the rest of the erased sector after
+    /// it reads 0xFF
+    fn sector(stored: &[u8]) -> [u8; Config::<2>::LEN] {
+        let mut sector = [0xFF; Config::<2>::LEN];
+        sector[..stored.len()].copy_from_slice(stored);
+        sector
+    }
+
+    /// The two inputs as saved in version 0 and 1, which predate the
+    /// disabled flag, the high extra buttons and the routing mask
+    fn version_0_config() -> Config<2> {
+        Config {
+            buttons: [
+                ButtonConfig {
+                    button: 2,
+                    long_press: Some(4),
+                    mode: ButtonMode::Toggle,
+                    polarity: Polarity::ActiveHigh,
+                    pull: ButtonPull::None,
+                    group: 3,
+                    solo: true,
+                    hold_to_activate_ms: 20,
+                    extra_buttons: 0x0100,
+                    tap_hold_ms: 300,
+                    ..BUTTON
+                },
+                ButtonConfig {
+                    button: 1,
+                    long_press: Some(7),
+                    double_tap: Some(6),
+                    mode: ButtonMode::TapHold,
+                    switch: SwitchType::NormallyClosed,
+                    ..BUTTON
+                },
+            ],
+            ..DEFAULTS
+        }
+    }
+
+    #[test]
+    fn loads_a_sector_of_every_version() {
+        let mut expected = version_0_config();
+        assert!(Config::from_bytes(&sector(V0), DEFAULTS) == Some((expected, 0)));
+        assert!(Config::from_bytes(&sector(V1), DEFAULTS) == Some((expected, 1)));
+
+        expected.buttons[0].enabled = false;
+        assert!(Config::from_bytes(&sector(V2), DEFAULTS) == Some((expected, 2)));
+
+        expected.buttons[0].extra_buttons = 0x0002_0100;
+        assert!(Config::from_bytes(&sector(V3), DEFAULTS) == Some((expected, 3)));
+
+        expected.keyboard_buttons = 0x0000_0800;
+        assert!(Config::from_bytes(&sector(V4), DEFAULTS) == Some((expected, CONFIG_VERSION)));
+    }
+
+    #[test]
+    fn saves_the_current_version() {
+        let mut saved = [0xFF; Config::<2>::LEN];
+        let (config, _) = Config::from_bytes(V4, DEFAULTS).unwrap();
+        config.to_bytes(&mut saved);
+        assert_eq!(saved, V4);
+
+        let (config, _) = Config::from_bytes(&sector(V0), DEFAULTS).unwrap();
+        config.to_bytes(&mut saved);
+        assert!(Config::from_bytes(&saved, DEFAULTS) == Some((config, CONFIG_VERSION)));
+    }
+
+    #[test]
+    fn rejects_a_corrupt_or_unknown_sector() {
+        for stored in [V0, V1, V2, V3, V4] {
+            // A flipped bit anywhere before the CRC
+            for index in 0..stored.len() - 4 {
+                let mut corrupt = sector(stored);
+                corrupt[index] ^= 0x10;
+                assert!(
+                    Config::from_bytes(&corrupt, DEFAULTS).is_none(),
+                    "byte {index}"
+                );
+            }
+        }
+
+        // A tap-hold input without a hold button, with a valid CRC
+        let mut stored = V4.to_vec();
+        stored[HEADER_LEN + BUTTON_LEN + 1] = NO_BUTTON;
+        let crc_offset = Config::<2>::CRC_OFFSET;
+        let crc = crc32(&stored[..crc_offset]);
+        stored[crc_offset..].copy_from_slice(&crc.to_le_bytes());
+        assert!(Config::from_bytes(&stored, DEFAULTS).is_none());
+
+        // A blank sector, and a too short one
+        assert!(Config::from_bytes(&[0xFF; Config::<2>::LEN], DEFAULTS).is_none());
+        assert!(Config::from_bytes(&V4[..V4.len() - 1], DEFAULTS).is_none());
+    }
+}
@@ -0,0 +1,34 @@
+//! Timestamped button events on the defmt log
+//!
+//! Each change of a logical button is logged as its own line, with the tick
+//! it was seen at, e.g. `BTN1 pressed @12345ms`. Watching the RTT log then
+//! shows exactly when every press and release reached the report.
+
+/// Logs presses and releases by comparing each report with the previous one
+pub struct Telemetry {
+    last_buttons: u32,
+}
+
+impl Telemetry {
+    pub fn new() -> Self {
+        Self { last_buttons: 0 }
+    }
+
+    /// Log every logical button that changed since the last call, with
+    /// `now_ms` as its timestamp
+    pub fn log_buttons(&mut self, buttons: u32, now_ms: u32) {
+        let changed = buttons ^ self.last_buttons;
+        for index in 0..crate::REPORT_BUTTONS {
+            let bit = 1 << index;
+            if changed & bit == 0 {
+                continue;
+            }
+            if buttons & bit != 0 {
+                crate::serial_log::info!("BTN{} pressed @{}ms", index + 1, now_ms);
+            } else {
+                crate::serial_log::info!("BTN{} released @{}ms", index + 1, now_ms);
+            }
+        }
+        self.last_buttons = buttons;
+    }
+}
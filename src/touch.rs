@@ -0,0 +1,151 @@
+//! Capacitive touch pads, read by how long each takes to discharge
+//!
+//! Each pad is a bare copper electrode on its own GPIO, with a high-value
+//! resistor (about 1MΩ) from the pin to ground. A read drives the pin high to
+//! charge the pad, lets it float, and counts how long the resistor takes to
+//! pull it below the input threshold. A finger adds capacitance, so a touched
+//! pad takes longer to discharge.
+//!
+//! `TouchPads::new()` measures each pad's untouched count as its baseline,
+//! so the pads mustn't be touched while the box starts. A pad then reads as
+//! touched once its count rises `on_percent` above the baseline, and as
+//! released once it falls back under `off_percent`. While a pad is released
+//! its baseline follows the count slowly, one step every `DRIFT_INTERVAL_MS`,
+//! to ride out drift from temperature and humidity without absorbing a touch.
+
+use embedded_hal::digital::{InputPin, OutputPin};
+
+use crate::bsp::hal::gpio::{
+    DynPinId, FunctionSio, OutputEnableOverride, Pin, PullNone, SioOutput,
+};
+
+/// GPIO pin type for a touch pad: an output that charges the pad, with the
+/// output disabled while it discharges through the resistor
+pub type TouchPin = Pin<DynPinId, FunctionSio<SioOutput>, PullNone>;
+
+/// CPU cycles the pad is driven high for before a read, about 1µs at the
+/// default 125MHz system clock
+const CHARGE_CYCLES: u32 = 125;
+/// Longest count a read waits for, after which the pad counts as fully
+/// charged; reached by a pad missing its resistor
+const MAX_COUNT: u32 = 10_000;
+/// Reads averaged into each pad's baseline at startup
+const CALIBRATION_READS: u32 = 16;
+/// Time (ms) between steps of the baseline towards a released pad's count
+const DRIFT_INTERVAL_MS: u32 = 1_000;
+/// Each drift step moves the baseline by 1/2^`DRIFT_SHIFT` of the way to the
+/// count, closing two thirds of a change in about 16s
+const DRIFT_SHIFT: u32 = 4;
+/// Fixed-point scale of the stored baselines, so drift steps smaller than a
+/// count still add up
+const BASELINE_SCALE: u32 = 1 << DRIFT_SHIFT;
+
+/// Touch and release levels of every pad, as a rise of the discharge count
+/// above the pad's baseline
+#[derive(Clone, Copy)]
+pub struct TouchConfig {
+    /// Rise (%) over the baseline at or above which a pad reads as touched
+    pub on_percent: u32,
+    /// Rise (%) over the baseline below which a touched pad reads as
+    /// released again
+    pub off_percent: u32,
+}
+
+impl TouchConfig {
+    /// Whether the release level is below the touch level, leaving a band of
+    /// hysteresis between them
+    pub const fn is_valid(&self) -> bool {
+        self.off_percent < self.on_percent
+    }
+}
+
+/// `N` touch pads, bit `n` of a read for pad `n`
+pub struct TouchPads<const N: usize> {
+    pads: [TouchPin; N],
+    config: TouchConfig,
+    // Untouched count of each pad, times `BASELINE_SCALE`
+    baseline: [u32; N],
+    // Pads currently reading as touched
+    touched: u16,
+    // Tick (ms) of the last drift step
+    drifted_ms: u32,
+}
+
+impl<const N: usize> TouchPads<N> {
+    /// Set up the pads and measure each one's untouched baseline
+    pub fn new(mut pads: [TouchPin; N], config: TouchConfig) -> Self {
+        const { assert!(N <= u16::BITS as usize) };
+        let mut baseline = [0; N];
+        for (pad, baseline) in pads.iter_mut().zip(baseline.iter_mut()) {
+            pad.set_input_enable(true);
+            let total: u32 = (0..CALIBRATION_READS).map(|_| discharge_count(pad)).sum();
+            *baseline = total * BASELINE_SCALE / CALIBRATION_READS;
+        }
+        Self {
+            pads,
+            config,
+            baseline,
+            touched: 0,
+            drifted_ms: 0,
+        }
+    }
+
+    /// Whether there are no pads at all
+    pub fn is_empty(&self) -> bool {
+        N == 0
+    }
+
+    /// Read every pad at `now_ms`, returning the touched ones
+    pub fn read(&mut self, now_ms: u32) -> u16 {
+        let drift = now_ms.wrapping_sub(self.drifted_ms) >= DRIFT_INTERVAL_MS;
+        if drift {
+            self.drifted_ms = now_ms;
+        }
+
+        for (index, (pad, baseline)) in self
+            .pads
+            .iter_mut()
+            .zip(self.baseline.iter_mut())
+            .enumerate()
+        {
+            let bit = 1 << index;
+            let count = discharge_count(pad) * BASELINE_SCALE;
+            let rise = count.saturating_sub(*baseline) * 100 / (*baseline).max(1);
+
+            if rise >= self.config.on_percent {
+                self.touched |= bit;
+            } else if rise < self.config.off_percent {
+                self.touched &= !bit;
+            }
+
+            // Only a released pad drifts, so a long touch isn't learned as
+            // the new baseline
+            if drift && self.touched & bit == 0 {
+                *baseline = *baseline - (*baseline >> DRIFT_SHIFT) + (count >> DRIFT_SHIFT);
+            }
+        }
+        self.touched
+    }
+}
+
+/// Charge `pad`, let it float, and count the loops until it reads low
+///
+/// Interrupts are held off for the read, which takes a few microseconds, so
+/// a handler running in the middle can't stretch the count.
+fn discharge_count(pad: &mut TouchPin) -> u32 {
+    cortex_m::interrupt::free(|_| {
+        pad.set_high().ok();
+        cortex_m::asm::delay(CHARGE_CYCLES);
+        pad.set_output_enable_override(OutputEnableOverride::Disable);
+
+        let mut count = 0;
+        while count < MAX_COUNT && pad.as_input().is_high().unwrap_or(false) {
+            count += 1;
+        }
+
+        // Drive it low until the next read, so it sits discharged
+        pad.set_low().ok();
+        pad.set_output_enable_override(OutputEnableOverride::Normal);
+        count
+    })
+}